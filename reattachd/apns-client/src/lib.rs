@@ -0,0 +1,174 @@
+//! APNs provider client, and the protocol between reattachd and a relay
+//! server that sends pushes on its behalf.
+
+pub mod relay;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use openssl::ec::EcKey;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::Private;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const PRODUCTION_URL: &str = "https://api.push.apple.com";
+pub const SANDBOX_URL: &str = "https://api.sandbox.push.apple.com";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+/// Apple rejects provider tokens older than an hour and throttles ones that are
+/// refreshed more often than every 20 minutes.
+const TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(50 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    #[error("APNs rejected the notification (HTTP {status}): {}", reason.as_deref().unwrap_or("no reason"))]
+    Rejected { status: u16, reason: Option<String> },
+    #[error("APNs request failed: {0}")]
+    Transport(String),
+    #[error("Failed to sign APNs provider token: {0}")]
+    Signing(String),
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    reason: String,
+}
+
+/// Signs the ES256 provider tokens APNs expects in the `authorization` header
+/// and caches them for as long as Apple allows.
+pub struct TokenSigner {
+    key: EcKey<Private>,
+    key_id: String,
+    team_id: String,
+    cached: Mutex<Option<(String, Instant)>>,
+}
+
+impl TokenSigner {
+    pub fn new(key_pem: &str, key_id: &str, team_id: &str) -> Result<Self, SendError> {
+        let key = EcKey::private_key_from_pem(key_pem.as_bytes())
+            .map_err(|e| SendError::Signing(e.to_string()))?;
+        Ok(Self {
+            key,
+            key_id: key_id.to_string(),
+            team_id: team_id.to_string(),
+            cached: Mutex::new(None),
+        })
+    }
+
+    fn token(&self) -> Result<String, SendError> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((ref token, issued_at)) = *cached {
+            if issued_at.elapsed() < TOKEN_REFRESH_INTERVAL {
+                return Ok(token.clone());
+            }
+        }
+        let token = self.sign()?;
+        *cached = Some((token.clone(), Instant::now()));
+        Ok(token)
+    }
+
+    fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    fn sign(&self) -> Result<String, SendError> {
+        let issued_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let header = serde_json::json!({ "alg": "ES256", "kid": self.key_id });
+        let claims = serde_json::json!({ "iss": self.team_id, "iat": issued_at });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+
+        let digest = hash(MessageDigest::sha256(), signing_input.as_bytes())
+            .map_err(|e| SendError::Signing(e.to_string()))?;
+        let signature =
+            EcdsaSig::sign(&digest, &self.key).map_err(|e| SendError::Signing(e.to_string()))?;
+        // JWS wants the raw fixed-width r || s, not the DER encoding.
+        let mut raw = signature
+            .r()
+            .to_vec_padded(32)
+            .map_err(|e| SendError::Signing(e.to_string()))?;
+        raw.extend(
+            signature
+                .s()
+                .to_vec_padded(32)
+                .map_err(|e| SendError::Signing(e.to_string()))?,
+        );
+
+        Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(raw)))
+    }
+}
+
+/// Minimal HTTP/2 APNs provider client that can be pointed at any base URL,
+/// e.g. the mock server used in tests.
+#[derive(Clone)]
+pub struct ApnsClient {
+    http: reqwest::Client,
+    base_url: String,
+    signer: Arc<TokenSigner>,
+}
+
+impl ApnsClient {
+    pub fn new(base_url: &str, signer: Arc<TokenSigner>) -> Result<Self, SendError> {
+        let http = reqwest::Client::builder()
+            .use_rustls_tls()
+            .http2_prior_knowledge()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| SendError::Transport(e.to_string()))?;
+        Ok(Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            signer,
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Posts a JSON payload to a device and returns the `apns-id` on success.
+    pub async fn send(&self, device_token: &str, topic: &str, payload: String) -> Result<Option<String>, SendError> {
+        let url = format!("{}/3/device/{}", self.base_url, device_token);
+        let response = self
+            .http
+            .post(&url)
+            .bearer_auth(self.signer.token()?)
+            .header("apns-topic", topic)
+            .header("apns-push-type", "alert")
+            .header("content-type", "application/json")
+            .body(payload)
+            .send()
+            .await
+            .map_err(|e| SendError::Transport(e.to_string()))?;
+
+        let status = response.status();
+        let apns_id = response
+            .headers()
+            .get("apns-id")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        if status.is_success() {
+            return Ok(apns_id);
+        }
+
+        let reason = response
+            .json::<ErrorBody>()
+            .await
+            .ok()
+            .map(|body| body.reason);
+        if reason.as_deref() == Some("ExpiredProviderToken") {
+            self.signer.invalidate();
+        }
+        Err(SendError::Rejected {
+            status: status.as_u16(),
+            reason,
+        })
+    }
+}
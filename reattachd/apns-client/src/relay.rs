@@ -0,0 +1,118 @@
+//! The relay protocol. A daemon without an APNs key pairs with a relay once,
+//! using a one-time code from the relay's operator, and gets an ID and a
+//! secret. It then posts each push, payload already built, to the relay,
+//! which signs it with its own key and forwards it to APNs.
+//!
+//! - `POST /v1/pair` with [`PairRequest`] returns [`PairResponse`].
+//! - `POST /v1/push` with [`PushRequest`] returns [`PushResponse`], or the
+//!   APNs status and `{"reason": ...}` body when APNs rejects the push.
+//!   Requests carry [`DAEMON_HEADER`], [`TIMESTAMP_HEADER`], [`NONCE_HEADER`]
+//!   and [`SIGNATURE_HEADER`], an HMAC-SHA256 of the timestamp, nonce and
+//!   body. The relay refuses a nonce it has seen within the clock skew, so a
+//!   captured request can't be sent again.
+//!
+//! The relay answers 401 with one of the reasons below when it refuses a
+//! request, and 502 with [`REASON_UPSTREAM`] when it can't reach APNs.
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+
+pub const DAEMON_HEADER: &str = "x-relay-daemon";
+pub const TIMESTAMP_HEADER: &str = "x-relay-timestamp";
+pub const NONCE_HEADER: &str = "x-relay-nonce";
+pub const SIGNATURE_HEADER: &str = "x-relay-signature";
+
+/// How far a request's timestamp may be from the relay's clock, in seconds.
+pub const MAX_CLOCK_SKEW_SECS: u64 = 300;
+
+pub const REASON_INVALID_PAIRING_CODE: &str = "InvalidPairingCode";
+pub const REASON_UNKNOWN_DAEMON: &str = "UnknownDaemon";
+pub const REASON_BAD_SIGNATURE: &str = "BadSignature";
+pub const REASON_STALE_REQUEST: &str = "StaleRequest";
+pub const REASON_REPLAYED_REQUEST: &str = "ReplayedRequest";
+pub const REASON_UPSTREAM: &str = "RelayUpstreamError";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairRequest {
+    pub code: String,
+    /// Shown to the relay's operator, e.g. the daemon's hostname.
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairResponse {
+    pub daemon_id: String,
+    /// Base64-encoded HMAC key for signing pushes.
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushRequest {
+    pub token: String,
+    pub sandbox: bool,
+    /// The APNs JSON payload.
+    pub payload: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushResponse {
+    pub apns_id: Option<String>,
+}
+
+/// A new random secret, base64-encoded.
+pub fn generate_secret() -> String {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    let mut bytes = [0u8; 32];
+    openssl::rand::rand_bytes(&mut bytes).expect("OpenSSL RNG failed");
+    STANDARD.encode(bytes)
+}
+
+/// A new random request nonce, hex-encoded.
+pub fn generate_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    openssl::rand::rand_bytes(&mut bytes).expect("OpenSSL RNG failed");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The hex HMAC-SHA256 of `"{timestamp}\n{nonce}\n{body}"` under `secret`.
+pub fn sign(secret: &str, timestamp: u64, nonce: &str, body: &[u8]) -> Result<String, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    let key = STANDARD.decode(secret).map_err(|e| format!("Invalid relay secret: {}", e))?;
+    let key = PKey::hmac(&key).map_err(|e| e.to_string())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|e| e.to_string())?;
+    let mut message = format!("{}\n{}\n", timestamp, nonce).into_bytes();
+    message.extend_from_slice(body);
+    let mac = signer.sign_oneshot_to_vec(&message).map_err(|e| e.to_string())?;
+    Ok(mac.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Checks `signature` in constant time.
+pub fn verify(secret: &str, timestamp: u64, nonce: &str, body: &[u8], signature: &str) -> bool {
+    match sign(secret, timestamp, nonce, body) {
+        Ok(expected) => expected.len() == signature.len() && openssl::memcmp::eq(expected.as_bytes(), signature.as_bytes()),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_only_matching_signatures() {
+        let secret = generate_secret();
+        let nonce = generate_nonce();
+        assert_eq!(nonce.len(), 32);
+        assert_ne!(nonce, generate_nonce());
+        let signature = sign(&secret, 1_700_000_000, &nonce, b"{}").unwrap();
+        assert_eq!(signature.len(), 64);
+        assert!(verify(&secret, 1_700_000_000, &nonce, b"{}", &signature));
+        assert!(!verify(&secret, 1_700_000_001, &nonce, b"{}", &signature));
+        assert!(!verify(&secret, 1_700_000_000, &generate_nonce(), b"{}", &signature));
+        assert!(!verify(&secret, 1_700_000_000, &nonce, b"{ }", &signature));
+        assert!(!verify(&generate_secret(), 1_700_000_000, &nonce, b"{}", &signature));
+        assert!(!verify(&secret, 1_700_000_000, &nonce, b"{}", "00"));
+    }
+}
@@ -0,0 +1,218 @@
+//! A local stand-in for the APNs provider API.
+//!
+//! Speaks cleartext HTTP/2 (prior knowledge) on `POST /3/device/{token}`,
+//! records every request it receives and can be told to reject specific
+//! tokens with an APNs error reason. The same state is exposed over
+//! `/mock/*` control endpoints so the standalone binary can be driven from
+//! the command line.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub device_token: String,
+    pub topic: Option<String>,
+    pub push_type: Option<String>,
+    pub authorization: Option<String>,
+    pub payload: serde_json::Value,
+    /// HTTP status the mock answered with.
+    pub status: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub status: u16,
+    pub reason: String,
+    /// How many requests to reject before succeeding again; `None` rejects
+    /// forever.
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FailureRequest {
+    token: String,
+    #[serde(flatten)]
+    failure: Failure,
+}
+
+#[derive(Default)]
+struct MockState {
+    requests: Vec<RecordedRequest>,
+    failures: HashMap<String, Failure>,
+    next_id: u64,
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+pub struct MockApns {
+    addr: SocketAddr,
+    state: SharedState,
+    server: JoinHandle<()>,
+}
+
+impl MockApns {
+    /// Starts the mock on an ephemeral loopback port.
+    pub async fn start() -> std::io::Result<Self> {
+        Self::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    pub async fn bind(addr: SocketAddr) -> std::io::Result<Self> {
+        let state = SharedState::default();
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let app = router(state.clone());
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(Self {
+            addr,
+            state,
+            server,
+        })
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        lock(&self.state).requests.clone()
+    }
+
+    pub fn clear_requests(&self) {
+        lock(&self.state).requests.clear();
+    }
+
+    /// Rejects every request for `token` with the given status and reason.
+    pub fn fail_token(&self, token: &str, status: u16, reason: &str) {
+        self.set_failure(token, status, reason, None);
+    }
+
+    /// Rejects the next `times` requests for `token`, then accepts again.
+    pub fn fail_next(&self, token: &str, times: u32, status: u16, reason: &str) {
+        self.set_failure(token, status, reason, Some(times));
+    }
+
+    fn set_failure(&self, token: &str, status: u16, reason: &str, times: Option<u32>) {
+        lock(&self.state).failures.insert(
+            token.to_string(),
+            Failure {
+                status,
+                reason: reason.to_string(),
+                times,
+            },
+        );
+    }
+}
+
+impl Drop for MockApns {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn lock(state: &SharedState) -> std::sync::MutexGuard<'_, MockState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn router(state: SharedState) -> Router {
+    Router::new()
+        .route("/3/device/{token}", post(push))
+        .route("/mock/requests", get(list_requests).delete(clear_requests))
+        .route("/mock/failures", post(add_failure))
+        .with_state(state)
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn error(status: u16, reason: &str) -> Response {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST);
+    (status, Json(serde_json::json!({ "reason": reason }))).into_response()
+}
+
+async fn push(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let mut state = lock(&state);
+    let payload = serde_json::from_str(&body).unwrap_or(serde_json::Value::Null);
+    let mut record = RecordedRequest {
+        device_token: token.clone(),
+        topic: header(&headers, "apns-topic"),
+        push_type: header(&headers, "apns-push-type"),
+        authorization: header(&headers, "authorization"),
+        payload,
+        status: 200,
+    };
+
+    let rejection = if record.authorization.is_none() {
+        Some((403, "MissingProviderToken".to_string()))
+    } else if record.topic.is_none() {
+        Some((400, "MissingTopic".to_string()))
+    } else if record.payload.is_null() {
+        Some((400, "PayloadEmpty".to_string()))
+    } else if let Some(failure) = state.failures.get_mut(&token) {
+        let rejection = (failure.status, failure.reason.clone());
+        match failure.times {
+            Some(1) => {
+                state.failures.remove(&token);
+            }
+            Some(ref mut n) => *n -= 1,
+            None => {}
+        }
+        Some(rejection)
+    } else {
+        None
+    };
+
+    if let Some((status, reason)) = rejection {
+        record.status = status;
+        state.requests.push(record);
+        return error(status, &reason);
+    }
+
+    state.next_id += 1;
+    let apns_id = format!("00000000-0000-0000-0000-{:012}", state.next_id);
+    state.requests.push(record);
+    let mut response = StatusCode::OK.into_response();
+    if let Ok(value) = HeaderValue::from_str(&apns_id) {
+        response.headers_mut().insert("apns-id", value);
+    }
+    response
+}
+
+async fn list_requests(State(state): State<SharedState>) -> Json<Vec<RecordedRequest>> {
+    Json(lock(&state).requests.clone())
+}
+
+async fn clear_requests(State(state): State<SharedState>) -> StatusCode {
+    lock(&state).requests.clear();
+    StatusCode::NO_CONTENT
+}
+
+async fn add_failure(
+    State(state): State<SharedState>,
+    Json(request): Json<FailureRequest>,
+) -> StatusCode {
+    lock(&state).failures.insert(request.token, request.failure);
+    StatusCode::CREATED
+}
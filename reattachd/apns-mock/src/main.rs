@@ -0,0 +1,34 @@
+use clap::Parser;
+use std::net::SocketAddr;
+
+/// Run a local APNs stand-in that records pushes instead of delivering them.
+///
+/// Point reattachd at it with `APNS_BASE_URL=http://<bind>`.
+#[derive(Parser)]
+#[command(name = "apns-mock")]
+struct Cli {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:2197")]
+    bind: SocketAddr,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let mock = match apns_mock::MockApns::bind(cli.bind).await {
+        Ok(mock) => mock,
+        Err(e) => {
+            eprintln!("Failed to bind {}: {}", cli.bind, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Mock APNs listening on {}", mock.base_url());
+    println!("  Recorded pushes:  GET {}/mock/requests", mock.base_url());
+    println!(
+        "  Inject failures:  POST {}/mock/failures {{\"token\": ..., \"status\": 410, \"reason\": \"Unregistered\"}}",
+        mock.base_url()
+    );
+
+    let _ = tokio::signal::ctrl_c().await;
+}
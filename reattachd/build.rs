@@ -13,6 +13,21 @@ fn xor_encode(input: &str) -> String {
 }
 
 fn main() {
+    println!("cargo:rerun-if-env-changed=APNS_KEY_BASE64");
+    println!("cargo:rerun-if-env-changed=APNS_KEY_ID");
+    println!("cargo:rerun-if-env-changed=APNS_TEAM_ID");
+    println!("cargo:rerun-if-env-changed=APNS_BUNDLE_ID");
+
+    // Without the feature the credentials are read at run time instead.
+    if env::var_os("CARGO_FEATURE_EMBEDDED_APNS_CREDENTIALS").is_none() {
+        if env::var_os("APNS_KEY_BASE64").is_some() {
+            println!(
+                "cargo:warning=APNS_KEY_BASE64 is only embedded with --features embedded-apns-credentials; ignoring it"
+            );
+        }
+        return;
+    }
+
     let out_dir = env::var("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("apns_config.rs");
 
@@ -35,9 +50,4 @@ const APNS_BUNDLE_ID_OBFUSCATED: Option<&str> = {};
     );
 
     fs::write(&dest_path, code).unwrap();
-
-    println!("cargo:rerun-if-env-changed=APNS_KEY_BASE64");
-    println!("cargo:rerun-if-env-changed=APNS_KEY_ID");
-    println!("cargo:rerun-if-env-changed=APNS_TEAM_ID");
-    println!("cargo:rerun-if-env-changed=APNS_BUNDLE_ID");
 }
@@ -0,0 +1,365 @@
+//! A reference relay for reattachd daemons that don't hold an APNs key.
+//!
+//! The relay keeps the key for one app. Its operator hands out one-time
+//! pairing codes (`reattach-relay pair`); a daemon exchanges a code for an ID
+//! and secret, then signs each push it posts. See [`apns_client::relay`] for
+//! the protocol.
+//!
+//! Daemons and unused codes live in `daemons.json` in the data directory,
+//! which is re-read on every request so that the CLI can add codes and
+//! revoke daemons while the server runs. Nonces of pushes within the clock
+//! skew are kept in `nonces.json`, so that a push captured shortly before a
+//! restart can't be replayed after it.
+
+use apns_client::relay::{
+    self, PairRequest, PairResponse, PushRequest, PushResponse, DAEMON_HEADER, MAX_CLOCK_SKEW_SECS, NONCE_HEADER,
+    REASON_BAD_SIGNATURE, REASON_INVALID_PAIRING_CODE, REASON_REPLAYED_REQUEST, REASON_STALE_REQUEST,
+    REASON_UNKNOWN_DAEMON, REASON_UPSTREAM, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use apns_client::{ApnsClient, SendError, TokenSigner};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub const STORE_FILE: &str = "daemons.json";
+pub const NONCES_FILE: &str = "nonces.json";
+/// Pairing codes avoid characters that are easy to misread.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+/// Longest nonce accepted, to bound what a paired daemon can make us keep.
+const MAX_NONCE_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Daemon {
+    pub id: String,
+    pub name: String,
+    pub secret: String,
+    pub paired_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PairingCode {
+    code: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Store {
+    #[serde(default)]
+    daemons: Vec<Daemon>,
+    #[serde(default)]
+    codes: Vec<PairingCode>,
+}
+
+impl Store {
+    fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(STORE_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn save(&self, data_dir: &Path) -> Result<(), String> {
+        let content = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_private(data_dir, STORE_FILE, &content)
+    }
+}
+
+/// A push the relay accepted, remembered until its timestamp is stale.
+#[derive(Debug, Serialize, Deserialize)]
+struct SeenNonce {
+    daemon_id: String,
+    nonce: String,
+    timestamp: u64,
+}
+
+/// `(daemon ID, nonce)` of accepted pushes, with their timestamps.
+type SeenNonces = HashMap<(String, String), u64>;
+
+fn load_nonces(data_dir: &Path) -> Result<SeenNonces, String> {
+    let path = data_dir.join(NONCES_FILE);
+    let seen: Vec<SeenNonce> = match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    Ok(seen
+        .into_iter()
+        .map(|seen| ((seen.daemon_id, seen.nonce), seen.timestamp))
+        .collect())
+}
+
+fn save_nonces(data_dir: &Path, nonces: &SeenNonces) -> Result<(), String> {
+    let seen: Vec<SeenNonce> = nonces
+        .iter()
+        .map(|((daemon_id, nonce), timestamp)| SeenNonce {
+            daemon_id: daemon_id.clone(),
+            nonce: nonce.clone(),
+            timestamp: *timestamp,
+        })
+        .collect();
+    let content = serde_json::to_vec(&seen).map_err(|e| e.to_string())?;
+    write_private(data_dir, NONCES_FILE, &content)
+}
+
+/// Writes `name` in `data_dir` through a temporary file, so a concurrent
+/// reader never sees a partial file. The files hold daemon secrets, so they
+/// are created readable by the owner only.
+fn write_private(data_dir: &Path, name: &str, content: &[u8]) -> Result<(), String> {
+    std::fs::create_dir_all(data_dir).map_err(|e| format!("Failed to create {}: {}", data_dir.display(), e))?;
+    let path = data_dir.join(name);
+    let temp = data_dir.join(format!("{}.{}.tmp", name, uuid::Uuid::new_v4().simple()));
+    let write = || -> std::io::Result<()> {
+        use std::io::Write;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temp)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, &path)
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&temp);
+        format!("Failed to write {}: {}", path.display(), e)
+    })
+}
+
+/// Adds a pairing code that expires after `ttl` and returns it.
+pub fn create_pairing_code(data_dir: &Path, ttl: chrono::Duration) -> Result<String, String> {
+    let mut bytes = [0u8; 8];
+    openssl::rand::rand_bytes(&mut bytes).map_err(|e| e.to_string())?;
+    let chars: String = bytes
+        .iter()
+        .map(|b| CODE_ALPHABET[*b as usize % CODE_ALPHABET.len()] as char)
+        .collect();
+    let code = format!("{}-{}", &chars[..4], &chars[4..]);
+
+    let mut store = Store::load(data_dir)?;
+    let now = Utc::now();
+    store.codes.retain(|c| c.expires_at > now);
+    store.codes.push(PairingCode {
+        code: code.clone(),
+        expires_at: now + ttl,
+    });
+    store.save(data_dir)?;
+    Ok(code)
+}
+
+pub fn list_daemons(data_dir: &Path) -> Result<Vec<Daemon>, String> {
+    Ok(Store::load(data_dir)?.daemons)
+}
+
+/// Forgets a daemon by ID or name. Returns false if there was none.
+pub fn revoke(data_dir: &Path, id_or_name: &str) -> Result<bool, String> {
+    let mut store = Store::load(data_dir)?;
+    let before = store.daemons.len();
+    store.daemons.retain(|d| d.id != id_or_name && d.name != id_or_name);
+    if store.daemons.len() == before {
+        return Ok(false);
+    }
+    store.save(data_dir)?;
+    Ok(true)
+}
+
+pub struct RelayConfig {
+    pub data_dir: PathBuf,
+    /// PEM-encoded APNs signing key.
+    pub key: String,
+    pub key_id: String,
+    pub team_id: String,
+    /// Bundle ID of the app the key belongs to; every push goes to it.
+    pub bundle_id: String,
+    pub sandbox_url: String,
+    pub production_url: String,
+}
+
+pub struct Relay {
+    data_dir: PathBuf,
+    /// Serializes store updates within this process.
+    store_lock: Mutex<()>,
+    /// Mirrors `nonces.json`.
+    seen_nonces: Mutex<SeenNonces>,
+    sandbox: ApnsClient,
+    production: ApnsClient,
+    bundle_id: String,
+}
+
+impl Relay {
+    pub fn new(config: RelayConfig) -> Result<Self, String> {
+        let signer = TokenSigner::new(&config.key, &config.key_id, &config.team_id)
+            .map(Arc::new)
+            .map_err(|e| e.to_string())?;
+        let seen_nonces = load_nonces(&config.data_dir)?;
+        Ok(Self {
+            sandbox: ApnsClient::new(&config.sandbox_url, signer.clone()).map_err(|e| e.to_string())?,
+            production: ApnsClient::new(&config.production_url, signer).map_err(|e| e.to_string())?,
+            bundle_id: config.bundle_id,
+            data_dir: config.data_dir,
+            store_lock: Mutex::new(()),
+            seen_nonces: Mutex::new(seen_nonces),
+        })
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/v1/pair", post(pair))
+            .route("/v1/push", post(push))
+            .with_state(self)
+    }
+}
+
+fn reject(status: StatusCode, reason: &str) -> Response {
+    (status, Json(serde_json::json!({ "reason": reason }))).into_response()
+}
+
+fn internal_error(error: String) -> Response {
+    tracing::error!("{}", error);
+    reject(StatusCode::INTERNAL_SERVER_ERROR, "InternalServerError")
+}
+
+async fn pair(State(relay): State<Arc<Relay>>, Json(request): Json<PairRequest>) -> Response {
+    let _guard = relay.store_lock.lock().await;
+    let mut store = match Store::load(&relay.data_dir) {
+        Ok(store) => store,
+        Err(e) => return internal_error(e),
+    };
+    let now = Utc::now();
+    store.codes.retain(|c| c.expires_at > now);
+    let code = request.code.trim().to_ascii_uppercase();
+    let Some(index) = store.codes.iter().position(|c| c.code == code) else {
+        tracing::warn!("Rejected pairing from {:?}: unknown or expired code", request.name);
+        return reject(StatusCode::UNAUTHORIZED, REASON_INVALID_PAIRING_CODE);
+    };
+    store.codes.remove(index);
+
+    let daemon = Daemon {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: request.name,
+        secret: relay::generate_secret(),
+        paired_at: now,
+    };
+    store.daemons.push(daemon.clone());
+    if let Err(e) = store.save(&relay.data_dir) {
+        return internal_error(e);
+    }
+    tracing::info!("Paired daemon {} ({})", daemon.name, daemon.id);
+    Json(PairResponse {
+        daemon_id: daemon.id,
+        secret: daemon.secret,
+    })
+    .into_response()
+}
+
+async fn push(State(relay): State<Arc<Relay>>, headers: HeaderMap, body: Bytes) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let Ok(timestamp) = header(TIMESTAMP_HEADER).parse::<u64>() else {
+        return reject(StatusCode::UNAUTHORIZED, REASON_STALE_REQUEST);
+    };
+    let now = Utc::now().timestamp().max(0) as u64;
+    if now.abs_diff(timestamp) > MAX_CLOCK_SKEW_SECS {
+        return reject(StatusCode::UNAUTHORIZED, REASON_STALE_REQUEST);
+    }
+
+    let store = match Store::load(&relay.data_dir) {
+        Ok(store) => store,
+        Err(e) => return internal_error(e),
+    };
+    let Some(daemon) = store.daemons.into_iter().find(|d| d.id == header(DAEMON_HEADER)) else {
+        return reject(StatusCode::UNAUTHORIZED, REASON_UNKNOWN_DAEMON);
+    };
+    let nonce = header(NONCE_HEADER);
+    let valid_nonce =
+        !nonce.is_empty() && nonce.len() <= MAX_NONCE_LEN && nonce.bytes().all(|b| b.is_ascii_alphanumeric());
+    if !valid_nonce || !relay::verify(&daemon.secret, timestamp, nonce, &body, header(SIGNATURE_HEADER)) {
+        tracing::warn!("Bad signature from daemon {} ({})", daemon.name, daemon.id);
+        return reject(StatusCode::UNAUTHORIZED, REASON_BAD_SIGNATURE);
+    }
+    {
+        let mut seen = relay.seen_nonces.lock().await;
+        // Past the skew a request is stale, so its nonce can be forgotten.
+        seen.retain(|_, seen_at| *seen_at + MAX_CLOCK_SKEW_SECS >= now);
+        if seen.insert((daemon.id.clone(), nonce.to_string()), timestamp).is_some() {
+            tracing::warn!("Replayed request from daemon {} ({})", daemon.name, daemon.id);
+            return reject(StatusCode::UNAUTHORIZED, REASON_REPLAYED_REQUEST);
+        }
+        if let Err(e) = save_nonces(&relay.data_dir, &seen) {
+            return internal_error(e);
+        }
+    }
+    let request: PushRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return reject(StatusCode::BAD_REQUEST, &format!("Invalid push request: {}", e)),
+    };
+
+    let client = if request.sandbox { &relay.sandbox } else { &relay.production };
+    match client.send(&request.token, &relay.bundle_id, request.payload).await {
+        Ok(apns_id) => {
+            tracing::info!("Relayed push for {} (sandbox: {})", daemon.name, request.sandbox);
+            Json(PushResponse { apns_id }).into_response()
+        }
+        // APNs' verdict goes back to the daemon as is, so that it retries and
+        // prunes tokens just as it would talking to APNs itself.
+        Err(SendError::Rejected { status, reason }) => {
+            tracing::info!("APNs rejected push for {}: HTTP {} {:?}", daemon.name, status, reason);
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+            match reason {
+                Some(reason) => reject(status, &reason),
+                None => status.into_response(),
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to relay push for {}: {}", daemon.name, e);
+            reject(StatusCode::BAD_GATEWAY, REASON_UPSTREAM)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairing_codes_expire_and_daemons_can_be_revoked() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let code = create_pairing_code(data_dir, chrono::Duration::minutes(10)).unwrap();
+        assert_eq!(code.len(), 9);
+        assert!(code.chars().all(|c| c == '-' || CODE_ALPHABET.contains(&(c as u8))));
+        create_pairing_code(data_dir, chrono::Duration::seconds(-1)).unwrap();
+        // Creating a code drops the expired ones.
+        create_pairing_code(data_dir, chrono::Duration::minutes(10)).unwrap();
+        assert_eq!(Store::load(data_dir).unwrap().codes.len(), 2);
+
+        let mut store = Store::load(data_dir).unwrap();
+        store.daemons.push(Daemon {
+            id: "d-1".to_string(),
+            name: "build-host".to_string(),
+            secret: relay::generate_secret(),
+            paired_at: Utc::now(),
+        });
+        store.save(data_dir).unwrap();
+        assert_eq!(list_daemons(data_dir).unwrap().len(), 1);
+        assert!(!revoke(data_dir, "other-host").unwrap());
+        assert!(revoke(data_dir, "build-host").unwrap());
+        assert!(list_daemons(data_dir).unwrap().is_empty());
+    }
+}
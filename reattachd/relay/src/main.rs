@@ -0,0 +1,132 @@
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Send pushes for reattachd daemons that don't have an APNs key.
+///
+/// Serves plain HTTP; put it behind a reverse proxy that terminates TLS.
+#[derive(Parser)]
+#[command(name = "reattach-relay")]
+#[command(version)]
+struct Cli {
+    /// Where paired daemons and pairing codes are kept
+    #[arg(long, env = "RELAY_DATA_DIR", default_value = ".")]
+    data_dir: PathBuf,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Run the relay
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8790")]
+        bind: SocketAddr,
+        /// The .p8 signing key from the Apple developer portal
+        #[arg(long, env = "APNS_KEY_FILE", value_name = "FILE")]
+        key: PathBuf,
+        #[arg(long, env = "APNS_KEY_ID")]
+        key_id: String,
+        #[arg(long, env = "APNS_TEAM_ID")]
+        team_id: String,
+        /// Bundle ID of the iOS app
+        #[arg(long, env = "APNS_BUNDLE_ID")]
+        bundle_id: String,
+        /// Send both sandbox and production pushes here, e.g. to apns-mock
+        #[arg(long, env = "APNS_BASE_URL")]
+        apns_base_url: Option<String>,
+    },
+    /// Create a one-time code for pairing a daemon
+    Pair {
+        /// How long the code stays valid, in minutes
+        #[arg(long, default_value_t = 10)]
+        expires_minutes: i64,
+    },
+    /// List paired daemons
+    List,
+    /// Unpair a daemon by ID or name
+    Revoke { daemon: String },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "reattach_relay=info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+    match cli.command {
+        Commands::Serve {
+            bind,
+            key,
+            key_id,
+            team_id,
+            bundle_id,
+            apns_base_url,
+        } => {
+            let pem = std::fs::read_to_string(&key)
+                .unwrap_or_else(|e| fail(format!("Failed to read {}: {}", key.display(), e)));
+            let config = reattach_relay::RelayConfig {
+                data_dir: cli.data_dir,
+                key: pem,
+                key_id,
+                team_id,
+                bundle_id,
+                sandbox_url: apns_base_url.clone().unwrap_or_else(|| apns_client::SANDBOX_URL.to_string()),
+                production_url: apns_base_url.unwrap_or_else(|| apns_client::PRODUCTION_URL.to_string()),
+            };
+            let relay = reattach_relay::Relay::new(config).unwrap_or_else(|e| fail(format!("{}: {}", key.display(), e)));
+            let listener = tokio::net::TcpListener::bind(bind)
+                .await
+                .unwrap_or_else(|e| fail(format!("Failed to bind {}: {}", bind, e)));
+            tracing::info!("Relay listening on http://{}", bind);
+            let shutdown = async {
+                let _ = tokio::signal::ctrl_c().await;
+            };
+            if let Err(e) = axum::serve(listener, Arc::new(relay).router())
+                .with_graceful_shutdown(shutdown)
+                .await
+            {
+                fail(e.to_string());
+            }
+        }
+        Commands::Pair { expires_minutes } => {
+            let code = reattach_relay::create_pairing_code(&cli.data_dir, chrono::Duration::minutes(expires_minutes))
+                .unwrap_or_else(|e| fail(e));
+            println!("Pairing code: {} (valid for {} minutes)", code, expires_minutes);
+            println!("\nOn the daemon's host, run:");
+            println!("  reattachd apns pair --relay <relay URL> --code {}", code);
+        }
+        Commands::List => {
+            let daemons = reattach_relay::list_daemons(&cli.data_dir).unwrap_or_else(|e| fail(e));
+            if daemons.is_empty() {
+                println!("No paired daemons");
+            }
+            for daemon in daemons {
+                println!(
+                    "{}  {}  paired {}",
+                    daemon.id,
+                    daemon.name,
+                    daemon.paired_at.format("%Y-%m-%d %H:%M UTC")
+                );
+            }
+        }
+        Commands::Revoke { daemon } => {
+            if !reattach_relay::revoke(&cli.data_dir, &daemon).unwrap_or_else(|e| fail(e)) {
+                fail(format!("No paired daemon {}", daemon));
+            }
+            println!("Revoked {}", daemon);
+        }
+    }
+}
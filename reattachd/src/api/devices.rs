@@ -0,0 +1,48 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{Device, SharedAuthService};
+
+/// A registered device without its bearer token.
+#[derive(Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub registered_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    /// Whether the device authenticates with a client certificate.
+    #[serde(default)]
+    pub client_cert: bool,
+}
+
+impl From<Device> for DeviceInfo {
+    fn from(d: Device) -> Self {
+        DeviceInfo {
+            id: d.id,
+            name: d.name,
+            registered_at: d.registered_at,
+            last_seen_at: d.last_seen_at,
+            client_cert: d.client_cert_sha256.is_some(),
+        }
+    }
+}
+
+pub async fn list_devices(State(auth): State<SharedAuthService>) -> Json<Vec<DeviceInfo>> {
+    Json(auth.list_devices().await.into_iter().map(DeviceInfo::from).collect())
+}
+
+pub async fn revoke_device(
+    State(auth): State<SharedAuthService>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if auth.revoke_device(&id).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
@@ -1,6 +1,11 @@
-use axum::{extract::Path, http::StatusCode, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 
+use super::panes::resolve;
 use crate::tmux;
 
 #[derive(Deserialize)]
@@ -14,10 +19,13 @@ pub struct ErrorResponse {
 }
 
 pub async fn send_input(
+    State(servers): State<tmux::Servers>,
     Path(target): Path<String>,
     Json(payload): Json<SendInputRequest>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    match tmux::send_keys(&target, &payload.text) {
+    let (server, target) =
+        resolve(&servers, &target).map_err(|error| (StatusCode::NOT_FOUND, Json(ErrorResponse { error })))?;
+    match tmux::send_keys(server, target, &payload.text) {
         Ok(()) => Ok(StatusCode::OK),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -29,9 +37,12 @@ pub async fn send_input(
 }
 
 pub async fn send_escape(
+    State(servers): State<tmux::Servers>,
     Path(target): Path<String>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    match tmux::send_escape(&target) {
+    let (server, target) =
+        resolve(&servers, &target).map_err(|error| (StatusCode::NOT_FOUND, Json(ErrorResponse { error })))?;
+    match tmux::send_escape(server, target) {
         Ok(()) => Ok(StatusCode::OK),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
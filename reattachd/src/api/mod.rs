@@ -1,13 +1,19 @@
+mod devices;
 mod input;
 mod notifications;
 mod output;
 mod panes;
 mod register;
 mod sessions;
+mod status;
+mod watchers;
 
+pub use devices::{list_devices, revoke_device, DeviceInfo};
 pub use input::{send_escape, send_input};
-pub use notifications::{register_apns_device, send_notification};
-pub use output::get_output;
-pub use panes::delete_pane;
+pub use notifications::{get_notification, list_notifications, register_apns_device, send_notification};
+pub use output::{get_output, OutputLimits};
+pub use panes::{delete_pane, PaneContext};
 pub use register::register_with_setup_token;
-pub use sessions::{create_session, list_sessions};
+pub use sessions::{create_session, list_sessions, SessionResponse, SessionsContext};
+pub use status::{get_status, healthz, StatusContext, StatusResponse};
+pub use watchers::{create_watcher, delete_watcher, list_watchers};
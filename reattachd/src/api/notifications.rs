@@ -1,8 +1,13 @@
-use axum::{extract::State, http::StatusCode, Json};
-use serde::Deserialize;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-use crate::apns::ApnsService;
+use crate::apns::{ApnsError, ApnsService, DeliveryOutcome, DeliveryStatus, HistoryEntry, Notification};
+use crate::auth::Device;
 
 pub type SharedApnsService = Arc<ApnsService>;
 
@@ -21,29 +26,102 @@ pub struct RegisterDeviceRequest {
 pub struct SendNotificationRequest {
     pub title: String,
     pub body: String,
+    #[serde(default)]
+    pub subtitle: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
     pub pane_target: Option<String>,
 }
 
+#[derive(Serialize)]
+pub struct SendNotificationResponse {
+    pub deliveries: Vec<DeliveryOutcome>,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// The notification key comes from the device's `/register`, so a push
+/// token is only ever bound to the key of the device that sent it.
 pub async fn register_apns_device(
     State(apns): State<SharedApnsService>,
+    device: Option<Extension<Device>>,
     Json(payload): Json<RegisterDeviceRequest>,
 ) -> StatusCode {
-    apns.register_device(payload.token, payload.sandbox, payload.device_id, payload.server_name).await;
+    let notification_key = device.and_then(|Extension(device)| device.notification_key);
+    apns.register_device(
+        payload.token,
+        payload.sandbox,
+        payload.device_id,
+        payload.server_name,
+        notification_key,
+    )
+    .await;
     StatusCode::CREATED
 }
 
 pub async fn send_notification(
     State(apns): State<SharedApnsService>,
     Json(payload): Json<SendNotificationRequest>,
-) -> StatusCode {
-    match apns
-        .send_notification(&payload.title, &payload.body, payload.pane_target.as_deref())
-        .await
-    {
-        Ok(()) => StatusCode::OK,
+) -> Result<(StatusCode, Json<SendNotificationResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let notification = Notification {
+        title: payload.title,
+        body: payload.body,
+        subtitle: payload.subtitle,
+        category: payload.category,
+        pane_target: payload.pane_target,
+        queued_at: None,
+    };
+    match apns.send_notification(&notification).await {
+        Ok(deliveries) => {
+            let any = |status| deliveries.iter().any(|d| d.status == status);
+            let status = if any(DeliveryStatus::Delivered) {
+                StatusCode::OK
+            } else if any(DeliveryStatus::Pending) {
+                StatusCode::ACCEPTED
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            Ok((status, Json(SendNotificationResponse { deliveries })))
+        }
         Err(e) => {
             tracing::error!("Failed to send notification: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            let status = match e {
+                ApnsError::NoDeviceToken => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((
+                status,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            ))
         }
     }
 }
+
+pub async fn list_notifications(
+    State(apns): State<SharedApnsService>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<Vec<HistoryEntry>> {
+    Json(apns.history(query.limit).await)
+}
+
+pub async fn get_notification(
+    State(apns): State<SharedApnsService>,
+    Path(id): Path<String>,
+) -> Result<Json<HistoryEntry>, StatusCode> {
+    apns.history_entry(&id).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
@@ -1,23 +1,28 @@
 use axum::{
-    extract::{Path, Query},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 
+use super::panes::resolve;
 use crate::tmux;
 
 #[derive(Deserialize)]
 pub struct OutputQuery {
-    #[serde(default = "default_lines")]
-    pub lines: u32,
+    pub lines: Option<u32>,
 }
 
-fn default_lines() -> u32 {
-    200
-}
+const DEFAULT_LINES: u32 = 200;
 
-const MAX_LINES: u32 = 1000;
+/// Upper bound for `lines`, from the `output.max_lines` setting. Shared so
+/// that a config reload applies to the running router.
+#[derive(Clone)]
+pub struct OutputLimits {
+    pub max_lines: Arc<AtomicU32>,
+}
 
 #[derive(Serialize)]
 pub struct OutputResponse {
@@ -30,19 +35,25 @@ pub struct ErrorResponse {
 }
 
 pub async fn get_output(
+    State(servers): State<tmux::Servers>,
+    State(limits): State<OutputLimits>,
     Path(target): Path<String>,
     Query(query): Query<OutputQuery>,
 ) -> Result<Json<OutputResponse>, (StatusCode, Json<ErrorResponse>)> {
-    if query.lines > MAX_LINES {
+    let max_lines = limits.max_lines.load(Ordering::Relaxed);
+    let lines = query.lines.unwrap_or(DEFAULT_LINES.min(max_lines));
+    if lines > max_lines {
         return Err((
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
-                error: format!("lines must be <= {}", MAX_LINES),
+                error: format!("lines must be <= {}", max_lines),
             }),
         ));
     }
 
-    match tmux::capture_pane(&target, query.lines) {
+    let (server, target) =
+        resolve(&servers, &target).map_err(|error| (StatusCode::NOT_FOUND, Json(ErrorResponse { error })))?;
+    match tmux::capture_pane(server, target, lines) {
         Ok(output) => Ok(Json(OutputResponse { output })),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -1,17 +1,52 @@
-use axum::{extract::Path, http::StatusCode, Json};
+use axum::{
+    extract::{FromRef, Path, State},
+    http::StatusCode,
+    Json,
+};
 use serde::Serialize;
 
+use super::OutputLimits;
 use crate::tmux;
 
+/// State for the `/panes/{target}` routes.
+#[derive(Clone)]
+pub struct PaneContext {
+    pub servers: tmux::Servers,
+    pub output: OutputLimits,
+}
+
+impl FromRef<PaneContext> for tmux::Servers {
+    fn from_ref(context: &PaneContext) -> Self {
+        context.servers.clone()
+    }
+}
+
+impl FromRef<PaneContext> for OutputLimits {
+    fn from_ref(context: &PaneContext) -> Self {
+        context.output.clone()
+    }
+}
+
 #[derive(Serialize)]
 pub struct ErrorResponse {
     pub error: String,
 }
 
+/// The local server a pane target is on and the target within it. Targets
+/// on peers don't get this far; see [`crate::hosts::proxy`].
+pub(super) fn resolve<'a>(servers: &'a tmux::Servers, target: &'a str) -> Result<(&'a tmux::Server, &'a str), String> {
+    servers
+        .resolve(target)
+        .ok_or_else(|| format!("Unknown server '{}'", tmux::split_target(target).0.unwrap_or_default()))
+}
+
 pub async fn delete_pane(
+    State(servers): State<tmux::Servers>,
     Path(target): Path<String>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    match tmux::kill_pane(&target) {
+    let (server, target) =
+        resolve(&servers, &target).map_err(|error| (StatusCode::NOT_FOUND, Json(ErrorResponse { error })))?;
+    match tmux::kill_pane(server, target) {
         Ok(()) => Ok(StatusCode::NO_CONTENT),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
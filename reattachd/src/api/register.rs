@@ -7,12 +7,25 @@ use crate::auth::{SetupTokenValidation, SharedAuthService};
 pub struct RegisterRequest {
     pub setup_token: String,
     pub device_name: String,
+    /// Base64 X9.63 P-256 public key; pushes to a device that sends one are
+    /// end-to-end encrypted. See [`crate::apns::encryption`].
+    #[serde(default)]
+    pub notification_key: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct RegisterResponse {
     pub device_id: String,
     pub device_token: String,
+    /// Base64 PKCS#12 with the device's client certificate and key, when
+    /// the daemon runs with `--mtls`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_identity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_identity_password: Option<String>,
+    /// Whether pushes to the device will be encrypted to its notification
+    /// key. Daemons that predate encryption leave this out.
+    pub notification_encryption: bool,
 }
 
 #[derive(Serialize)]
@@ -25,13 +38,55 @@ pub async fn register_with_setup_token(
     State(auth): State<SharedAuthService>,
     Json(payload): Json<RegisterRequest>,
 ) -> Result<Json<RegisterResponse>, impl IntoResponse> {
+    if let Some(ref key) = payload.notification_key {
+        if let Err(e) = crate::apns::encryption::parse_public_key(key) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(RegisterError {
+                    error: format!("Invalid notification key: {}", e),
+                    code: "INVALID_NOTIFICATION_KEY".to_string(),
+                }),
+            ));
+        }
+    }
+    let notification_encryption = payload.notification_key.is_some();
     match auth
-        .register_device(&payload.setup_token, &payload.device_name)
+        .register_device(&payload.setup_token, &payload.device_name, payload.notification_key)
         .await
     {
+        Ok(device) if auth.requires_client_certs() => {
+            match auth.issue_client_identity(&device.id, &device.name).await {
+                Ok(identity) => Ok(Json(RegisterResponse {
+                    device_id: device.id,
+                    device_token: device.token,
+                    client_identity: Some(base64::Engine::encode(
+                        &base64::engine::general_purpose::STANDARD,
+                        identity.pkcs12,
+                    )),
+                    client_identity_password: Some(identity.password),
+                    notification_encryption,
+                })),
+                Err(e) => {
+                    // Without a certificate the device could never
+                    // authenticate, so don't keep it half registered.
+                    tracing::error!("Failed to issue client certificate: {}", e);
+                    auth.revoke_device(&device.id).await;
+                    Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(RegisterError {
+                            error: "Failed to issue a client certificate.".to_string(),
+                            code: "CLIENT_CERT_FAILED".to_string(),
+                        }),
+                    ))
+                }
+            }
+        }
         Ok(device) => Ok(Json(RegisterResponse {
             device_id: device.id,
             device_token: device.token,
+            client_identity: None,
+            client_identity_password: None,
+            notification_encryption,
         })),
         Err(SetupTokenValidation::Expired) => Err((
             StatusCode::UNAUTHORIZED,
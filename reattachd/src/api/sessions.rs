@@ -1,17 +1,36 @@
-use axum::{http::StatusCode, Json};
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
+use crate::detect::{self, PaneSnapshot, PaneStatus, SharedStatusDetector};
+use crate::hosts::{self, Route, SharedHosts};
 use crate::tmux;
 
-#[derive(Serialize)]
+/// What `/sessions` works with: the local servers and peers to list, and
+/// the detector that tracks the status of local panes.
+pub struct SessionsContext {
+    pub hosts: SharedHosts,
+    pub detector: SharedStatusDetector,
+}
+
+pub type SharedSessionsContext = Arc<SessionsContext>;
+
+#[derive(Serialize, Deserialize)]
 pub struct PaneResponse {
     pub index: u32,
     pub active: bool,
     pub target: String,
     pub current_path: String,
+    pub current_command: String,
+    pub status: PaneStatus,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct WindowResponse {
     pub index: u32,
     pub name: String,
@@ -19,17 +38,28 @@ pub struct WindowResponse {
     pub panes: Vec<PaneResponse>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct SessionResponse {
     pub name: String,
     pub attached: bool,
     pub windows: Vec<WindowResponse>,
+    /// The server the session is on: `default`, a socket from
+    /// `tmux.sockets`, or a peer's ID followed by one of those.
+    #[serde(default = "default_server")]
+    pub server: String,
+}
+
+fn default_server() -> String {
+    tmux::DEFAULT_SERVER.to_string()
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct CreateSessionRequest {
     pub name: String,
     pub cwd: String,
+    /// Where to create the session; the default tmux server if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -37,55 +67,157 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
-pub async fn list_sessions() -> Result<Json<Vec<SessionResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    match tmux::list_sessions() {
-        Ok(sessions) => {
-            let response: Vec<SessionResponse> = sessions
+fn pane_status(detector: &detect::StatusDetector, server: &tmux::Server, pane: &tmux::Pane) -> PaneStatus {
+    // Capture only the visible screen so that `cursor_y` indexes into it.
+    let screen: Vec<String> = tmux::capture_pane_text(server, &pane.target, 0)
+        .map(|output| output.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    let quiet_for = detector.observe(&server.qualify(&pane.target), &screen);
+    detect::classify(&PaneSnapshot {
+        current_command: &pane.current_command,
+        dead: pane.dead,
+        screen: &screen,
+        cursor_y: Some(pane.cursor_y),
+        quiet_for,
+    })
+}
+
+/// Qualifies the sessions a peer lists with the peer's ID.
+fn adopt(peer_id: &str, mut session: SessionResponse) -> SessionResponse {
+    let qualify = |rest: &str| format!("{}{}{}", peer_id, tmux::SEPARATOR, rest);
+    session.server = if session.server == tmux::DEFAULT_SERVER {
+        peer_id.to_string()
+    } else {
+        qualify(&session.server)
+    };
+    for pane in session.windows.iter_mut().flat_map(|w| w.panes.iter_mut()) {
+        pane.target = qualify(&pane.target);
+    }
+    session
+}
+
+/// The sessions on every local server, with the status of each pane. This
+/// runs tmux once per server and once per pane, so it belongs on a blocking
+/// thread.
+fn list_local(context: &SessionsContext) -> Result<Vec<SessionResponse>, String> {
+    let mut listed = Vec::new();
+    for server in context.hosts.servers.iter() {
+        match tmux::list_sessions(server) {
+            Ok(sessions) => listed.push((server, sessions)),
+            Err(e) if server.is_default() => return Err(e.to_string()),
+            Err(e) => return Err(format!("{}: {}", server.id, e)),
+        }
+    }
+    let targets: Vec<String> = listed
+        .iter()
+        .flat_map(|(server, sessions)| {
+            sessions
+                .iter()
+                .flat_map(|s| s.windows.iter())
+                .flat_map(|w| w.panes.iter())
+                .map(|p| server.qualify(&p.target))
+        })
+        .collect();
+    context.detector.retain(&targets.iter().map(String::as_str).collect::<Vec<_>>());
+
+    let mut response: Vec<SessionResponse> = Vec::new();
+    for (server, sessions) in listed {
+        response.extend(sessions.into_iter().map(|s| SessionResponse {
+            name: s.name,
+            attached: s.attached,
+            windows: s
+                .windows
                 .into_iter()
-                .map(|s| SessionResponse {
-                    name: s.name,
-                    attached: s.attached,
-                    windows: s
-                        .windows
+                .map(|w| WindowResponse {
+                    index: w.index,
+                    name: w.name,
+                    active: w.active,
+                    panes: w
+                        .panes
                         .into_iter()
-                        .map(|w| WindowResponse {
-                            index: w.index,
-                            name: w.name,
-                            active: w.active,
-                            panes: w
-                                .panes
-                                .into_iter()
-                                .map(|p| PaneResponse {
-                                    index: p.index,
-                                    active: p.active,
-                                    target: p.target,
-                                    current_path: p.current_path,
-                                })
-                                .collect(),
+                        .map(|p| PaneResponse {
+                            status: pane_status(&context.detector, server, &p),
+                            index: p.index,
+                            active: p.active,
+                            target: server.qualify(&p.target),
+                            current_path: p.current_path,
+                            current_command: p.current_command,
                         })
                         .collect(),
                 })
-                .collect();
-            Ok(Json(response))
+                .collect(),
+            server: server.id.clone(),
+        }));
+    }
+    Ok(response)
+}
+
+/// The sessions on every local server, followed by those of every peer that
+/// answers. Requests from another daemon get only the local ones.
+pub async fn list_sessions(
+    State(context): State<SharedSessionsContext>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SessionResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    // Peers are asked first so that they answer while tmux is listing.
+    let mut peers = tokio::task::JoinSet::new();
+    if !hosts::is_proxied(&headers) {
+        for (index, peer) in context.hosts.peers().iter().enumerate() {
+            let peer = peer.clone();
+            peers.spawn(async move { (index, peer.get::<Vec<SessionResponse>>(&["sessions"]).await) });
+        }
+    }
+
+    let local = {
+        let context = context.clone();
+        tokio::task::spawn_blocking(move || list_local(&context)).await
+    };
+    let mut response = local
+        .map_err(|e| e.to_string())
+        .and_then(|listed| listed)
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error })))?;
+
+    let mut answers: Vec<_> = peers.join_all().await;
+    answers.sort_by_key(|(index, _)| *index);
+    for (index, answer) in answers {
+        let peer = &context.hosts.peers()[index];
+        match answer {
+            Ok(sessions) => response.extend(sessions.into_iter().map(|s| adopt(&peer.id, s))),
+            Err(e) => tracing::warn!("Failed to list the sessions of peer {}: {}", peer.id, e),
         }
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )),
     }
+    Ok(Json(response))
 }
 
 pub async fn create_session(
+    State(context): State<SharedSessionsContext>,
     Json(payload): Json<CreateSessionRequest>,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    match tmux::create_session(&payload.name, &payload.cwd) {
-        Ok(()) => Ok(StatusCode::CREATED),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let server_id = payload.server.as_deref().unwrap_or(tmux::DEFAULT_SERVER);
+    match context.hosts.route(server_id) {
+        Some(Route::Local(server)) => match tmux::create_session(server, &payload.name, &payload.cwd) {
+            Ok(()) => Ok(StatusCode::CREATED.into_response()),
+            Err(e) => Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )),
+        },
+        Some(Route::Peer(peer, server)) => {
+            let request = CreateSessionRequest {
+                server: server.map(str::to_string),
+                ..payload
+            };
+            let body = serde_json::to_vec(&request).unwrap_or_default();
+            let content_type = HeaderValue::from_static("application/json");
+            Ok(peer
+                .forward(Method::POST, &["sessions"], None, Some(&content_type), body.into())
+                .await)
+        }
+        None => Err((
+            StatusCode::NOT_FOUND,
             Json(ErrorResponse {
-                error: e.to_string(),
+                error: format!("Unknown server '{}'", server_id),
             }),
         )),
     }
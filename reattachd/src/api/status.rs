@@ -0,0 +1,153 @@
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::api::notifications::SharedApnsService;
+use crate::auth::SharedAuthService;
+use crate::tmux;
+
+/// What the daemon knows about itself, for `/status`.
+pub struct StatusContext {
+    pub started_at: DateTime<Utc>,
+    /// Addresses the daemon listens on, e.g. `https://100.64.0.1:8787`.
+    pub binds: Vec<String>,
+    pub tls_cert: Option<PathBuf>,
+    pub servers: tmux::Servers,
+    pub auth: SharedAuthService,
+    pub apns: Option<SharedApnsService>,
+}
+
+pub type SharedStatusContext = Arc<StatusContext>;
+
+#[derive(Serialize, Deserialize)]
+pub struct TlsStatus {
+    pub cert_path: PathBuf,
+    /// Days until the certificate expires; `None` if it can't be read.
+    pub expires_in_days: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TmuxStatus {
+    pub reachable: bool,
+    pub sessions: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApnsStatus {
+    pub configured: bool,
+    pub push_tokens: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusResponse {
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: i64,
+    pub binds: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsStatus>,
+    pub tmux: TmuxStatus,
+    pub apns: ApnsStatus,
+    pub devices: usize,
+    /// Things that keep the daemon from working; `reattachd status` exits
+    /// non-zero if there are any.
+    pub problems: Vec<String>,
+    /// Things worth a look that don't break anything yet.
+    pub warnings: Vec<String>,
+}
+
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+pub async fn get_status(State(context): State<SharedStatusContext>) -> Json<StatusResponse> {
+    let mut problems = Vec::new();
+    let mut warnings = Vec::new();
+
+    let tls = context.tls_cert.as_ref().map(|cert_path| TlsStatus {
+        expires_in_days: crate::cert_remaining_days(cert_path),
+        cert_path: cert_path.clone(),
+    });
+    if let Some(ref tls) = tls {
+        match tls.expires_in_days {
+            None => problems.push(format!("TLS certificate {} can't be read", tls.cert_path.display())),
+            Some(days) if days < 0 => problems.push("TLS certificate has expired".to_string()),
+            Some(days) if days < crate::CERT_MIN_REMAINING_DAYS => {
+                warnings.push(format!("TLS certificate expires in {} days", days))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let servers = context.servers.clone();
+    let listed = tokio::task::spawn_blocking(move || {
+        let mut sessions = 0;
+        for server in servers.iter() {
+            match tmux::list_sessions(server) {
+                Ok(listed) => sessions += listed.len(),
+                Err(e) if server.is_default() => return Err(e.to_string()),
+                Err(e) => return Err(format!("{}: {}", server.id, e)),
+            }
+        }
+        Ok(sessions)
+    });
+    let tmux = match listed.await {
+        Ok(Ok(sessions)) => TmuxStatus {
+            reachable: true,
+            sessions,
+            error: None,
+        },
+        Ok(Err(e)) => TmuxStatus {
+            reachable: false,
+            sessions: 0,
+            error: Some(e),
+        },
+        Err(e) => TmuxStatus {
+            reachable: false,
+            sessions: 0,
+            error: Some(e.to_string()),
+        },
+    };
+    if let Some(ref error) = tmux.error {
+        problems.push(format!("tmux is not reachable: {}", error.trim()));
+    }
+
+    let apns = match context.apns {
+        Some(ref apns) => ApnsStatus {
+            configured: true,
+            push_tokens: apns.token_count().await,
+        },
+        None => ApnsStatus {
+            configured: false,
+            push_tokens: 0,
+        },
+    };
+    if !apns.configured {
+        warnings.push("APNs is not configured; push notifications are disabled".to_string());
+    } else if apns.push_tokens == 0 {
+        warnings.push("No device has registered for push notifications".to_string());
+    }
+
+    let devices = context.auth.list_devices().await.len();
+    if devices == 0 {
+        warnings.push("No devices registered; the API is open to anyone who can reach it".to_string());
+    }
+
+    let now = Utc::now();
+    Json(StatusResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        started_at: context.started_at,
+        uptime_secs: (now - context.started_at).num_seconds(),
+        binds: context.binds.clone(),
+        tls,
+        tmux,
+        apns,
+        devices,
+        problems,
+        warnings,
+    })
+}
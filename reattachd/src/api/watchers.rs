@@ -0,0 +1,43 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+
+use crate::watch::{NewWatcher, SharedWatchService, Watcher};
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+pub async fn list_watchers(State(watch): State<SharedWatchService>) -> Json<Vec<Watcher>> {
+    Json(watch.list().await)
+}
+
+pub async fn create_watcher(
+    State(watch): State<SharedWatchService>,
+    Json(payload): Json<NewWatcher>,
+) -> Result<(StatusCode, Json<Watcher>), (StatusCode, Json<ErrorResponse>)> {
+    match watch.add(payload).await {
+        Ok(watcher) => Ok((StatusCode::CREATED, Json(watcher))),
+        Err(e) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )),
+    }
+}
+
+pub async fn delete_watcher(
+    State(watch): State<SharedWatchService>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if watch.remove(&id).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
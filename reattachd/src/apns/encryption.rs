@@ -0,0 +1,142 @@
+//! End-to-end encrypted notifications. A device that wants them sends a P-256
+//! key-agreement public key (base64 of the X9.63 uncompressed point, as in
+//! CryptoKit's `x963Representation`) with `/register`. Each push to it is
+//! then sealed to that key:
+//!
+//! 1. an ephemeral P-256 key agrees a shared secret with the device key;
+//! 2. HKDF-SHA256 with an empty salt and [`INFO`] derives a 32-byte key;
+//! 3. AES-256-GCM encrypts the JSON content with a random 12-byte nonce and
+//!    the notification ID as associated data.
+//!
+//! The push carries `{"v": 1, "key": <ephemeral public key>, "box": <nonce
+//! || ciphertext || tag>}`, both base64, in `encrypted`; that last layout is
+//! CryptoKit's `AES.GCM.SealedBox.combined`. Only the device's notification
+//! service extension, which holds the private key, can open it.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use openssl::bn::BigNumContext;
+use openssl::derive::Deriver;
+use openssl::ec::{EcGroup, EcKey, EcPoint, PointConversionForm};
+use openssl::md::Md;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::pkey_ctx::PkeyCtx;
+use openssl::symm::Cipher;
+use serde_json::Value;
+
+pub const VERSION: u32 = 1;
+pub const INFO: &[u8] = b"reattach notification v1";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+fn group() -> EcGroup {
+    EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("P-256 is built into OpenSSL")
+}
+
+/// Parses a device's base64 X9.63 P-256 public key.
+pub fn parse_public_key(key: &str) -> Result<PKey<Public>, String> {
+    let invalid = || "not a base64 X9.63 P-256 public key".to_string();
+    let bytes = STANDARD.decode(key.trim()).map_err(|_| invalid())?;
+    let group = group();
+    let mut ctx = BigNumContext::new().map_err(|e| e.to_string())?;
+    let point = EcPoint::from_bytes(&group, &bytes, &mut ctx).map_err(|_| invalid())?;
+    let key = EcKey::from_public_key(&group, &point).map_err(|_| invalid())?;
+    key.check_key().map_err(|_| invalid())?;
+    PKey::from_ec_key(key).map_err(|e| e.to_string())
+}
+
+fn derive_key(shared_secret: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+    ctx.derive_init()?;
+    ctx.set_hkdf_md(Md::sha256())?;
+    ctx.set_hkdf_key(shared_secret)?;
+    ctx.add_hkdf_info(INFO)?;
+    let mut key = vec![0u8; 32];
+    ctx.derive(Some(&mut key))?;
+    Ok(key)
+}
+
+/// Encrypts `plaintext` for the holder of `device_key` and returns the value
+/// for the payload's `encrypted` field.
+pub fn seal(device_key: &str, plaintext: &[u8], associated_data: &[u8]) -> Result<Value, String> {
+    let peer = parse_public_key(device_key)?;
+    let group = group();
+    let ephemeral = EcKey::generate(&group).and_then(PKey::from_ec_key).map_err(|e| e.to_string())?;
+    let mut deriver = Deriver::new(&ephemeral).map_err(|e| e.to_string())?;
+    deriver.set_peer(&peer).map_err(|e| e.to_string())?;
+    let shared_secret = deriver.derive_to_vec().map_err(|e| e.to_string())?;
+    let key = derive_key(&shared_secret).map_err(|e| e.to_string())?;
+
+    let mut nonce = [0u8; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut nonce).map_err(|e| e.to_string())?;
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = openssl::symm::encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        associated_data,
+        plaintext,
+        &mut tag,
+    )
+    .map_err(|e| e.to_string())?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+
+    let mut ctx = BigNumContext::new().map_err(|e| e.to_string())?;
+    let ephemeral_public = ephemeral
+        .ec_key()
+        .and_then(|key| key.public_key().to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx))
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({
+        "v": VERSION,
+        "key": STANDARD.encode(ephemeral_public),
+        "box": STANDARD.encode(sealed),
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use openssl::pkey::Private;
+
+    /// A device key pair: the private key and the base64 public key it sends
+    /// to `/register`.
+    pub(crate) fn device_key() -> (PKey<Private>, String) {
+        let group = group();
+        let key = EcKey::generate(&group).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let public = key
+            .public_key()
+            .to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .unwrap();
+        (PKey::from_ec_key(key).unwrap(), STANDARD.encode(public))
+    }
+
+    /// What the notification service extension does.
+    pub(crate) fn open(private_key: &PKey<Private>, encrypted: &Value, associated_data: &[u8]) -> Result<Vec<u8>, String> {
+        assert_eq!(encrypted["v"], VERSION);
+        let ephemeral = parse_public_key(encrypted["key"].as_str().unwrap())?;
+        let sealed = STANDARD.decode(encrypted["box"].as_str().unwrap()).unwrap();
+        let mut deriver = Deriver::new(private_key).unwrap();
+        deriver.set_peer(&ephemeral).unwrap();
+        let key = derive_key(&deriver.derive_to_vec().unwrap()).unwrap();
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        openssl::symm::decrypt_aead(Cipher::aes_256_gcm(), &key, Some(nonce), associated_data, ciphertext, tag)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn only_the_device_key_opens_sealed_content() {
+        let (private_key, public_key) = device_key();
+        let sealed = seal(&public_key, b"{\"body\":\"secret\"}", b"notification-1").unwrap();
+        assert_eq!(open(&private_key, &sealed, b"notification-1").unwrap(), b"{\"body\":\"secret\"}");
+        assert!(open(&private_key, &sealed, b"notification-2").is_err());
+        let (other_key, _) = device_key();
+        assert!(open(&other_key, &sealed, b"notification-1").is_err());
+
+        assert!(parse_public_key("bm90IGEga2V5").is_err());
+        assert!(seal("", b"{}", b"").is_err());
+    }
+}
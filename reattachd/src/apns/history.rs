@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+/// Older notifications are dropped from the history beyond this count.
+const MAX_ENTRIES: usize = 200;
+
+/// A sent notification with the full text its pushed body was cut from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub sent_at: DateTime<Utc>,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pane_target: Option<String>,
+    /// Set when the notification waited in the offline queue.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queued_at: Option<DateTime<Utc>>,
+    /// Body as pushed to devices.
+    pub summary: String,
+    /// Body as received, before summarizing.
+    pub body: String,
+}
+
+/// Recent notifications, newest last, persisted to
+/// `notification_history.json` in the data dir.
+pub struct History {
+    entries: RwLock<VecDeque<HistoryEntry>>,
+    path: PathBuf,
+}
+
+impl History {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            entries: RwLock::new(entries),
+            path,
+        }
+    }
+
+    pub async fn push(&self, entry: HistoryEntry) {
+        let mut entries = self.entries.write().await;
+        entries.push_back(entry);
+        while entries.len() > MAX_ENTRIES {
+            entries.pop_front();
+        }
+        let result = serde_json::to_string(&*entries)
+            .map_err(std::io::Error::other)
+            .and_then(|content| std::fs::write(&self.path, content));
+        if let Err(e) = result {
+            tracing::error!("Failed to save notification history: {}", e);
+        }
+    }
+
+    /// The most recent `limit` entries, newest first.
+    pub async fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.entries.read().await.iter().rev().take(limit).cloned().collect()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.entries.read().await.len()
+    }
+
+    pub async fn get(&self, id: &str) -> Option<HistoryEntry> {
+        self.entries.read().await.iter().find(|e| e.id == id).cloned()
+    }
+}
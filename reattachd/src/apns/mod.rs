@@ -0,0 +1,1043 @@
+pub mod credentials;
+pub mod encryption;
+mod history;
+pub mod relay;
+
+use a2::{DefaultNotificationBuilder, NotificationBuilder, NotificationOptions};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, RwLock};
+
+pub use apns_client::{ApnsClient, SendError, TokenSigner, PRODUCTION_URL, SANDBOX_URL};
+pub use history::HistoryEntry;
+pub use relay::{Pairing, RelayClient};
+
+use crate::summary;
+use history::History;
+
+/// APNs rejects payloads larger than this.
+const MAX_PAYLOAD_BYTES: usize = 4096;
+/// Default budget for the summarized notification body.
+pub const DEFAULT_BODY_BYTES: usize = 1024;
+/// Largest body budget allowed, leaving room in the payload for the title,
+/// subtitle and custom data.
+pub const MAX_BODY_BYTES: usize = 3072;
+/// How long [`ApnsService::send_notification`] waits for first attempts.
+/// Retries, and first attempts still running then, carry on in the
+/// background, so `/notify` answers well within the 10 s agent hooks give
+/// `reattachd notify`.
+const FIRST_ATTEMPT_WAIT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApnsError {
+    #[error("APNs client error: {0}")]
+    Client(String),
+    #[error("No device token registered")]
+    NoDeviceToken,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceToken {
+    pub token: String,
+    pub sandbox: bool,
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub server_name: String,
+    /// The device's key for end-to-end encrypted pushes; see [`encryption`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notification_key: Option<String>,
+}
+
+/// An alert to push to every registered device.
+#[derive(Debug, Clone, Default)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    pub subtitle: Option<String>,
+    /// `aps.category`, which the app uses to group alerts and offer actions.
+    pub category: Option<String>,
+    /// Pane the app opens when the notification is tapped.
+    pub pane_target: Option<String>,
+    /// When `reattachd notify` queued it because the daemon was down.
+    pub queued_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Notification {
+    pub fn new(title: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            body: body.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Where pushes go.
+pub enum Upstream {
+    /// Straight to APNs, signed with our own key.
+    Direct {
+        key: String,
+        key_id: String,
+        team_id: String,
+        bundle_id: String,
+        /// Base URL for sandbox (development) pushes, normally [`SANDBOX_URL`].
+        sandbox_url: String,
+        /// Base URL for production pushes, normally [`PRODUCTION_URL`].
+        production_url: String,
+    },
+    /// To a relay that holds the key; see [`relay`].
+    Relay(Pairing),
+}
+
+pub struct ApnsConfig {
+    pub upstream: Upstream,
+    pub data_dir: PathBuf,
+    pub retry_policy: RetryPolicy,
+    /// Notification bodies are summarized to fit this many bytes; see
+    /// [`summary::summarize`].
+    pub body_max_bytes: usize,
+    /// Never send plaintext to devices without a notification key.
+    pub require_encryption: bool,
+}
+
+pub struct ApnsService {
+    sender: Sender,
+    device_tokens: Arc<RwLock<Vec<DeviceToken>>>,
+    tokens_file: PathBuf,
+    retry_policy: RetryPolicy,
+    body_max_bytes: AtomicUsize,
+    require_encryption: AtomicBool,
+    history: History,
+}
+
+/// How often and how patiently a single device delivery is retried when APNs
+/// reports a transient failure.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after the given (1-based) failed attempt.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// APNs accepted the notification.
+    Delivered,
+    /// APNs rejected the token for good; it has been removed.
+    Removed,
+    /// Delivery failed, either permanently or after exhausting retries.
+    Failed,
+    /// The first attempt failed transiently or is still running; delivery
+    /// goes on in the background.
+    Pending,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryOutcome {
+    pub device_id: String,
+    /// Shortened device token, safe to show in logs and CLI output.
+    pub token: String,
+    pub sandbox: bool,
+    pub status: DeliveryStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub attempts: u32,
+    #[serde(skip)]
+    full_token: String,
+}
+
+impl ApnsService {
+    pub async fn new(config: ApnsConfig) -> Result<Self, ApnsError> {
+        let sender = Sender::new(config.upstream).map_err(|e| ApnsError::Client(e.to_string()))?;
+
+        std::fs::create_dir_all(&config.data_dir)?;
+        let tokens_file = config.data_dir.join("device_tokens.json");
+        let history = History::load(config.data_dir.join("notification_history.json"));
+
+        let device_tokens = Self::load_tokens(&tokens_file).unwrap_or_default();
+        tracing::info!("Loaded {} device tokens from {:?}", device_tokens.len(), tokens_file);
+
+        Ok(Self {
+            sender,
+            device_tokens: Arc::new(RwLock::new(device_tokens)),
+            tokens_file,
+            retry_policy: config.retry_policy,
+            body_max_bytes: AtomicUsize::new(config.body_max_bytes.min(MAX_BODY_BYTES)),
+            require_encryption: AtomicBool::new(config.require_encryption),
+            history,
+        })
+    }
+
+    fn load_tokens(path: &PathBuf) -> Option<Vec<DeviceToken>> {
+        let file = File::open(path).ok()?;
+        let reader = BufReader::new(file);
+        serde_json::from_reader(reader).ok()
+    }
+
+    fn save_tokens(path: &PathBuf, tokens: &[DeviceToken]) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(writer, tokens)
+            .map_err(std::io::Error::other)
+    }
+
+    pub async fn register_device(
+        &self,
+        token: String,
+        sandbox: bool,
+        device_id: String,
+        server_name: String,
+        notification_key: Option<String>,
+    ) {
+        let mut tokens = self.device_tokens.write().await;
+        let device_token = DeviceToken {
+            token: token.clone(),
+            sandbox,
+            device_id: device_id.clone(),
+            server_name: server_name.clone(),
+            notification_key: notification_key.clone(),
+        };
+
+        if let Some(existing) = tokens.iter_mut().find(|t| t.token == token) {
+            let mut updated = false;
+            if existing.sandbox != sandbox {
+                tracing::info!(
+                    "Updated device token: {}... (sandbox: {} -> {})",
+                    &token[..20.min(token.len())],
+                    existing.sandbox,
+                    sandbox
+                );
+                existing.sandbox = sandbox;
+                updated = true;
+            }
+            if existing.device_id != device_id {
+                tracing::info!(
+                    "Updated device token: {}... (device_id: {} -> {})",
+                    &token[..20.min(token.len())],
+                    existing.device_id,
+                    device_id
+                );
+                existing.device_id = device_id;
+                updated = true;
+            }
+            if existing.server_name != server_name {
+                tracing::info!(
+                    "Updated device token: {}... (server_name: {} -> {})",
+                    &token[..20.min(token.len())],
+                    existing.server_name,
+                    server_name
+                );
+                existing.server_name = server_name;
+                updated = true;
+            }
+            if existing.notification_key != notification_key {
+                tracing::info!(
+                    "Updated device token: {}... (encrypted: {} -> {})",
+                    &token[..20.min(token.len())],
+                    existing.notification_key.is_some(),
+                    notification_key.is_some()
+                );
+                existing.notification_key = notification_key;
+                updated = true;
+            }
+            if updated {
+                if let Err(e) = Self::save_tokens(&self.tokens_file, &tokens) {
+                    tracing::error!("Failed to save device tokens: {}", e);
+                }
+            }
+        } else {
+            tracing::info!(
+                "Registered device token: {}... (sandbox: {}, device_id: {}, server_name: {}, encrypted: {})",
+                &token[..20.min(token.len())],
+                sandbox,
+                device_id,
+                server_name,
+                device_token.notification_key.is_some()
+            );
+            tokens.push(device_token);
+            if let Err(e) = Self::save_tokens(&self.tokens_file, &tokens) {
+                tracing::error!("Failed to save device tokens: {}", e);
+            }
+        }
+    }
+
+    pub async fn send_notification(&self, notification: &Notification) -> Result<Vec<DeliveryOutcome>, ApnsError> {
+        let tokens = self.device_tokens.read().await.clone();
+        if tokens.is_empty() {
+            return Err(ApnsError::NoDeviceToken);
+        }
+
+        if let Some(ref target) = notification.pane_target {
+            tracing::info!("Notification paneTarget: {}", target);
+        }
+
+        // Devices get a summary; the full text stays in the history, where
+        // the app can look it up by `notificationId`.
+        let entry = HistoryEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            sent_at: chrono::Utc::now(),
+            title: notification.title.clone(),
+            subtitle: notification.subtitle.clone(),
+            category: notification.category.clone(),
+            pane_target: notification.pane_target.clone(),
+            queued_at: notification.queued_at,
+            summary: summary::summarize(&notification.body, self.body_max_bytes.load(Ordering::Relaxed)),
+            body: notification.body.clone(),
+        };
+        let notification_id = entry.id.clone();
+        let body = entry.summary.clone();
+        self.history.push(entry).await;
+
+        // Each device gets its own delivery task so that one device backing off
+        // does not hold up the others. A task reports the outcome of its first
+        // attempt, or its final one if that came first, and keeps retrying
+        // after we have answered.
+        let mut firsts = Vec::new();
+        for device_token in tokens {
+            let pending = DeliveryOutcome::new(&device_token, DeliveryStatus::Pending, None, 1);
+            let job = DeliveryJob {
+                notification: Notification {
+                    title: notification_title(&device_token.server_name, &notification.title),
+                    body: body.clone(),
+                    ..notification.clone()
+                },
+                notification_id: notification_id.clone(),
+                require_encryption: self.require_encryption.load(Ordering::Relaxed),
+                device_token,
+            };
+            let (first_tx, first_rx) = oneshot::channel();
+            let (sender, policy) = (self.sender.clone(), self.retry_policy);
+            let (tokens, tokens_file) = (Arc::clone(&self.device_tokens), self.tokens_file.clone());
+            tokio::spawn(async move {
+                let mut first = Some(first_tx);
+                let outcome = deliver(sender, job, policy, &mut first).await;
+                if outcome.status == DeliveryStatus::Removed {
+                    remove_token(&tokens, &tokens_file, &outcome.full_token).await;
+                }
+                if let Some(first) = first {
+                    let _ = first.send(outcome);
+                }
+            });
+            firsts.push((pending, first_rx));
+        }
+
+        let deadline = tokio::time::Instant::now() + FIRST_ATTEMPT_WAIT;
+        let mut outcomes = Vec::new();
+        for (pending, first) in firsts {
+            match tokio::time::timeout_at(deadline, first).await {
+                Ok(Ok(outcome)) => outcomes.push(outcome),
+                Ok(Err(_)) => tracing::error!("APNs delivery task for token {} ended without an outcome", pending.token),
+                Err(_) => outcomes.push(pending),
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Re-reads the device tokens from disk and applies new notification
+    /// settings. Returns the number of tokens; on error the current ones are
+    /// kept.
+    pub async fn reload(&self, body_max_bytes: usize, require_encryption: bool) -> Result<usize, String> {
+        self.body_max_bytes
+            .store(body_max_bytes.min(MAX_BODY_BYTES), Ordering::Relaxed);
+        self.require_encryption.store(require_encryption, Ordering::Relaxed);
+        let tokens = match std::fs::read_to_string(&self.tokens_file) {
+            Ok(content) => serde_json::from_str::<Vec<DeviceToken>>(&content)
+                .map_err(|e| format!("Invalid {}: {}", self.tokens_file.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.tokens_file.display(), e)),
+        };
+        let count = tokens.len();
+        *self.device_tokens.write().await = tokens;
+        Ok(count)
+    }
+
+    pub async fn token_count(&self) -> usize {
+        self.device_tokens.read().await.len()
+    }
+
+    pub async fn history_len(&self) -> usize {
+        self.history.len().await
+    }
+
+    /// The most recent `limit` notifications, newest first.
+    pub async fn history(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.history.recent(limit).await
+    }
+
+    pub async fn history_entry(&self, id: &str) -> Option<HistoryEntry> {
+        self.history.get(id).await
+    }
+
+}
+
+/// Forgets a token APNs rejected for good. Delivery tasks call this, possibly
+/// after `send_notification` has returned.
+async fn remove_token(device_tokens: &RwLock<Vec<DeviceToken>>, tokens_file: &PathBuf, token: &str) {
+    let mut tokens = device_tokens.write().await;
+    tokens.retain(|t| t.token != token);
+    if let Err(e) = ApnsService::save_tokens(tokens_file, &tokens) {
+        tracing::error!("Failed to save device tokens: {}", e);
+    }
+    tracing::info!("Removed invalid token {}...", token_prefix(token));
+}
+
+#[derive(Debug, PartialEq)]
+enum FailureKind {
+    /// The device token is no longer valid and should be forgotten.
+    Terminal,
+    /// Worth retrying after a backoff.
+    Transient,
+    /// Retrying will not help, but the token itself may still be fine.
+    Permanent,
+}
+
+fn classify_error(error: &SendError) -> FailureKind {
+    match error {
+        SendError::Rejected { status, reason } => match reason.as_deref() {
+            Some("BadDeviceToken")
+            | Some("Unregistered")
+            | Some("ExpiredToken")
+            | Some("DeviceTokenNotForTopic") => FailureKind::Terminal,
+            Some("TooManyRequests")
+            | Some("InternalServerError")
+            | Some("ServiceUnavailable")
+            | Some("Shutdown")
+            | Some("IdleTimeout")
+            | Some("ExpiredProviderToken") => FailureKind::Transient,
+            _ if *status == 410 => FailureKind::Terminal,
+            _ if *status == 429 || *status >= 500 => FailureKind::Transient,
+            _ => FailureKind::Permanent,
+        },
+        SendError::Transport(_) => FailureKind::Transient,
+        SendError::Signing(_) => FailureKind::Permanent,
+    }
+}
+
+fn failure_reason(error: &SendError) -> String {
+    match error {
+        SendError::Rejected {
+            reason: Some(reason),
+            ..
+        } => reason.clone(),
+        SendError::Rejected { status, .. } => format!("HTTP {}", status),
+        other => other.to_string(),
+    }
+}
+
+fn token_prefix(token: &str) -> &str {
+    &token[..20.min(token.len())]
+}
+
+fn notification_title(server_name: &str, title: &str) -> String {
+    if server_name.is_empty() {
+        return title.to_string();
+    }
+    let full_title = format!("{}: {}", server_name, title);
+    const MAX_TITLE_LEN: usize = 40;
+    if full_title.chars().count() > MAX_TITLE_LEN {
+        let prefix = format!("{}: ...", server_name);
+        let prefix_len = prefix.chars().count();
+        let remaining = MAX_TITLE_LEN.saturating_sub(prefix_len);
+        let title_chars: Vec<char> = title.chars().collect();
+        let skip = title_chars.len().saturating_sub(remaining);
+        let truncated: String = title_chars.into_iter().skip(skip).collect();
+        format!("{}: ...{}", server_name, truncated)
+    } else {
+        full_title
+    }
+}
+
+/// Sends one payload to one device, through whichever upstream is configured.
+#[derive(Clone)]
+enum Sender {
+    Direct {
+        sandbox: ApnsClient,
+        production: ApnsClient,
+        bundle_id: String,
+    },
+    Relay(RelayClient),
+}
+
+impl Sender {
+    fn new(upstream: Upstream) -> Result<Self, SendError> {
+        match upstream {
+            Upstream::Direct {
+                key,
+                key_id,
+                team_id,
+                bundle_id,
+                sandbox_url,
+                production_url,
+            } => {
+                let signer = Arc::new(TokenSigner::new(&key, &key_id, &team_id)?);
+                let sandbox = ApnsClient::new(&sandbox_url, signer.clone())?;
+                let production = ApnsClient::new(&production_url, signer)?;
+                tracing::info!(
+                    "APNs clients initialized (sandbox: {}, production: {})",
+                    sandbox.base_url(),
+                    production.base_url()
+                );
+                Ok(Self::Direct {
+                    sandbox,
+                    production,
+                    bundle_id,
+                })
+            }
+            Upstream::Relay(pairing) => {
+                let client = RelayClient::new(pairing)?;
+                tracing::info!("Sending pushes through the relay at {}", client.url());
+                Ok(Self::Relay(client))
+            }
+        }
+    }
+
+    async fn send(&self, device_token: &DeviceToken, payload: String) -> Result<Option<String>, SendError> {
+        match self {
+            Self::Direct {
+                sandbox,
+                production,
+                bundle_id,
+            } => {
+                let client = if device_token.sandbox { sandbox } else { production };
+                client.send(&device_token.token, bundle_id, payload).await
+            }
+            Self::Relay(client) => client.send(&device_token.token, device_token.sandbox, payload).await,
+        }
+    }
+}
+
+struct DeliveryJob {
+    device_token: DeviceToken,
+    notification: Notification,
+    notification_id: String,
+    require_encryption: bool,
+}
+
+/// What an encrypted push shows if the notification service extension can't
+/// decrypt it.
+const ENCRYPTED_PLACEHOLDER_BODY: &str = "New notification";
+
+fn payload_json(job: &DeliveryJob, body: &str) -> Result<String, String> {
+    let device_token = &job.device_token;
+    let notification = &job.notification;
+    let mut builder = DefaultNotificationBuilder::new().set_sound("default");
+    if device_token.notification_key.is_some() {
+        let title = if device_token.server_name.is_empty() { "Reattach" } else { &device_token.server_name };
+        builder = builder
+            .set_title(title)
+            .set_body(ENCRYPTED_PLACEHOLDER_BODY)
+            .set_mutable_content();
+    } else {
+        builder = builder.set_title(&notification.title).set_body(body);
+        if let Some(ref subtitle) = notification.subtitle {
+            builder = builder.set_subtitle(subtitle);
+        }
+    }
+    if let Some(ref category) = notification.category {
+        builder = builder.set_category(category);
+    }
+    let mut payload = builder.build(&device_token.token, NotificationOptions::default());
+
+    if let Some(ref key) = device_token.notification_key {
+        let content = serde_json::json!({
+            "title": notification.title,
+            "subtitle": notification.subtitle,
+            "body": body,
+            "paneTarget": notification.pane_target,
+        });
+        let sealed = encryption::seal(key, content.to_string().as_bytes(), job.notification_id.as_bytes())?;
+        payload.data.insert("encrypted", sealed);
+    } else if let Some(ref target) = notification.pane_target {
+        payload.data.insert("paneTarget", Value::String(target.clone()));
+    }
+
+    if !device_token.device_id.is_empty() {
+        payload.data.insert("deviceId", Value::String(device_token.device_id.clone()));
+    }
+    payload
+        .data
+        .insert("notificationId", Value::String(job.notification_id.clone()));
+    if let Some(queued_at) = notification.queued_at {
+        payload.data.insert("queuedAt", Value::String(queued_at.to_rfc3339()));
+    }
+
+    serde_json::to_string(&payload).map_err(|e| e.to_string())
+}
+
+impl DeliveryOutcome {
+    fn new(device_token: &DeviceToken, status: DeliveryStatus, reason: Option<String>, attempts: u32) -> Self {
+        Self {
+            device_id: device_token.device_id.clone(),
+            token: format!("{}...", token_prefix(&device_token.token)),
+            sandbox: device_token.sandbox,
+            status,
+            reason,
+            attempts,
+            full_token: device_token.token.clone(),
+        }
+    }
+}
+
+/// Delivers to one device, retrying transient failures. When the first
+/// attempt fails transiently, a [`DeliveryStatus::Pending`] outcome goes to
+/// `first` before retrying, so the caller needn't wait for the retries.
+async fn deliver(
+    sender: Sender,
+    job: DeliveryJob,
+    policy: RetryPolicy,
+    first: &mut Option<oneshot::Sender<DeliveryOutcome>>,
+) -> DeliveryOutcome {
+    let device_token = &job.device_token;
+    let environment = if device_token.sandbox { "sandbox" } else { "production" };
+    let mut attempt = 0;
+    // Label for the outcome metric: like `reason`, but without free-form
+    // error text that would make every failure its own series.
+    let mut metric_reason = String::new();
+
+    let (status, reason) = loop {
+        attempt += 1;
+
+        if job.require_encryption && device_token.notification_key.is_none() {
+            tracing::warn!(
+                "Not sending to token {}...: it has no notification key and encryption is required",
+                token_prefix(&device_token.token)
+            );
+            metric_reason = "NotEncrypted".to_string();
+            break (DeliveryStatus::Failed, Some("NotEncrypted".to_string()));
+        }
+
+        let body = match payload_json(&job, &job.notification.body) {
+            // Long titles or custom data can still push the payload over the
+            // limit; shorten the body by the excess. Encrypted, it's base64,
+            // which only shrinks in whole 3-byte groups.
+            Ok(body) if body.len() > MAX_PAYLOAD_BYTES => {
+                let mut excess = body.len() - MAX_PAYLOAD_BYTES;
+                if device_token.notification_key.is_some() {
+                    excess += 3;
+                }
+                let budget = job.notification.body.len().saturating_sub(excess + summary::ELLIPSIS.len());
+                payload_json(&job, &summary::truncate(&job.notification.body, budget))
+            }
+            other => other,
+        };
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => {
+                metric_reason = "Payload".to_string();
+                break (DeliveryStatus::Failed, Some(e));
+            }
+        };
+        let error = match sender.send(device_token, body).await {
+            Ok(apns_id) => {
+                tracing::info!("APNs notification sent ({}): apns-id {:?}", environment, apns_id);
+                break (DeliveryStatus::Delivered, None);
+            }
+            Err(e) => e,
+        };
+
+        let reason = failure_reason(&error);
+        metric_reason = match error {
+            SendError::Rejected { .. } => reason.clone(),
+            SendError::Transport(_) => "Transport".to_string(),
+            SendError::Signing(_) => "Signing".to_string(),
+        };
+        match classify_error(&error) {
+            FailureKind::Terminal => {
+                tracing::warn!(
+                    "Removing invalid token: {}... (sandbox: {}, reason: {})",
+                    token_prefix(&device_token.token),
+                    device_token.sandbox,
+                    reason
+                );
+                break (DeliveryStatus::Removed, Some(reason));
+            }
+            FailureKind::Transient if attempt < policy.max_attempts => {
+                if let Some(first) = first.take() {
+                    let pending = DeliveryOutcome::new(device_token, DeliveryStatus::Pending, Some(reason.clone()), attempt);
+                    let _ = first.send(pending);
+                }
+                let delay = policy.backoff(attempt);
+                tracing::warn!(
+                    "APNs transient error for token {}... ({}), retrying in {:?} (attempt {}/{})",
+                    token_prefix(&device_token.token),
+                    reason,
+                    delay,
+                    attempt,
+                    policy.max_attempts
+                );
+                tokio::time::sleep(delay).await;
+            }
+            _ => {
+                tracing::error!(
+                    "APNs error for token {}... after {} attempt(s): {}",
+                    token_prefix(&device_token.token),
+                    attempt,
+                    error
+                );
+                break (DeliveryStatus::Failed, Some(reason));
+            }
+        }
+    };
+
+    let outcome = match status {
+        DeliveryStatus::Delivered => "delivered",
+        DeliveryStatus::Removed => "removed",
+        DeliveryStatus::Failed | DeliveryStatus::Pending => "failed",
+    };
+    metrics::counter!("reattachd_apns_deliveries_total", "outcome" => outcome, "reason" => metric_reason, "environment" => environment)
+        .increment(1);
+    DeliveryOutcome::new(device_token, status, reason, attempt)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use apns_mock::MockApns;
+
+    fn rejected(status: u16, reason: Option<&str>) -> SendError {
+        SendError::Rejected {
+            status,
+            reason: reason.map(str::to_string),
+        }
+    }
+
+    pub(crate) fn test_key_pem() -> String {
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::nid::Nid;
+        use openssl::pkey::PKey;
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("P-256 group");
+        let key = PKey::from_ec_key(EcKey::generate(&group).expect("generate key")).expect("pkey");
+        String::from_utf8(key.private_key_to_pem_pkcs8().expect("pem")).expect("utf-8")
+    }
+
+    pub(crate) async fn service_for(sandbox: &MockApns, production: &MockApns) -> (ApnsService, tempfile::TempDir) {
+        let temp = tempfile::tempdir().unwrap();
+        let config = ApnsConfig {
+            upstream: Upstream::Direct {
+                key: test_key_pem(),
+                key_id: "KEYID12345".to_string(),
+                team_id: "TEAMID1234".to_string(),
+                bundle_id: "com.example.reattach".to_string(),
+                sandbox_url: sandbox.base_url(),
+                production_url: production.base_url(),
+            },
+            data_dir: temp.path().to_path_buf(),
+            retry_policy: RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(5),
+            },
+            body_max_bytes: DEFAULT_BODY_BYTES,
+            require_encryption: false,
+        };
+        let service = ApnsService::new(config).await.expect("service starts");
+        (service, temp)
+    }
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        let policy = RetryPolicy {
+            max_attempts: 6,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(3),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+        assert_eq!(policy.backoff(4), Duration::from_secs(3));
+        assert_eq!(policy.backoff(40), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn classify_error_removes_tokens_on_terminal_reasons() {
+        for reason in ["BadDeviceToken", "Unregistered", "ExpiredToken", "DeviceTokenNotForTopic"] {
+            assert_eq!(classify_error(&rejected(400, Some(reason))), FailureKind::Terminal);
+        }
+        let error = rejected(410, None);
+        assert_eq!(classify_error(&error), FailureKind::Terminal);
+        assert_eq!(failure_reason(&error), "HTTP 410");
+    }
+
+    #[test]
+    fn classify_error_retries_transient_failures() {
+        assert_eq!(classify_error(&rejected(429, Some("TooManyRequests"))), FailureKind::Transient);
+        assert_eq!(classify_error(&rejected(503, None)), FailureKind::Transient);
+        assert_eq!(
+            classify_error(&SendError::Transport("timed out".to_string())),
+            FailureKind::Transient
+        );
+    }
+
+    #[test]
+    fn classify_error_gives_up_on_permanent_failures() {
+        let error = rejected(413, Some("PayloadTooLarge"));
+        assert_eq!(classify_error(&error), FailureKind::Permanent);
+        assert_eq!(failure_reason(&error), "PayloadTooLarge");
+    }
+
+    #[test]
+    fn notification_title_keeps_tail_when_too_long() {
+        assert_eq!(notification_title("", "dev:0 · project"), "dev:0 · project");
+        assert_eq!(notification_title("mac", "dev:0"), "mac: dev:0");
+        let title = notification_title("mac", "a-very-long-session-name:12 · some-project-dir");
+        assert_eq!(title.chars().count(), 40);
+        assert!(title.starts_with("mac: ..."));
+        assert!(title.ends_with("some-project-dir"));
+    }
+
+    #[tokio::test]
+    async fn routes_tokens_to_sandbox_and_production() {
+        let sandbox = MockApns::start().await.expect("mock starts");
+        let production = MockApns::start().await.expect("mock starts");
+        let (service, _temp) = service_for(&sandbox, &production).await;
+        service
+            .register_device("sandbox-token".into(), true, "dev-1".into(), String::new(), None)
+            .await;
+        service
+            .register_device("production-token".into(), false, "dev-2".into(), String::new(), None)
+            .await;
+
+        let notification = Notification {
+            subtitle: Some("Permission needed".into()),
+            category: Some("AGENT_PERMISSION".into()),
+            pane_target: Some("dev:0.1".into()),
+            ..Notification::new("dev:0 · app", "Waiting for input")
+        };
+        let outcomes = service
+            .send_notification(&notification)
+            .await
+            .expect("send succeeds");
+        assert!(outcomes.iter().all(|o| o.status == DeliveryStatus::Delivered));
+
+        let sandbox_requests = sandbox.requests();
+        let production_requests = production.requests();
+        assert_eq!(sandbox_requests.len(), 1);
+        assert_eq!(production_requests.len(), 1);
+        assert_eq!(sandbox_requests[0].device_token, "sandbox-token");
+        assert_eq!(production_requests[0].device_token, "production-token");
+
+        let request = &sandbox_requests[0];
+        assert_eq!(request.topic.as_deref(), Some("com.example.reattach"));
+        assert!(request
+            .authorization
+            .as_deref()
+            .is_some_and(|a| a.starts_with("Bearer ")));
+        assert_eq!(request.payload["aps"]["alert"]["body"], "Waiting for input");
+        assert_eq!(request.payload["aps"]["alert"]["subtitle"], "Permission needed");
+        assert_eq!(request.payload["aps"]["category"], "AGENT_PERMISSION");
+        assert_eq!(request.payload["paneTarget"], "dev:0.1");
+        assert_eq!(request.payload["deviceId"], "dev-1");
+    }
+
+    #[tokio::test]
+    async fn encrypts_pushes_to_devices_with_a_notification_key() {
+        let sandbox = MockApns::start().await.expect("mock starts");
+        let production = MockApns::start().await.expect("mock starts");
+        let (service, _temp) = service_for(&sandbox, &production).await;
+        let (private_key, public_key) = encryption::tests::device_key();
+        service
+            .register_device("sealed".into(), false, "dev-1".into(), "mac".into(), Some(public_key))
+            .await;
+        service
+            .register_device("plain".into(), false, "dev-2".into(), "mac".into(), None)
+            .await;
+
+        let notification = Notification {
+            subtitle: Some("Permission needed".into()),
+            category: Some("AGENT_PERMISSION".into()),
+            pane_target: Some("dev:0.1".into()),
+            ..Notification::new("dev:0 · app", "Delete the build directory?")
+        };
+        service.send_notification(&notification).await.expect("send succeeds");
+
+        let requests = production.requests();
+        let sealed = requests.iter().find(|r| r.device_token == "sealed").expect("sealed push");
+        let payload = &sealed.payload;
+        assert_eq!(payload["aps"]["mutable-content"], 1);
+        assert_eq!(payload["aps"]["alert"]["title"], "mac");
+        assert_eq!(payload["aps"]["alert"]["body"], ENCRYPTED_PLACEHOLDER_BODY);
+        assert_eq!(payload["aps"]["category"], "AGENT_PERMISSION");
+        assert!(payload.get("paneTarget").is_none());
+        assert!(!payload.to_string().contains("build directory"));
+
+        let id = payload["notificationId"].as_str().expect("id present");
+        let content = encryption::tests::open(&private_key, &payload["encrypted"], id.as_bytes()).expect("opens");
+        let content: Value = serde_json::from_slice(&content).expect("JSON content");
+        assert_eq!(content["title"], "mac: dev:0 · app");
+        assert_eq!(content["subtitle"], "Permission needed");
+        assert_eq!(content["body"], "Delete the build directory?");
+        assert_eq!(content["paneTarget"], "dev:0.1");
+
+        let plain = requests.iter().find(|r| r.device_token == "plain").expect("plain push");
+        assert_eq!(plain.payload["aps"]["alert"]["body"], "Delete the build directory?");
+
+        // With encryption required, the device without a key gets nothing.
+        service.reload(DEFAULT_BODY_BYTES, true).await.expect("reloads");
+        let outcomes = service.send_notification(&notification).await.expect("send succeeds");
+        let plain = outcomes.iter().find(|o| o.device_id == "dev-2").expect("plain outcome");
+        assert_eq!(plain.status, DeliveryStatus::Failed);
+        assert_eq!(plain.reason.as_deref(), Some("NotEncrypted"));
+        assert_eq!(production.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn truncates_title_with_server_name() {
+        let sandbox = MockApns::start().await.expect("mock starts");
+        let production = MockApns::start().await.expect("mock starts");
+        let (service, _temp) = service_for(&sandbox, &production).await;
+        service
+            .register_device("token".into(), false, String::new(), "build-host".into(), None)
+            .await;
+
+        service
+            .send_notification(&Notification::new("a-very-long-session-name:3 · reattach", "done"))
+            .await
+            .expect("send succeeds");
+
+        let requests = production.requests();
+        let title = requests[0].payload["aps"]["alert"]["title"]
+            .as_str()
+            .expect("title present")
+            .to_string();
+        assert!(title.chars().count() <= 40);
+        assert!(title.starts_with("build-host: ..."));
+        assert!(title.ends_with("· reattach"));
+    }
+
+    #[tokio::test]
+    async fn summarizes_body_and_keeps_full_text_in_history() {
+        let sandbox = MockApns::start().await.expect("mock starts");
+        let production = MockApns::start().await.expect("mock starts");
+        let (service, _temp) = service_for(&sandbox, &production).await;
+        service
+            .register_device("token".into(), false, String::new(), String::new(), None)
+            .await;
+
+        let body = format!(
+            "## Done\n\n{}\n\n```\ncargo test\n```\n\nShould I **open a PR**?",
+            "Refactored the parser and updated every call site. ".repeat(40)
+        );
+        service
+            .send_notification(&Notification::new("dev:0", body.clone()))
+            .await
+            .expect("send succeeds");
+
+        let requests = production.requests();
+        let pushed = requests[0].payload["aps"]["alert"]["body"].as_str().expect("body present");
+        assert!(pushed.len() <= DEFAULT_BODY_BYTES);
+        assert!(pushed.ends_with("Should I open a PR?"), "{}", pushed);
+        assert!(!pushed.contains("##") && !pushed.contains("```"));
+
+        let id = requests[0].payload["notificationId"].as_str().expect("id present");
+        let entry = service.history_entry(id).await.expect("entry recorded");
+        assert_eq!(entry.body, body);
+        assert_eq!(entry.summary, pushed);
+        assert_eq!(service.history(10).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prunes_tokens_rejected_with_terminal_reasons() {
+        let sandbox = MockApns::start().await.expect("mock starts");
+        let production = MockApns::start().await.expect("mock starts");
+        let (service, temp) = service_for(&sandbox, &production).await;
+        for token in ["good", "unregistered", "expired", "bad"] {
+            service
+                .register_device(token.into(), false, String::new(), String::new(), None)
+                .await;
+        }
+        production.fail_token("unregistered", 410, "Unregistered");
+        production.fail_token("expired", 410, "ExpiredToken");
+        production.fail_token("bad", 400, "BadDeviceToken");
+
+        let outcomes = service
+            .send_notification(&Notification::new("title", "body"))
+            .await
+            .expect("send succeeds");
+        let removed = outcomes
+            .iter()
+            .filter(|o| o.status == DeliveryStatus::Removed)
+            .count();
+        assert_eq!(removed, 3);
+
+        let remaining = service.device_tokens.read().await.clone();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].token, "good");
+        let persisted = ApnsService::load_tokens(&temp.path().join("device_tokens.json")).expect("tokens saved");
+        assert_eq!(persisted.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures_with_backoff() {
+        let sandbox = MockApns::start().await.expect("mock starts");
+        let production = MockApns::start().await.expect("mock starts");
+        let (service, _temp) = service_for(&sandbox, &production).await;
+        service
+            .register_device("flaky".into(), false, String::new(), String::new(), None)
+            .await;
+        service
+            .register_device("down".into(), false, String::new(), String::new(), None)
+            .await;
+        production.fail_next("flaky", 2, 503, "ServiceUnavailable");
+        production.fail_token("down", 500, "InternalServerError");
+
+        // The answer comes after the first attempt; retries go on after it.
+        let outcomes = service
+            .send_notification(&Notification::new("title", "body"))
+            .await
+            .expect("send succeeds");
+        for outcome in &outcomes {
+            assert_eq!(outcome.status, DeliveryStatus::Pending);
+            assert_eq!(outcome.attempts, 1);
+        }
+        let down = outcomes.iter().find(|o| o.full_token == "down").expect("down outcome");
+        assert_eq!(down.reason.as_deref(), Some("InternalServerError"));
+
+        let attempts = |token: &str| {
+            production
+                .requests()
+                .into_iter()
+                .filter(|r| r.device_token == token)
+                .map(|r| r.status)
+                .collect::<Vec<_>>()
+        };
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while (attempts("flaky").len() < 3 || attempts("down").len() < 3) && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(attempts("flaky"), [503, 503, 200]);
+        assert_eq!(attempts("down"), [500, 500, 500]);
+        // Transient failures never prune the token.
+        assert_eq!(service.device_tokens.read().await.len(), 2);
+    }
+}
@@ -0,0 +1,346 @@
+//! Sending pushes through a relay (see `reattach-relay`) instead of talking
+//! to APNs with a key of our own. `reattachd apns pair` trades a one-time
+//! code from the relay's operator for an ID and secret, kept in `relay.json`
+//! in the data dir; every push is signed with the secret.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use apns_client::relay::{
+    self as protocol, PairRequest, PairResponse, PushRequest, PushResponse, DAEMON_HEADER, NONCE_HEADER,
+    SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+
+use super::{credentials, SendError};
+
+pub const PAIRING_FILE: &str = "relay.json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pairing {
+    pub url: String,
+    pub daemon_id: String,
+    pub secret: String,
+}
+
+impl Pairing {
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(PAIRING_FILE)
+    }
+
+    /// The saved pairing, or `None` if the daemon isn't paired.
+    pub fn load(data_dir: &Path) -> Result<Option<Self>, String> {
+        let path = Self::path(data_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = crate::fsutil::read_private(&path)?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, data_dir: &Path) -> Result<(), String> {
+        let content = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(data_dir).map_err(|e| format!("Failed to create {}: {}", data_dir.display(), e))?;
+        crate::fsutil::write_private(&Self::path(data_dir), &content)
+    }
+
+    /// Forgets the pairing. Returns false if there was none.
+    pub fn remove(data_dir: &Path) -> Result<bool, String> {
+        let path = Self::path(data_dir);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+}
+
+fn http_client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Checks that `url` is one we can trust with the pairing secret and
+/// notification content: https, or plain http to this machine.
+fn check_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid relay URL {}: {}", url, e))?;
+    // IPv6 hosts come bracketed, e.g. `[::1]`.
+    let loopback = parsed.host_str().is_some_and(|host| {
+        host.eq_ignore_ascii_case("localhost")
+            || host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    });
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(format!("The relay URL {} must use https (plain http is only allowed for localhost)", url)),
+    }
+}
+
+/// Redeems a pairing code with the relay at `url`.
+pub async fn pair(url: &str, code: &str, name: &str) -> Result<Pairing, String> {
+    let url = url.trim_end_matches('/').to_string();
+    check_url(&url)?;
+    let response = http_client()?
+        .post(format!("{}/v1/pair", url))
+        .json(&PairRequest {
+            code: code.to_string(),
+            name: name.to_string(),
+        })
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach the relay at {}: {}", url, e))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let reason = error_reason(response).await;
+        return Err(format!(
+            "The relay refused to pair (HTTP {}): {}",
+            status.as_u16(),
+            reason.as_deref().unwrap_or("no reason")
+        ));
+    }
+    let paired: PairResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid pairing response from {}: {}", url, e))?;
+    Ok(Pairing {
+        url,
+        daemon_id: paired.daemon_id,
+        secret: paired.secret,
+    })
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    reason: String,
+}
+
+async fn error_reason(response: reqwest::Response) -> Option<String> {
+    response.json::<ErrorBody>().await.ok().map(|body| body.reason)
+}
+
+/// Posts pushes to a relay. Its answers mirror APNs', so callers treat
+/// failures the same way.
+#[derive(Clone)]
+pub struct RelayClient {
+    http: reqwest::Client,
+    pairing: Arc<Pairing>,
+}
+
+impl RelayClient {
+    pub fn new(pairing: Pairing) -> Result<Self, SendError> {
+        check_url(&pairing.url).map_err(SendError::Transport)?;
+        Ok(Self {
+            http: http_client().map_err(SendError::Transport)?,
+            pairing: Arc::new(pairing),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.pairing.url
+    }
+
+    /// Sends a JSON payload to a device and returns the `apns-id` on success.
+    pub async fn send(&self, device_token: &str, sandbox: bool, payload: String) -> Result<Option<String>, SendError> {
+        let body = serde_json::to_vec(&PushRequest {
+            token: device_token.to_string(),
+            sandbox,
+            payload,
+        })
+        .map_err(|e| SendError::Signing(e.to_string()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let nonce = protocol::generate_nonce();
+        let signature =
+            protocol::sign(&self.pairing.secret, timestamp, &nonce, &body).map_err(SendError::Signing)?;
+
+        let response = self
+            .http
+            .post(format!("{}/v1/push", self.pairing.url))
+            .header(DAEMON_HEADER, &self.pairing.daemon_id)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(NONCE_HEADER, nonce)
+            .header(SIGNATURE_HEADER, signature)
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| SendError::Transport(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json::<PushResponse>().await.ok().and_then(|r| r.apns_id));
+        }
+        Err(SendError::Rejected {
+            status: status.as_u16(),
+            reason: error_reason(response).await,
+        })
+    }
+}
+
+/// Asks the relay whether it knows us and APNs accepts its key, by sending
+/// to a token that can't exist.
+pub async fn verify(pairing: &Pairing, sandbox: bool) -> Result<(), String> {
+    let client = RelayClient::new(pairing.clone()).map_err(|e| e.to_string())?;
+    let result = client.send(credentials::PROBE_TOKEN, sandbox, credentials::probe_payload()).await;
+    credentials::probe_accepted(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ApnsConfig, ApnsService, DeliveryStatus, Notification, RetryPolicy, Upstream, DEFAULT_BODY_BYTES};
+    use super::*;
+    use apns_mock::MockApns;
+    use reattach_relay::{Relay, RelayConfig};
+
+    async fn start_relay(apns: &MockApns, data_dir: &Path) -> String {
+        let relay = Relay::new(RelayConfig {
+            data_dir: data_dir.to_path_buf(),
+            key: super::super::tests::test_key_pem(),
+            key_id: "RELAYKEY01".to_string(),
+            team_id: "RELAYTEAM1".to_string(),
+            bundle_id: "com.example.relayed".to_string(),
+            sandbox_url: apns.base_url(),
+            production_url: apns.base_url(),
+        })
+        .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, Arc::new(relay).router()).await;
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn pairs_with_relay_and_sends_through_it() {
+        let apns = MockApns::start().await.unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let base = temp.path();
+        let relay_dir = base.join("relay");
+        let data_dir = base.join("daemon");
+        let url = start_relay(&apns, &relay_dir).await;
+
+        let error = pair(&url, "NOPE-NOPE", "build-host").await.unwrap_err();
+        assert!(error.contains(protocol::REASON_INVALID_PAIRING_CODE), "{}", error);
+        let code = reattach_relay::create_pairing_code(&relay_dir, chrono::Duration::minutes(10)).unwrap();
+        let pairing = pair(&format!("{}/", url), &code.to_lowercase(), "build-host").await.unwrap();
+        assert_eq!(pairing.url, url);
+        // Codes are single-use.
+        assert!(pair(&url, &code, "other-host").await.is_err());
+        pairing.save(&data_dir).unwrap();
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(Pairing::path(&data_dir)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let pairing = Pairing::load(&data_dir).unwrap().unwrap();
+
+        let service = ApnsService::new(ApnsConfig {
+            upstream: Upstream::Relay(pairing.clone()),
+            data_dir: data_dir.clone(),
+            retry_policy: RetryPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(5),
+            },
+            body_max_bytes: DEFAULT_BODY_BYTES,
+            require_encryption: false,
+        })
+        .await
+        .unwrap();
+        service.register_device("device-token".into(), true, "dev-1".into(), String::new(), None).await;
+        service.register_device("gone-token".into(), false, "dev-2".into(), String::new(), None).await;
+        apns.fail_token("gone-token", 410, "Unregistered");
+
+        let outcomes = service
+            .send_notification(&Notification::new("dev:0", "Waiting for input"))
+            .await
+            .unwrap();
+        let delivered = outcomes.iter().find(|o| o.device_id == "dev-1").unwrap();
+        assert_eq!(delivered.status, DeliveryStatus::Delivered);
+        // APNs' rejections come back through the relay and prune the token.
+        let gone = outcomes.iter().find(|o| o.device_id == "dev-2").unwrap();
+        assert_eq!(gone.status, DeliveryStatus::Removed);
+        assert_eq!(service.token_count().await, 1);
+
+        let request = apns.requests().into_iter().find(|r| r.device_token == "device-token").unwrap();
+        assert_eq!(request.topic.as_deref(), Some("com.example.relayed"));
+        assert_eq!(request.payload["aps"]["alert"]["body"], "Waiting for input");
+        assert_eq!(request.payload["deviceId"], "dev-1");
+
+        let forged = RelayClient::new(Pairing {
+            secret: protocol::generate_secret(),
+            ..pairing.clone()
+        })
+        .unwrap();
+        let error = forged.send("device-token", true, "{}".to_string()).await.unwrap_err();
+        assert!(error.to_string().contains(protocol::REASON_BAD_SIGNATURE), "{}", error);
+
+        // A captured request can't be sent again.
+        let body = serde_json::to_vec(&PushRequest {
+            token: "device-token".to_string(),
+            sandbox: true,
+            payload: "{}".to_string(),
+        })
+        .unwrap();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let nonce = protocol::generate_nonce();
+        let signature = protocol::sign(&pairing.secret, timestamp, &nonce, &body).unwrap();
+        let send = |url: &str| {
+            reqwest::Client::new()
+                .post(format!("{}/v1/push", url))
+                .header(DAEMON_HEADER, &pairing.daemon_id)
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(NONCE_HEADER, &nonce)
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+        };
+        assert!(send(&url).await.unwrap().status().is_success());
+        let replayed = send(&url).await.unwrap();
+        assert_eq!(replayed.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert_eq!(error_reason(replayed).await.as_deref(), Some(protocol::REASON_REPLAYED_REQUEST));
+        // Nor after the relay restarts.
+        let restarted = start_relay(&apns, &relay_dir).await;
+        let replayed = send(&restarted).await.unwrap();
+        assert_eq!(error_reason(replayed).await.as_deref(), Some(protocol::REASON_REPLAYED_REQUEST));
+
+        assert!(reattach_relay::revoke(&relay_dir, &pairing.daemon_id).unwrap());
+        let outcomes = service
+            .send_notification(&Notification::new("dev:0", "Still waiting"))
+            .await
+            .unwrap();
+        assert_eq!(outcomes[0].status, DeliveryStatus::Failed);
+        assert_eq!(outcomes[0].reason.as_deref(), Some(protocol::REASON_UNKNOWN_DAEMON));
+        assert_eq!(outcomes[0].attempts, 1);
+    }
+
+    #[test]
+    fn requires_https_except_for_loopback() {
+        assert!(check_url("https://relay.example.com").is_ok());
+        for url in ["http://localhost:8790", "http://127.0.0.1:8790", "http://[::1]:8790"] {
+            assert!(check_url(url).is_ok(), "{}", url);
+        }
+        for url in ["http://relay.example.com", "http://192.168.1.20:8790", "ftp://localhost", "relay.example.com"] {
+            assert!(check_url(url).is_err(), "{}", url);
+        }
+        let pairing = Pairing {
+            url: "http://relay.example.com".to_string(),
+            daemon_id: "d-1".to_string(),
+            secret: protocol::generate_secret(),
+        };
+        assert!(RelayClient::new(pairing).is_err());
+    }
+}
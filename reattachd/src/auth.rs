@@ -5,6 +5,8 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::mtls::ClientIdentity;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Device {
     pub id: String,
@@ -12,6 +14,13 @@ pub struct Device {
     pub token: String,
     pub registered_at: DateTime<Utc>,
     pub last_seen_at: Option<DateTime<Utc>>,
+    /// SHA-256 fingerprint of the client certificate issued with `--mtls`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert_sha256: Option<String>,
+    /// Public key the device's pushes are encrypted to; see
+    /// [`crate::apns::encryption`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notification_key: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +52,7 @@ pub enum SetupTokenValidation {
 pub struct AuthService {
     store: RwLock<AuthStore>,
     data_path: PathBuf,
+    client_certs: bool,
 }
 
 impl AuthService {
@@ -60,9 +70,20 @@ impl AuthService {
         Ok(Self {
             store: RwLock::new(store),
             data_path,
+            client_certs: false,
         })
     }
 
+    /// Identifies devices by client certificate rather than bearer token.
+    pub fn with_client_certs(mut self) -> Self {
+        self.client_certs = true;
+        self
+    }
+
+    pub fn requires_client_certs(&self) -> bool {
+        self.client_certs
+    }
+
     async fn save(&self) -> Result<(), std::io::Error> {
         let store = self.store.read().await;
         let content = serde_json::to_string_pretty(&*store)?;
@@ -94,7 +115,7 @@ impl AuthService {
 
     pub async fn validate_setup_token(&self, token: &str) -> SetupTokenValidation {
         // Reload from disk to pick up setup tokens created by `reattachd setup`
-        self.reload().await;
+        let _ = self.reload().await;
 
         let store = self.store.read().await;
         if let Some(setup_token) = &store.setup_token {
@@ -112,21 +133,25 @@ impl AuthService {
         }
     }
 
-    async fn reload(&self) {
-        if self.data_path.exists() {
-            if let Ok(content) = std::fs::read_to_string(&self.data_path) {
-                if let Ok(new_store) = serde_json::from_str::<AuthStore>(&content) {
-                    let mut store = self.store.write().await;
-                    *store = new_store;
-                }
-            }
+    /// Replaces the in-memory store with `auth.json`. A missing or unreadable
+    /// file leaves the current store in place.
+    pub async fn reload(&self) -> Result<(), String> {
+        if !self.data_path.exists() {
+            return Ok(());
         }
+        let content = std::fs::read_to_string(&self.data_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.data_path.display(), e))?;
+        let new_store = serde_json::from_str::<AuthStore>(&content)
+            .map_err(|e| format!("Invalid {}: {}", self.data_path.display(), e))?;
+        *self.store.write().await = new_store;
+        Ok(())
     }
 
     pub async fn register_device(
         &self,
         setup_token: &str,
         device_name: &str,
+        notification_key: Option<String>,
     ) -> Result<Device, SetupTokenValidation> {
         match self.validate_setup_token(setup_token).await {
             SetupTokenValidation::Valid => {}
@@ -139,6 +164,8 @@ impl AuthService {
             token: generate_token(),
             registered_at: Utc::now(),
             last_seen_at: None,
+            client_cert_sha256: None,
+            notification_key,
         };
 
         {
@@ -160,6 +187,33 @@ impl AuthService {
         store.devices.iter().find(|d| d.token == token).cloned()
     }
 
+    /// Issues a client certificate for a registered device and records its
+    /// fingerprint as the device's identity.
+    pub async fn issue_client_identity(&self, device_id: &str, device_name: &str) -> Result<ClientIdentity, String> {
+        let data_dir = self.data_path.parent().unwrap_or(std::path::Path::new("."));
+        let identity = crate::mtls::issue_client_identity(data_dir, device_id, device_name)?;
+        {
+            let mut store = self.store.write().await;
+            let device = store
+                .devices
+                .iter_mut()
+                .find(|d| d.id == device_id)
+                .ok_or_else(|| format!("Device {} is not registered", device_id))?;
+            device.client_cert_sha256 = Some(identity.fingerprint.clone());
+        }
+        self.save().await.map_err(|e| e.to_string())?;
+        Ok(identity)
+    }
+
+    pub async fn validate_client_cert(&self, fingerprint: &str) -> Option<Device> {
+        let store = self.store.read().await;
+        store
+            .devices
+            .iter()
+            .find(|d| d.client_cert_sha256.as_deref() == Some(fingerprint))
+            .cloned()
+    }
+
     pub async fn update_last_seen(&self, device_id: &str) {
         {
             let mut store = self.store.write().await;
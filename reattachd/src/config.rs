@@ -0,0 +1,785 @@
+//! Daemon settings. Each one comes from, in order of precedence, a
+//! command-line flag, an environment variable, `config.toml` in the data dir
+//! or its default:
+//!
+//! ```toml
+//! [server]
+//! port = 8787                       # REATTACHD_PORT, PORT
+//! bind_addr = "127.0.0.1"           # REATTACHD_BIND_ADDR
+//! listen = ["https://100.64.0.1:8787", "http://127.0.0.1:8787"]  # --listen, REATTACHD_LISTEN
+//! metrics_addr = "127.0.0.1:9187"   # --metrics-addr, REATTACHD_METRICS_ADDR
+//! drain_timeout_secs = 10           # REATTACHD_DRAIN_TIMEOUT_SECS
+//!
+//! [tls]
+//! tailscale = false                 # --tailscale
+//! self_signed = false               # --self-signed
+//! cert = "/etc/reattachd/cert.pem"  # --tls-cert, REATTACHD_TLS_CERT
+//! key = "/etc/reattachd/key.pem"    # --tls-key, REATTACHD_TLS_KEY
+//! mtls = false                      # --mtls
+//!
+//! [setup]
+//! expires = "10m"                   # REATTACHD_SETUP_EXPIRES
+//!
+//! [tmux]
+//! sockets = ["work", "ci=/tmp/ci.sock"]  # REATTACHD_TMUX_SOCKETS
+//!
+//! [output]
+//! max_lines = 1000                  # REATTACHD_OUTPUT_MAX_LINES
+//!
+//! [notifications]
+//! max_body_bytes = 1024             # REATTACHD_NOTIFY_MAX_BYTES
+//! require_encryption = false        # REATTACHD_NOTIFY_REQUIRE_ENCRYPTION
+//!
+//! [apns]
+//! key_id = "ABC123DEFG"             # APNS_KEY_ID
+//! team_id = "TEAM123456"            # APNS_TEAM_ID
+//! bundle_id = "com.example.app"     # APNS_BUNDLE_ID
+//! key_file = "apns_key.p8"          # APNS_KEY_FILE
+//! base_url = "http://127.0.0.1:8443"  # APNS_BASE_URL
+//! sandbox_url = "https://api.sandbox.push.apple.com"  # APNS_SANDBOX_URL
+//! production_url = "https://api.push.apple.com"       # APNS_PRODUCTION_URL
+//! ```
+//!
+//! Relative paths in the file are relative to the data dir. On SIGHUP the
+//! daemon re-reads the settings; those in [`RELOADABLE`] apply right away and
+//! the others at the next restart.
+
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml_edit::{Array, Value};
+
+use crate::apns;
+use crate::listen::Listener;
+use crate::tmux;
+
+pub const FILE_NAME: &str = "config.toml";
+pub const DEFAULT_PORT: u16 = 8787;
+const DEFAULT_SETUP_EXPIRES: &str = "10m";
+const DEFAULT_OUTPUT_MAX_LINES: u32 = 1000;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 10;
+
+/// Settings a running daemon applies when it reloads its configuration.
+/// `setup.expires` only matters when a setup token is created.
+pub const RELOADABLE: &[&str] = &[
+    "output.max_lines",
+    "notifications.max_body_bytes",
+    "notifications.require_encryption",
+    "setup.expires",
+];
+
+pub fn path(data_dir: &Path) -> PathBuf {
+    data_dir.join(FILE_NAME)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub server: ServerSection,
+    pub tls: TlsSection,
+    pub setup: SetupSection,
+    pub tmux: TmuxSection,
+    pub output: OutputSection,
+    pub notifications: NotificationsSection,
+    pub apns: ApnsSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub port: Option<u16>,
+    pub bind_addr: Option<String>,
+    pub listen: Option<Vec<String>>,
+    pub metrics_addr: Option<String>,
+    pub drain_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    pub tailscale: Option<bool>,
+    pub self_signed: Option<bool>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub mtls: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SetupSection {
+    pub expires: Option<String>,
+}
+
+/// Extra tmux servers, as `ID=SOCKET` or `SOCKET`; see [`tmux::Server::parse`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TmuxSection {
+    pub sockets: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputSection {
+    pub max_lines: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsSection {
+    pub max_body_bytes: Option<usize>,
+    pub require_encryption: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApnsSection {
+    pub key_id: Option<String>,
+    pub team_id: Option<String>,
+    pub bundle_id: Option<String>,
+    pub key_file: Option<String>,
+    pub base_url: Option<String>,
+    pub sandbox_url: Option<String>,
+    pub production_url: Option<String>,
+}
+
+/// Settings given as command-line flags; `None` or `false` if not given.
+#[derive(Debug, Default, Clone)]
+pub struct Flags {
+    pub tailscale: bool,
+    pub self_signed: bool,
+    pub mtls: bool,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub listen: Vec<String>,
+    pub metrics_addr: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Flag(&'static str),
+    Env(&'static str),
+    File,
+    Default,
+}
+
+/// A resolved setting for `reattachd config show`; `value` is `None` for
+/// optional settings that are not set.
+#[derive(Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    pub value: Option<Value>,
+    pub source: Source,
+}
+
+#[derive(Debug)]
+pub struct ApnsSettings {
+    pub key_id: Option<String>,
+    pub team_id: Option<String>,
+    pub bundle_id: Option<String>,
+    /// See [`apns::credentials`] for where else the key can come from.
+    pub key_file: PathBuf,
+    pub sandbox_url: String,
+    pub production_url: String,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub port: u16,
+    pub bind_addr: Option<String>,
+    pub listen: Vec<String>,
+    pub metrics_addr: Option<String>,
+    pub drain_timeout: std::time::Duration,
+    pub tailscale: bool,
+    pub self_signed: bool,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub mtls: bool,
+    pub setup_expires: String,
+    /// The default tmux server and those in `tmux.sockets`.
+    pub tmux_servers: tmux::Servers,
+    pub output_max_lines: u32,
+    pub notify_max_bytes: usize,
+    /// Only send pushes to devices that registered a notification key.
+    pub notify_require_encryption: bool,
+    pub apns: ApnsSettings,
+    pub file: PathBuf,
+    pub settings: Vec<Setting>,
+    flags: Flags,
+}
+
+/// Conversion to a TOML value for `config show`.
+trait Show {
+    fn show(&self) -> Value;
+}
+
+impl Show for String {
+    fn show(&self) -> Value {
+        Value::from(self.as_str())
+    }
+}
+
+impl Show for bool {
+    fn show(&self) -> Value {
+        Value::from(*self)
+    }
+}
+
+impl Show for u16 {
+    fn show(&self) -> Value {
+        Value::from(i64::from(*self))
+    }
+}
+
+impl Show for u32 {
+    fn show(&self) -> Value {
+        Value::from(i64::from(*self))
+    }
+}
+
+impl Show for u64 {
+    fn show(&self) -> Value {
+        Value::from(*self as i64)
+    }
+}
+
+impl Show for usize {
+    fn show(&self) -> Value {
+        Value::from(*self as i64)
+    }
+}
+
+impl Show for Vec<String> {
+    fn show(&self) -> Value {
+        Value::Array(self.iter().map(String::as_str).collect::<Array>())
+    }
+}
+
+struct Resolver<'a> {
+    env: &'a dyn Fn(&str) -> Option<String>,
+    file: &'a Path,
+    settings: Vec<Setting>,
+    errors: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn describe(&self, source: &Source) -> String {
+        match source {
+            Source::Flag(flag) => flag.to_string(),
+            Source::Env(name) => format!("env {}", name),
+            Source::File => self.file.display().to_string(),
+            Source::Default => "default".to_string(),
+        }
+    }
+
+    /// The flag if given, else the first environment variable that is set,
+    /// else the file's value.
+    fn pick<T>(
+        &mut self,
+        key: &'static str,
+        flag: Option<(&'static str, T)>,
+        envs: &[&'static str],
+        file: Option<T>,
+    ) -> Option<(T, Source)>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        if let Some((name, value)) = flag {
+            return Some((value, Source::Flag(name)));
+        }
+        for &name in envs {
+            if let Some(raw) = (self.env)(name) {
+                match raw.parse() {
+                    Ok(value) => return Some((value, Source::Env(name))),
+                    Err(e) => {
+                        self.errors.push(format!("{} (env {}): invalid value '{}': {}", key, name, raw, e));
+                        return None;
+                    }
+                }
+            }
+        }
+        file.map(|value| (value, Source::File))
+    }
+
+    fn required<T: Show>(&mut self, key: &'static str, picked: Option<(T, Source)>, default: T) -> T {
+        let (value, source) = picked.unwrap_or((default, Source::Default));
+        self.settings.push(Setting {
+            key,
+            value: Some(value.show()),
+            source,
+        });
+        value
+    }
+
+    fn optional<T: Show>(&mut self, key: &'static str, picked: Option<(T, Source)>) -> Option<T> {
+        let (value, source) = match picked {
+            Some((value, source)) => (Some(value), source),
+            None => (None, Source::Default),
+        };
+        self.settings.push(Setting {
+            key,
+            value: value.as_ref().map(Show::show),
+            source,
+        });
+        value
+    }
+
+    fn invalid(&mut self, key: &str, message: impl fmt::Display) {
+        let source = self.settings.iter().find(|setting| setting.key == key);
+        match source {
+            Some(setting) => {
+                let source = self.describe(&setting.source);
+                self.errors.push(format!("{} ({}): {}", key, source, message));
+            }
+            None => self.errors.push(format!("{}: {}", key, message)),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `config.toml` from the data dir and resolves it against the
+    /// process environment and `flags`. Errors are one message per problem.
+    pub fn load(data_dir: &Path, flags: Flags) -> Result<Config, Vec<String>> {
+        let file = path(data_dir);
+        let file_config = match std::fs::read_to_string(&file) {
+            Ok(content) => toml_edit::de::from_str(&content)
+                .map_err(|e| vec![format!("{}: {}", file.display(), e.to_string().trim_end())])?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileConfig::default(),
+            Err(e) => return Err(vec![format!("Failed to read {}: {}", file.display(), e)]),
+        };
+        Self::resolve(flags, &|name| std::env::var(name).ok(), file_config, &file)
+    }
+
+    /// Loads the settings again with the same flags.
+    pub fn reload(&self) -> Result<Config, Vec<String>> {
+        let data_dir = self.file.parent().unwrap_or(Path::new("."));
+        Self::load(data_dir, self.flags.clone())
+    }
+
+    /// Keys whose value differs in `other`.
+    pub fn changed(&self, other: &Config) -> Vec<&'static str> {
+        let shown = |setting: &Setting| setting.value.as_ref().map(|value| value.to_string());
+        self.settings
+            .iter()
+            .zip(&other.settings)
+            .filter(|(old, new)| shown(old) != shown(new))
+            .map(|(old, _)| old.key)
+            .collect()
+    }
+
+    pub fn resolve(
+        flags: Flags,
+        env: &dyn Fn(&str) -> Option<String>,
+        file_config: FileConfig,
+        file: &Path,
+    ) -> Result<Config, Vec<String>> {
+        let retained_flags = flags.clone();
+        let mut r = Resolver {
+            env,
+            file,
+            settings: Vec::new(),
+            errors: Vec::new(),
+        };
+        let FileConfig {
+            server,
+            tls,
+            setup,
+            tmux: tmux_file,
+            output,
+            notifications,
+            apns: apns_file,
+        } = file_config;
+        let data_dir = file.parent().unwrap_or(Path::new("."));
+        let relative_to_data_dir = |path: String| data_dir.join(path).to_string_lossy().to_string();
+
+        let picked = r.pick("server.port", None, &["REATTACHD_PORT", "PORT"], server.port);
+        let port = r.required("server.port", picked, DEFAULT_PORT);
+        let picked = r.pick("server.bind_addr", None, &["REATTACHD_BIND_ADDR"], server.bind_addr);
+        let bind_addr = r.optional("server.bind_addr", picked);
+        let picked = if !flags.listen.is_empty() {
+            Some((flags.listen, Source::Flag("--listen")))
+        } else if let Some(specs) = env("REATTACHD_LISTEN") {
+            let specs = specs.split_whitespace().map(str::to_string).collect();
+            Some((specs, Source::Env("REATTACHD_LISTEN")))
+        } else {
+            server.listen.map(|listen| (listen, Source::File))
+        };
+        let listen = r.required("server.listen", picked, Vec::new());
+        let picked = r.pick(
+            "server.metrics_addr",
+            flags.metrics_addr.map(|addr| ("--metrics-addr", addr)),
+            &["REATTACHD_METRICS_ADDR"],
+            server.metrics_addr,
+        );
+        let metrics_addr = r.optional("server.metrics_addr", picked);
+        let picked = r.pick(
+            "server.drain_timeout_secs",
+            None,
+            &["REATTACHD_DRAIN_TIMEOUT_SECS"],
+            server.drain_timeout_secs,
+        );
+        let drain_timeout_secs = r.required("server.drain_timeout_secs", picked, DEFAULT_DRAIN_TIMEOUT_SECS);
+
+        let picked = r.pick("tls.tailscale", flags.tailscale.then_some(("--tailscale", true)), &[], tls.tailscale);
+        let tailscale = r.required("tls.tailscale", picked, false);
+        let picked = r.pick(
+            "tls.self_signed",
+            flags.self_signed.then_some(("--self-signed", true)),
+            &[],
+            tls.self_signed,
+        );
+        let self_signed = r.required("tls.self_signed", picked, false);
+        let picked = r.pick(
+            "tls.cert",
+            flags.tls_cert.map(|cert| ("--tls-cert", cert)),
+            &["REATTACHD_TLS_CERT"],
+            tls.cert.map(relative_to_data_dir),
+        );
+        let tls_cert = r.optional("tls.cert", picked);
+        let picked = r.pick(
+            "tls.key",
+            flags.tls_key.map(|key| ("--tls-key", key)),
+            &["REATTACHD_TLS_KEY"],
+            tls.key.map(relative_to_data_dir),
+        );
+        let tls_key = r.optional("tls.key", picked);
+        let picked = r.pick("tls.mtls", flags.mtls.then_some(("--mtls", true)), &[], tls.mtls);
+        let mtls = r.required("tls.mtls", picked, false);
+
+        let picked = r.pick("setup.expires", None, &["REATTACHD_SETUP_EXPIRES"], setup.expires);
+        let setup_expires = r.required("setup.expires", picked, DEFAULT_SETUP_EXPIRES.to_string());
+        let picked = match env("REATTACHD_TMUX_SOCKETS") {
+            Some(specs) => {
+                let specs = specs.split_whitespace().map(str::to_string).collect();
+                Some((specs, Source::Env("REATTACHD_TMUX_SOCKETS")))
+            }
+            None => tmux_file.sockets.map(|sockets| (sockets, Source::File)),
+        };
+        let tmux_sockets = r.required("tmux.sockets", picked, Vec::new());
+        let picked = r.pick("output.max_lines", None, &["REATTACHD_OUTPUT_MAX_LINES"], output.max_lines);
+        let output_max_lines = r.required("output.max_lines", picked, DEFAULT_OUTPUT_MAX_LINES);
+        let picked = r.pick(
+            "notifications.max_body_bytes",
+            None,
+            &["REATTACHD_NOTIFY_MAX_BYTES"],
+            notifications.max_body_bytes,
+        );
+        let notify_max_bytes = r.required("notifications.max_body_bytes", picked, apns::DEFAULT_BODY_BYTES);
+        let picked = r.pick(
+            "notifications.require_encryption",
+            None,
+            &["REATTACHD_NOTIFY_REQUIRE_ENCRYPTION"],
+            notifications.require_encryption,
+        );
+        let notify_require_encryption = r.required("notifications.require_encryption", picked, false);
+
+        let picked = r.pick("apns.key_id", None, &["APNS_KEY_ID"], apns_file.key_id);
+        let key_id = r.optional("apns.key_id", picked);
+        let picked = r.pick("apns.team_id", None, &["APNS_TEAM_ID"], apns_file.team_id);
+        let team_id = r.optional("apns.team_id", picked);
+        let picked = r.pick("apns.bundle_id", None, &["APNS_BUNDLE_ID"], apns_file.bundle_id);
+        let bundle_id = r.optional("apns.bundle_id", picked);
+        let picked = r.pick(
+            "apns.key_file",
+            None,
+            &["APNS_KEY_FILE"],
+            apns_file.key_file.map(relative_to_data_dir),
+        );
+        let key_file_set = picked.is_some();
+        let default_key_file = data_dir.join(apns::credentials::KEY_FILE).to_string_lossy().to_string();
+        let key_file = r.required("apns.key_file", picked, default_key_file);
+        // base_url points both environments at one server (e.g. the mock
+        // APNs server); the per-environment settings take precedence.
+        let base_url = r.pick("apns.base_url", None, &["APNS_BASE_URL"], apns_file.base_url);
+        let picked = r.pick("apns.sandbox_url", None, &["APNS_SANDBOX_URL"], apns_file.sandbox_url);
+        let sandbox_url = r.required("apns.sandbox_url", picked.or(base_url.clone()), apns::SANDBOX_URL.to_string());
+        let picked = r.pick("apns.production_url", None, &["APNS_PRODUCTION_URL"], apns_file.production_url);
+        let production_url = r.required("apns.production_url", picked.or(base_url), apns::PRODUCTION_URL.to_string());
+
+        if port == 0 {
+            r.invalid("server.port", "must be between 1 and 65535");
+        }
+        if let Some(ref bind_addr) = bind_addr {
+            if let Err(e) = Listener::parse(&format!("http://{}", bind_addr), port) {
+                r.invalid("server.bind_addr", e);
+            }
+        }
+        for spec in &listen {
+            if let Err(e) = Listener::parse(spec, port) {
+                r.invalid("server.listen", e);
+            }
+        }
+        if let Some(ref addr) = metrics_addr {
+            if let Err(e) = std::net::ToSocketAddrs::to_socket_addrs(addr.as_str()) {
+                r.invalid("server.metrics_addr", format!("'{}' is not a host:port address: {}", addr, e));
+            }
+        }
+
+        match (&tls_cert, &tls_key) {
+            (Some(_), None) => r.invalid("tls.cert", "tls.key must be set as well"),
+            (None, Some(_)) => r.invalid("tls.key", "tls.cert must be set as well"),
+            _ => {}
+        }
+        for (key, path) in [("tls.cert", &tls_cert), ("tls.key", &tls_key)] {
+            if let Some(path) = path {
+                if !Path::new(path).is_file() {
+                    r.invalid(key, format!("{} does not exist", path));
+                }
+            }
+        }
+        let tls_modes = [tailscale, self_signed, tls_cert.is_some() || tls_key.is_some()];
+        if tls_modes.iter().filter(|&&on| on).count() > 1 {
+            r.invalid("tls", "use only one of tailscale, self_signed and cert/key");
+        }
+        if mtls && !tls_modes.contains(&true) {
+            r.invalid("tls.mtls", "needs TLS: set tailscale, self_signed or cert/key");
+        }
+
+        if crate::parse_duration(&setup_expires).is_none() {
+            r.invalid("setup.expires", format!("'{}' is not a duration like 10m, 1h, 1d or never", setup_expires));
+        }
+        let mut sockets: Vec<tmux::Server> = Vec::new();
+        for spec in &tmux_sockets {
+            match tmux::Server::parse(spec) {
+                Ok(server) if sockets.iter().any(|other| other.id == server.id) => {
+                    r.invalid("tmux.sockets", format!("server ID '{}' is used twice", server.id));
+                }
+                Ok(server) => sockets.push(server),
+                Err(e) => r.invalid("tmux.sockets", e),
+            }
+        }
+        if output_max_lines == 0 {
+            r.invalid("output.max_lines", "must be at least 1");
+        }
+        if notify_max_bytes == 0 || notify_max_bytes > apns::MAX_BODY_BYTES {
+            r.invalid(
+                "notifications.max_body_bytes",
+                format!("must be between 1 and {} to fit the APNs payload limit", apns::MAX_BODY_BYTES),
+            );
+        }
+        if key_file_set && !Path::new(&key_file).is_file() {
+            r.invalid("apns.key_file", format!("{} does not exist", key_file));
+        }
+        for (key, url) in [("apns.sandbox_url", &sandbox_url), ("apns.production_url", &production_url)] {
+            if let Err(e) = reqwest::Url::parse(url) {
+                r.invalid(key, format!("'{}' is not a URL: {}", url, e));
+            }
+        }
+
+        if !r.errors.is_empty() {
+            return Err(r.errors);
+        }
+        Ok(Config {
+            port,
+            bind_addr,
+            listen,
+            metrics_addr,
+            drain_timeout: std::time::Duration::from_secs(drain_timeout_secs),
+            tailscale,
+            self_signed,
+            tls_cert,
+            tls_key,
+            mtls,
+            setup_expires,
+            tmux_servers: tmux::Servers::new(sockets),
+            output_max_lines,
+            notify_max_bytes,
+            notify_require_encryption,
+            apns: ApnsSettings {
+                key_id,
+                team_id,
+                bundle_id,
+                key_file: PathBuf::from(key_file),
+                sandbox_url,
+                production_url,
+            },
+            file: file.to_path_buf(),
+            settings: r.settings,
+            flags: retained_flags,
+        })
+    }
+
+    /// The resolved settings as TOML, each annotated with where it came from.
+    pub fn show(&self) -> String {
+        let describe = |source: &Source| match source {
+            Source::Flag(flag) => flag.to_string(),
+            Source::Env(name) => format!("env {}", name),
+            Source::File => FILE_NAME.to_string(),
+            Source::Default => "default".to_string(),
+        };
+        let mut out = format!("# {}\n", self.file.display());
+        let mut section = "";
+        for setting in &self.settings {
+            let (table, name) = setting.key.split_once('.').unwrap_or(("", setting.key));
+            if table != section {
+                out.push_str(&format!("\n[{}]\n", table));
+                section = table;
+            }
+            match setting.value {
+                Some(ref value) => out.push_str(&format!("{} = {}  # {}\n", name, value, describe(&setting.source))),
+                None => out.push_str(&format!("# {} is not set\n", name)),
+            }
+        }
+        out
+    }
+}
+
+/// Sets `key = value` pairs in `[table]` of the config file, keeping the
+/// rest of the file as it is.
+pub fn set(file: &Path, table: &str, values: &[(&str, String)]) -> Result<(), String> {
+    let content = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", file.display(), e)),
+    };
+    let mut document: toml_edit::DocumentMut = content
+        .parse()
+        .map_err(|e| format!("{}: {}", file.display(), e))?;
+    let section = document
+        .entry(table)
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .ok_or_else(|| format!("{}: {} is not a table", file.display(), table))?;
+    for (key, value) in values {
+        section[key] = toml_edit::value(value.as_str());
+    }
+    std::fs::write(file, document.to_string()).map_err(|e| format!("Failed to write {}: {}", file.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(flags: Flags, env: &[(&str, &str)], toml: &str) -> Result<Config, Vec<String>> {
+        let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let file_config = toml_edit::de::from_str(toml).unwrap();
+        Config::resolve(
+            flags,
+            &|name| env.get(name).cloned(),
+            file_config,
+            Path::new("/data/reattachd/config.toml"),
+        )
+    }
+
+    fn source<'a>(config: &'a Config, key: &str) -> &'a Source {
+        &config.settings.iter().find(|s| s.key == key).unwrap().source
+    }
+
+    #[test]
+    fn flag_beats_env_beats_file_beats_default() {
+        let toml = r#"
+            [server]
+            port = 9000
+            metrics_addr = "127.0.0.1:9100"
+            [setup]
+            expires = "1h"
+        "#;
+        let flags = Flags {
+            metrics_addr: Some("127.0.0.1:9300".into()),
+            ..Flags::default()
+        };
+        let env = [("REATTACHD_PORT", "9100"), ("REATTACHD_METRICS_ADDR", "127.0.0.1:9200")];
+        let config = resolve(flags, &env, toml).unwrap();
+
+        assert_eq!(config.metrics_addr.as_deref(), Some("127.0.0.1:9300"));
+        assert_eq!(source(&config, "server.metrics_addr"), &Source::Flag("--metrics-addr"));
+        assert_eq!(config.port, 9100);
+        assert_eq!(source(&config, "server.port"), &Source::Env("REATTACHD_PORT"));
+        assert_eq!(config.setup_expires, "1h");
+        assert_eq!(source(&config, "setup.expires"), &Source::File);
+        assert_eq!(config.output_max_lines, DEFAULT_OUTPUT_MAX_LINES);
+        assert_eq!(source(&config, "output.max_lines"), &Source::Default);
+
+        let shown = config.show();
+        assert!(shown.contains("[server]\nport = 9100  # env REATTACHD_PORT\n"), "{}", shown);
+        assert!(shown.contains("# bind_addr is not set\n"), "{}", shown);
+    }
+
+    #[test]
+    fn base_url_applies_to_both_apns_environments() {
+        let config = resolve(
+            Flags::default(),
+            &[("APNS_SANDBOX_URL", "http://127.0.0.1:1")],
+            "[apns]\nbase_url = \"http://127.0.0.1:2\"",
+        )
+        .unwrap();
+        assert_eq!(config.apns.sandbox_url, "http://127.0.0.1:1");
+        assert_eq!(config.apns.production_url, "http://127.0.0.1:2");
+    }
+
+    #[test]
+    fn reports_every_invalid_setting_with_its_source() {
+        let toml = r#"
+            [server]
+            listen = ["ftp://127.0.0.1"]
+            [tls]
+            tailscale = true
+            mtls = true
+            [notifications]
+            max_body_bytes = 99999
+        "#;
+        let flags = Flags {
+            self_signed: true,
+            ..Flags::default()
+        };
+        let errors = resolve(flags, &[("REATTACHD_PORT", "http"), ("REATTACHD_SETUP_EXPIRES", "soon")], toml).unwrap_err();
+
+        let expected = [
+            "server.port (env REATTACHD_PORT): invalid value 'http'",
+            "server.listen (/data/reattachd/config.toml): Invalid listener 'ftp://127.0.0.1'",
+            "tls: use only one of",
+            "setup.expires (env REATTACHD_SETUP_EXPIRES): 'soon' is not a duration",
+            "notifications.max_body_bytes (/data/reattachd/config.toml): must be between 1 and",
+        ];
+        assert_eq!(errors.len(), expected.len(), "{:#?}", errors);
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(error.starts_with(expected), "{} does not start with {}", error, expected);
+        }
+    }
+
+    #[test]
+    fn reads_tmux_sockets() {
+        let toml = "[tmux]\nsockets = [\"work\", \"ci=/tmp/ci.sock\"]";
+        let config = resolve(Flags::default(), &[], toml).unwrap();
+        let ids: Vec<&str> = config.tmux_servers.iter().map(|server| server.id.as_str()).collect();
+        assert_eq!(ids, ["default", "work", "ci"]);
+
+        let config = resolve(Flags::default(), &[("REATTACHD_TMUX_SOCKETS", "agents")], toml).unwrap();
+        assert!(config.tmux_servers.get("agents").is_some());
+        assert!(config.tmux_servers.get("work").is_none());
+
+        let errors = resolve(Flags::default(), &[], "[tmux]\nsockets = [\"work\", \"work=/tmp/w\"]").unwrap_err();
+        assert_eq!(errors, ["tmux.sockets (/data/reattachd/config.toml): server ID 'work' is used twice"]);
+    }
+
+    #[test]
+    fn lists_changed_settings() {
+        let old = resolve(Flags::default(), &[], "[output]\nmax_lines = 500").unwrap();
+        let new = resolve(Flags::default(), &[("REATTACHD_PORT", "9000")], "").unwrap();
+        assert_eq!(old.changed(&new), vec!["server.port", "output.max_lines"]);
+        assert!(old.changed(&old).is_empty());
+    }
+
+    #[test]
+    fn sets_values_and_keeps_the_rest_of_the_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let file = dir.join(FILE_NAME);
+        std::fs::write(&file, "# my settings\n[server]\nport = 9000\n\n[apns]\nkey_id = \"OLD\"\n").unwrap();
+
+        set(&file, "apns", &[("key_id", "NEW".to_string()), ("team_id", "TEAM".to_string())]).unwrap();
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert!(content.starts_with("# my settings\n[server]\nport = 9000\n"), "{}", content);
+        let parsed: FileConfig = toml_edit::de::from_str(&content).unwrap();
+        assert_eq!(parsed.apns.key_id.as_deref(), Some("NEW"));
+        assert_eq!(parsed.apns.team_id.as_deref(), Some("TEAM"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let error = toml_edit::de::from_str::<FileConfig>("[server]\nprot = 8787").unwrap_err();
+        assert!(error.to_string().contains("unknown field `prot`"), "{}", error);
+    }
+}
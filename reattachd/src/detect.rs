@@ -0,0 +1,232 @@
+//! Heuristic classification of what a pane is doing, for agents and programs
+//! that cannot tell us themselves via hooks.
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Output newer than this means the pane is still busy.
+const ACTIVE_WINDOW: Duration = Duration::from_secs(5);
+/// How many lines from the bottom of the screen are searched for prompts.
+const BOTTOM_LINES: usize = 15;
+
+const SHELLS: &[&str] = &["bash", "zsh", "fish", "sh", "dash", "ksh", "tcsh", "csh", "nu", "login"];
+
+/// Footers agents show only while they are working.
+const RUNNING_PATTERNS: &[&str] = &[
+    "esc to interrupt",  // Claude Code, Codex
+    "ctrl+c to interrupt",
+    "esc to cancel",     // Gemini CLI
+];
+
+/// Questions and input affordances that mean the pane waits for the user.
+const WAITING_PATTERNS: &[&str] = &[
+    // Claude Code
+    "do you want to proceed?",
+    "do you want to make this edit",
+    "do you want to create",
+    "? for shortcuts",
+    // Codex
+    "would you like to",
+    "⏎ send",
+    // aider
+    "(y)es/(n)o",
+    // Generic confirmations
+    "[y/n]",
+    "(y/n)",
+    "(yes/no)",
+    "press enter to continue",
+    "password:",
+    "passphrase",
+];
+
+/// Line prefixes of agent input boxes.
+const PROMPT_PREFIXES: &[&str] = &["│ >", "> ", "› ", "❯ "];
+/// Line endings that look like a prompt waiting at the cursor. Not ':',
+/// which ends too many log lines and headings.
+const PROMPT_SUFFIXES: &[&str] = &[">", "?", "❯", "›"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaneStatus {
+    /// A program is busy producing output.
+    Running,
+    /// A program is asking the user for something.
+    WaitingForInput,
+    /// Nothing is going on: a shell prompt or a dead pane.
+    Idle,
+}
+
+pub struct PaneSnapshot<'a> {
+    pub current_command: &'a str,
+    pub dead: bool,
+    /// Text of the visible screen, one entry per line.
+    pub screen: &'a [String],
+    /// Cursor row within `screen`, if known.
+    pub cursor_y: Option<u32>,
+    /// Time since the screen content last changed.
+    pub quiet_for: Duration,
+}
+
+pub fn is_shell(command: &str) -> bool {
+    SHELLS.contains(&command.trim_start_matches('-'))
+}
+
+pub fn classify(snapshot: &PaneSnapshot) -> PaneStatus {
+    if snapshot.dead {
+        return PaneStatus::Idle;
+    }
+
+    let bottom: Vec<String> = snapshot
+        .screen
+        .iter()
+        .rev()
+        .filter(|l| !l.trim().is_empty())
+        .take(BOTTOM_LINES)
+        .map(|l| l.to_lowercase())
+        .collect();
+    let contains_any = |patterns: &[&str]| bottom.iter().any(|l| patterns.iter().any(|p| l.contains(p)));
+
+    if contains_any(RUNNING_PATTERNS) {
+        return PaneStatus::Running;
+    }
+    if contains_any(WAITING_PATTERNS) {
+        return PaneStatus::WaitingForInput;
+    }
+    if is_shell(snapshot.current_command) {
+        return PaneStatus::Idle;
+    }
+    if snapshot.quiet_for < ACTIVE_WINDOW {
+        return PaneStatus::Running;
+    }
+
+    let cursor_line = snapshot
+        .cursor_y
+        .and_then(|y| snapshot.screen.get(y as usize))
+        .or_else(|| snapshot.screen.iter().rev().find(|l| !l.trim().is_empty()))
+        .map(|l| l.trim())
+        .unwrap_or("");
+    let looks_like_prompt = PROMPT_PREFIXES.iter().any(|p| cursor_line.starts_with(p.trim_end()))
+        || PROMPT_SUFFIXES.iter().any(|s| cursor_line.ends_with(s));
+    if looks_like_prompt {
+        PaneStatus::WaitingForInput
+    } else {
+        PaneStatus::Running
+    }
+}
+
+/// Remembers when each pane's screen last changed, so that repeated
+/// observations can tell a busy pane from a quiet one.
+#[derive(Default)]
+pub struct StatusDetector {
+    seen: Mutex<HashMap<String, (u64, Instant)>>,
+}
+
+impl StatusDetector {
+    /// Records the current screen of `target` and returns how long it has
+    /// been unchanged. A pane seen for the first time counts as just changed.
+    pub fn observe(&self, target: &str, screen: &[String]) -> Duration {
+        let mut hasher = DefaultHasher::new();
+        screen.hash(&mut hasher);
+        let hash = hasher.finish();
+        let now = Instant::now();
+
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        match seen.get_mut(target) {
+            Some((last_hash, changed_at)) if *last_hash == hash => now.duration_since(*changed_at),
+            Some(entry) => {
+                *entry = (hash, now);
+                Duration::ZERO
+            }
+            None => {
+                seen.insert(target.to_string(), (hash, now));
+                Duration::ZERO
+            }
+        }
+    }
+
+    /// Forgets panes that no longer exist.
+    pub fn retain(&self, targets: &[&str]) {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.retain(|target, _| targets.contains(&target.as_str()));
+    }
+}
+
+pub type SharedStatusDetector = Arc<StatusDetector>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn snapshot<'a>(command: &'a str, screen: &'a [String], quiet_for: Duration) -> PaneSnapshot<'a> {
+        PaneSnapshot {
+            current_command: command,
+            dead: false,
+            screen,
+            cursor_y: None,
+            quiet_for,
+        }
+    }
+
+    #[test]
+    fn claude_code_working_and_waiting() {
+        let working = screen(&["● Reading files…", "✻ Thinking… (12s · esc to interrupt)", "│ >", "? for shortcuts"]);
+        assert_eq!(classify(&snapshot("node", &working, Duration::from_secs(60))), PaneStatus::Running);
+
+        let idle_box = screen(&["● Done.", "╭────╮", "│ >  │", "╰────╯", "  ? for shortcuts"]);
+        assert_eq!(classify(&snapshot("claude", &idle_box, Duration::ZERO)), PaneStatus::WaitingForInput);
+
+        let permission = screen(&["Bash command", "  rm -rf target", "Do you want to proceed?", "❯ 1. Yes", "  2. No"]);
+        assert_eq!(classify(&snapshot("node", &permission, Duration::ZERO)), PaneStatus::WaitingForInput);
+    }
+
+    #[test]
+    fn codex_and_aider_prompts() {
+        let codex = screen(&["Would you like to run the following command?", "$ cargo test", "▌ Yes   No"]);
+        assert_eq!(classify(&snapshot("codex", &codex, Duration::ZERO)), PaneStatus::WaitingForInput);
+
+        let aider = screen(&["Applied edit to src/main.rs", "", "architect> "]);
+        assert_eq!(classify(&snapshot("aider", &aider, Duration::from_secs(30))), PaneStatus::WaitingForInput);
+    }
+
+    #[test]
+    fn shell_prompt_is_idle() {
+        let shell = screen(&["$ ls", "Cargo.toml src", "$ "]);
+        assert_eq!(classify(&snapshot("zsh", &shell, Duration::ZERO)), PaneStatus::Idle);
+        assert_eq!(classify(&snapshot("-bash", &shell, Duration::ZERO)), PaneStatus::Idle);
+    }
+
+    #[test]
+    fn generic_program_uses_output_age_and_cursor_line() {
+        let build = screen(&["   Compiling serde v1.0.0", "   Compiling tokio v1.0.0"]);
+        assert_eq!(classify(&snapshot("cargo", &build, Duration::from_secs(1))), PaneStatus::Running);
+        assert_eq!(classify(&snapshot("cargo", &build, Duration::from_secs(60))), PaneStatus::Running);
+
+        let question = screen(&["Overwrite existing file? "]);
+        assert_eq!(classify(&snapshot("cp", &question, Duration::from_secs(60))), PaneStatus::WaitingForInput);
+        let confirm = screen(&["Proceed with install [Y/n]"]);
+        assert_eq!(classify(&snapshot("apt", &confirm, Duration::ZERO)), PaneStatus::WaitingForInput);
+        let heading = screen(&["Test summary:"]);
+        assert_eq!(classify(&snapshot("pytest", &heading, Duration::from_secs(60))), PaneStatus::Running);
+    }
+
+    #[test]
+    fn detector_tracks_quiet_time_per_pane() {
+        let detector = StatusDetector::default();
+        let a = screen(&["a"]);
+        let b = screen(&["b"]);
+        assert_eq!(detector.observe("dev:0.0", &a), Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(detector.observe("dev:0.0", &a) >= Duration::from_millis(5));
+        assert_eq!(detector.observe("dev:0.0", &b), Duration::ZERO);
+        detector.retain(&[]);
+        assert_eq!(detector.observe("dev:0.0", &b), Duration::ZERO);
+    }
+}
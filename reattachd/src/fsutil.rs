@@ -0,0 +1,97 @@
+//! Files that must not be readable by other users: keys, secrets and tokens.
+
+use std::path::Path;
+
+/// Reads a private file, refusing one that other users can access.
+pub fn read_private(path: &Path) -> Result<String, String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "{} is accessible by other users (mode {:o}); run 'chmod 600 {}'",
+                path.display(),
+                mode,
+                path.display()
+            ));
+        }
+    }
+    std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+/// Writes a file only its owner can read.
+pub fn write_private(path: &Path, content: &[u8]) -> Result<(), String> {
+    write_with_mode(path, content, 0o600)
+}
+
+/// Writes a file that should only ever have `mode`. The content goes to a
+/// temporary file created with that mode, which is then renamed over `path`,
+/// so the file is never more accessible, not even for a moment, and never
+/// left half-written.
+pub fn write_with_mode(path: &Path, content: &[u8], mode: u32) -> Result<(), String> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("Failed to write {}: not a file path", path.display()))?;
+    let temp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        uuid::Uuid::new_v4().simple()
+    ));
+    let write = || -> std::io::Result<()> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(mode);
+            let file = options.open(&temp)?;
+            // The umask may have cleared bits of `mode`.
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            write_and_sync(file, content)?;
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = mode;
+            write_and_sync(options.open(&temp)?, content)?;
+        }
+        std::fs::rename(&temp, path)
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&temp);
+        format!("Failed to write {}: {}", path.display(), e)
+    })
+}
+
+fn write_and_sync(mut file: std::fs::File, content: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn replaces_files_with_mode_from_creation() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("secret.key");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let error = read_private(&path).unwrap_err();
+        assert!(error.contains("chmod 600"), "{}", error);
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(read_private(&path).unwrap(), "new");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        write_with_mode(&path, b"public", 0o644).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o644);
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1, "no temporary files are left");
+
+        assert!(write_private(&dir.join("missing/secret.key"), b"new").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use super::{home_file, notify_command, read_existing, AgentIntegration, HookEvent, Plan, Scope};
+
+/// aider: `notifications-command` in `~/.aider.conf.yml`, which aider runs
+/// whenever it is waiting for input.
+pub struct Aider;
+
+const HOOK_COMMENT: &str = "# Reattach push notification hook";
+
+fn is_notification_key(line: &str) -> bool {
+    let t = line.trim_start();
+    t.starts_with("notifications:") || t.starts_with("notifications-command:")
+}
+
+fn without_hook(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .filter(|l| l.trim() != HOOK_COMMENT && !is_notification_key(l))
+        .collect()
+}
+
+impl Aider {
+    fn command_line(&self) -> String {
+        format!("notifications-command: {}", notify_command(self.name()))
+    }
+}
+
+impl AgentIntegration for Aider {
+    fn name(&self) -> &'static str {
+        "aider"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "aider"
+    }
+
+    fn detect(&self) -> bool {
+        super::which("aider") || home_file(".aider.conf.yml").is_some_and(|p| p.is_file())
+    }
+
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf> {
+        scope.file(".aider.conf.yml", ".aider.conf.yml", None)
+    }
+
+    fn is_hooked(&self, scope: &Scope) -> bool {
+        let line = self.command_line();
+        self.config_path(scope)
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .is_some_and(|s| s.lines().any(|l| l.trim() == line))
+    }
+
+    fn plan_install(&self, scope: &Scope, _events: &[HookEvent]) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let before = read_existing(&path)?;
+        let existing = before.as_deref().unwrap_or("");
+        let line = self.command_line();
+        let has_other_command = existing.lines().any(|l| {
+            let t = l.trim();
+            t.starts_with("notifications-command:") && t != line
+        });
+        if has_other_command {
+            return Ok(Plan::Skip(format!(
+                "Skipped aider update: notifications-command is already configured in {}\nAdd Reattach manually if needed: {}",
+                path.display(),
+                line
+            )));
+        }
+
+        let mut out = without_hook(existing).join("\n");
+        if !out.is_empty() {
+            out.push_str("\n\n");
+        }
+        out.push_str(&format!("{}\nnotifications: true\n{}\n", HOOK_COMMENT, line));
+        Ok(Plan::edit(path, before, Some(out)))
+    }
+
+    fn plan_uninstall(&self, scope: &Scope) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        if !self.is_hooked(scope) {
+            return Ok(Plan::Skip("No Reattach hook found in aider config".to_string()));
+        }
+        let before = read_existing(&path)?;
+        let mut out = without_hook(before.as_deref().unwrap_or(""))
+            .join("\n")
+            .trim_end()
+            .to_string();
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        Ok(Plan::edit(path, before, Some(out)))
+    }
+}
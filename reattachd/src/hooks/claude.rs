@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use super::{
+    ensure_event_hook, has_event_hook, json_settings_string, load_json_settings, object_entry,
+    parse_json_settings, prune_event_hook, read_existing, AgentIntegration, HookEvent, Plan, Scope,
+    HOOK_NOTIFY_COMMAND,
+};
+
+/// Claude Code: `hooks` in `~/.claude/settings.json`, one entry per
+/// [`HookEvent`].
+pub struct Claude;
+
+impl AgentIntegration for Claude {
+    fn name(&self) -> &'static str {
+        "claude"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Claude Code"
+    }
+
+    fn detect(&self) -> bool {
+        super::detect_agent("claude", ".claude")
+    }
+
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf> {
+        scope.file(".claude/settings.json", ".claude/settings.json", Some(".claude/settings.local.json"))
+    }
+
+    fn is_hooked(&self, scope: &Scope) -> bool {
+        let Some(root) = self.config_path(scope).and_then(|p| load_json_settings(&p)) else {
+            return false;
+        };
+        HookEvent::ALL
+            .iter()
+            .any(|e| has_event_hook(&root, e.claude_hook().0, HOOK_NOTIFY_COMMAND))
+    }
+
+    fn plan_install(&self, scope: &Scope, events: &[HookEvent]) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let before = read_existing(&path)?;
+        let mut root = parse_json_settings(&path, before.as_deref())?;
+        let hooks = object_entry(root.as_object_mut().expect("object expected"), "hooks");
+        // Events that were not picked are removed, so re-running install
+        // with a different --events selection replaces the previous one.
+        for event in HookEvent::ALL {
+            let (name, matcher) = event.claude_hook();
+            if events.contains(event) {
+                ensure_event_hook(hooks, name, matcher, HOOK_NOTIFY_COMMAND);
+            } else {
+                prune_event_hook(hooks, name, Some(matcher), HOOK_NOTIFY_COMMAND);
+            }
+        }
+        Ok(Plan::edit(path, before, Some(json_settings_string(&root))))
+    }
+
+    fn plan_uninstall(&self, scope: &Scope) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let Some(before) = read_existing(&path)? else {
+            return Ok(Plan::Skip("No Claude settings file found".to_string()));
+        };
+        let mut root = parse_json_settings(&path, Some(&before))?;
+        if let Some(hooks) = root.get_mut("hooks").and_then(|v| v.as_object_mut()) {
+            for event in HookEvent::ALL {
+                prune_event_hook(hooks, event.claude_hook().0, None, HOOK_NOTIFY_COMMAND);
+            }
+        }
+        Ok(Plan::edit(path, Some(before), Some(json_settings_string(&root))))
+    }
+}
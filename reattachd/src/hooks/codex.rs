@@ -0,0 +1,215 @@
+use std::path::PathBuf;
+
+use toml_edit::{value, Array, DocumentMut, Value};
+
+use super::{read_existing, AgentIntegration, HookEvent, Plan, Scope};
+
+/// Codex CLI: top-level `notify` in `~/.codex/config.toml`.
+///
+/// Codex runs `notify` with the event JSON appended as the last argument. If
+/// another command is already configured it is chained behind
+/// `reattachd notify --`, which runs it with the same arguments.
+pub struct Codex;
+
+const HOOK_COMMENT: &str = "# Reattach push notification hook\n";
+
+fn is_reattachd(program: &str) -> bool {
+    std::path::Path::new(program)
+        .file_name()
+        .and_then(|n| n.to_str())
+        == Some("reattachd")
+}
+
+/// Splits a `notify` argv into whether it runs Reattach and the command
+/// chained behind it (or the foreign command itself, when it doesn't).
+fn split_notify(argv: &[String]) -> (bool, Vec<String>) {
+    match argv {
+        [program, sub, rest @ ..] if is_reattachd(program) && sub == "notify" => {
+            let chained = match rest {
+                [sep, chained @ ..] if sep == "--" => chained.to_vec(),
+                _ => Vec::new(),
+            };
+            (true, chained)
+        }
+        _ => (false, argv.to_vec()),
+    }
+}
+
+/// The configured `notify` argv, or `None` if it is missing or not an array
+/// of strings.
+fn notify_argv(doc: &DocumentMut) -> Option<Vec<String>> {
+    doc.get("notify")?
+        .as_array()?
+        .iter()
+        .map(|v| v.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Sets `notify` in the root table. An existing entry is updated in place so
+/// that its comments and position survive; a new one gets a marker comment.
+fn set_notify(doc: &mut DocumentMut, argv: &[String]) {
+    let array: Array = argv.iter().map(String::as_str).collect();
+    if let Some(existing) = doc.get_mut("notify").and_then(|item| item.as_value_mut()) {
+        let decor = existing.decor().clone();
+        *existing = Value::Array(array);
+        *existing.decor_mut() = decor;
+        return;
+    }
+    doc["notify"] = value(array);
+    if let Some(mut key) = doc.as_table_mut().key_mut("notify") {
+        key.leaf_decor_mut().set_prefix(HOOK_COMMENT);
+    }
+}
+
+fn parse(content: &str, path: &std::path::Path) -> Result<DocumentMut, String> {
+    content
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("Refusing to modify {}: not valid TOML ({})", path.display(), e))
+}
+
+/// Adds Reattach to `notify`, chaining any other configured command.
+pub(super) fn install_notify(doc: &mut DocumentMut) -> Result<(), String> {
+    let chained = match doc.get("notify") {
+        None => Vec::new(),
+        Some(_) => {
+            let argv = notify_argv(doc)
+                .filter(|argv| !argv.is_empty())
+                .ok_or("notify is not an array of strings")?;
+            let (hooked, chained) = split_notify(&argv);
+            if hooked {
+                return Ok(());
+            }
+            chained
+        }
+    };
+
+    let mut argv = vec!["reattachd".to_string(), "notify".to_string()];
+    if !chained.is_empty() {
+        argv.push("--".to_string());
+        argv.extend(chained);
+    }
+    set_notify(doc, &argv);
+    Ok(())
+}
+
+/// Removes Reattach from `notify`, restoring a chained command if any.
+pub(super) fn uninstall_notify(doc: &mut DocumentMut) -> bool {
+    let Some(argv) = notify_argv(doc) else {
+        return false;
+    };
+    let (hooked, chained) = split_notify(&argv);
+    if !hooked {
+        return false;
+    }
+    if chained.is_empty() {
+        doc.remove("notify");
+    } else {
+        set_notify(doc, &chained);
+    }
+    true
+}
+
+impl AgentIntegration for Codex {
+    fn name(&self) -> &'static str {
+        "codex"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Codex"
+    }
+
+    fn detect(&self) -> bool {
+        super::detect_agent("codex", ".codex")
+    }
+
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf> {
+        scope.file(".codex/config.toml", ".codex/config.toml", None)
+    }
+
+    fn is_hooked(&self, scope: &Scope) -> bool {
+        self.config_path(scope)
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|s| s.parse::<DocumentMut>().ok())
+            .and_then(|doc| notify_argv(&doc))
+            .is_some_and(|argv| split_notify(&argv).0)
+    }
+
+    fn plan_install(&self, scope: &Scope, _events: &[HookEvent]) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let before = read_existing(&path)?;
+        let mut doc = parse(before.as_deref().unwrap_or(""), &path)?;
+        install_notify(&mut doc).map_err(|e| format!("Refusing to modify {}: {}", path.display(), e))?;
+        Ok(Plan::edit(path, before, Some(doc.to_string())))
+    }
+
+    fn plan_uninstall(&self, scope: &Scope) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let Some(before) = read_existing(&path)? else {
+            return Ok(Plan::Skip("No Codex config file found".to_string()));
+        };
+        let mut doc = parse(&before, &path)?;
+        if !uninstall_notify(&mut doc) {
+            return Ok(Plan::Skip("No Reattach hook found in Codex config".to_string()));
+        }
+        Ok(Plan::edit(path, Some(before), Some(doc.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install(input: &str) -> String {
+        let mut doc: DocumentMut = input.parse().unwrap();
+        install_notify(&mut doc).unwrap();
+        doc.to_string()
+    }
+
+    #[test]
+    fn notify_lands_in_root_table_and_keeps_comments() {
+        let input = "# my settings\n[tui]\nnotifications = true # inline\n";
+        let out = install(input);
+        let doc: DocumentMut = out.parse().unwrap();
+        assert_eq!(notify_argv(&doc).unwrap(), ["reattachd", "notify"]);
+        assert!(doc["tui"].get("notify").is_none());
+        assert!(out.contains("# my settings"));
+        assert!(out.contains("notifications = true # inline"));
+        assert_eq!(install(&out), out, "install is idempotent");
+    }
+
+    #[test]
+    fn existing_notify_is_chained_and_restored() {
+        let input = "model = \"o3\"\n# desktop\nnotify = [\"notify-send\", \"Codex\"] # mine\n";
+        let out = install(input);
+        let mut doc: DocumentMut = out.parse().unwrap();
+        assert_eq!(
+            notify_argv(&doc).unwrap(),
+            ["reattachd", "notify", "--", "notify-send", "Codex"]
+        );
+
+        assert!(out.contains("# desktop\nnotify = ["));
+        assert!(out.contains("] # mine"));
+
+        assert!(uninstall_notify(&mut doc));
+        assert_eq!(doc.to_string(), input);
+    }
+
+    #[test]
+    fn legacy_hook_is_recognized_and_removed() {
+        let legacy = "# Reattach push notification hook\nnotify = [\"reattachd\", \"notify\"]\n\n[tui]\nx = 1\n";
+        assert_eq!(install(legacy), legacy);
+
+        let mut doc: DocumentMut = legacy.parse().unwrap();
+        assert!(uninstall_notify(&mut doc));
+        let out = doc.to_string();
+        assert!(!out.contains("notify ="));
+        assert!(!out.contains("Reattach"));
+        assert!(out.contains("[tui]"));
+    }
+
+    #[test]
+    fn rejects_non_array_notify() {
+        let mut doc: DocumentMut = "notify = \"script.sh\"\n".parse().unwrap();
+        assert!(install_notify(&mut doc).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use super::{home_file, AgentIntegration, HookEvent, Plan, Scope};
+
+/// GitHub Copilot CLI has no user-level hook to run a command when it waits
+/// for input, so it is listed for detection only; pane watchers cover it.
+pub struct Copilot;
+
+impl AgentIntegration for Copilot {
+    fn name(&self) -> &'static str {
+        "copilot"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Copilot CLI"
+    }
+
+    fn detect(&self) -> bool {
+        super::detect_agent("copilot", ".copilot")
+    }
+
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf> {
+        match scope {
+            Scope::Global => home_file(".copilot/config.json"),
+            Scope::Project { .. } => None,
+        }
+    }
+
+    fn is_hooked(&self, _scope: &Scope) -> bool {
+        false
+    }
+
+    fn plan_install(&self, _scope: &Scope, _events: &[HookEvent]) -> Result<Plan, String> {
+        Err("no notification hook available; use `reattachd watch add --waiting` on its pane instead".to_string())
+    }
+
+    fn plan_uninstall(&self, _scope: &Scope) -> Result<Plan, String> {
+        Ok(Plan::Skip("Nothing to remove".to_string()))
+    }
+}
@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+
+use super::{
+    json_settings_string, load_json_settings, notify_command, object_entry, parse_json_settings,
+    read_existing, AgentIntegration, HookEvent, Plan, Scope,
+};
+
+/// Cursor CLI: `stop` hook in `~/.cursor/hooks.json`.
+pub struct Cursor;
+
+const EVENT: &str = "stop";
+
+fn has_command(entries: &serde_json::Value, command: &str) -> bool {
+    entries
+        .as_array()
+        .is_some_and(|arr| arr.iter().any(|h| h.get("command").and_then(|v| v.as_str()) == Some(command)))
+}
+
+impl AgentIntegration for Cursor {
+    fn name(&self) -> &'static str {
+        "cursor"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Cursor CLI"
+    }
+
+    fn detect(&self) -> bool {
+        super::detect_agent("cursor-agent", ".cursor")
+    }
+
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf> {
+        scope.file(".cursor/hooks.json", ".cursor/hooks.json", None)
+    }
+
+    fn is_hooked(&self, scope: &Scope) -> bool {
+        let Some(root) = self.config_path(scope).and_then(|p| load_json_settings(&p)) else {
+            return false;
+        };
+        root.pointer(&format!("/hooks/{}", EVENT))
+            .is_some_and(|entries| has_command(entries, &notify_command(self.name())))
+    }
+
+    fn plan_install(&self, scope: &Scope, _events: &[HookEvent]) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let before = read_existing(&path)?;
+        let mut root = parse_json_settings(&path, before.as_deref())?;
+        let command = notify_command(self.name());
+
+        let root_obj = root.as_object_mut().expect("object expected");
+        root_obj.entry("version").or_insert_with(|| serde_json::json!(1));
+        let hooks = object_entry(root_obj, "hooks");
+        let entries = hooks
+            .entry(EVENT.to_string())
+            .or_insert_with(|| serde_json::json!([]));
+        if !entries.is_array() {
+            *entries = serde_json::json!([]);
+        }
+        if !has_command(entries, &command) {
+            entries
+                .as_array_mut()
+                .expect("array expected")
+                .push(serde_json::json!({ "command": command }));
+        }
+        Ok(Plan::edit(path, before, Some(json_settings_string(&root))))
+    }
+
+    fn plan_uninstall(&self, scope: &Scope) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let Some(before) = read_existing(&path)? else {
+            return Ok(Plan::Skip("No Cursor hooks file found".to_string()));
+        };
+        let mut root = parse_json_settings(&path, Some(&before))?;
+        let command = notify_command(self.name());
+        if let Some(entries) = root
+            .pointer_mut(&format!("/hooks/{}", EVENT))
+            .and_then(|v| v.as_array_mut())
+        {
+            entries.retain(|h| h.get("command").and_then(|v| v.as_str()) != Some(command.as_str()));
+        }
+        Ok(Plan::edit(path, Some(before), Some(json_settings_string(&root))))
+    }
+}
@@ -0,0 +1,232 @@
+//! Applying hook changes to agent settings files: dry-run diffs, timestamped
+//! backups and restoring from them. Backups go to [`BACKUP_DIR`] in the data
+//! dir rather than next to the settings file, where a project's would end up
+//! in its repository.
+
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
+
+pub const BACKUP_DIR: &str = "hook-backups";
+const BACKUP_MARKER: &str = ".reattach-backup-";
+/// Older backups of the same file beyond this count are deleted.
+const KEEP_BACKUPS: usize = 5;
+
+/// What installing or uninstalling a hook would do.
+pub enum Plan {
+    /// Replace the content of `path` (`before` is `None` when the file does
+    /// not exist yet); `after: None` deletes the file.
+    Edit {
+        path: PathBuf,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// Nothing to do; the message says why.
+    Skip(String),
+}
+
+impl Plan {
+    pub fn edit(path: PathBuf, before: Option<String>, after: Option<String>) -> Self {
+        Plan::Edit { path, before, after }
+    }
+}
+
+/// Reads a settings file, returning `None` if it does not exist.
+pub fn read_existing(path: &Path) -> Result<Option<String>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Carries out a plan, or with `dry_run` describes it as a unified diff.
+/// The file's previous content is backed up into `backup_dir`.
+pub fn apply(plan: Plan, dry_run: bool, backup_dir: &Path) -> Result<String, String> {
+    let (path, before, after) = match plan {
+        Plan::Skip(message) => return Ok(message),
+        Plan::Edit { path, before, after } => (path, before, after),
+    };
+    if before == after {
+        return Ok(format!("{} is already up to date", path.display()));
+    }
+
+    if dry_run {
+        return Ok(diff(&path, before.as_deref(), after.as_deref()));
+    }
+
+    let backup = match before {
+        Some(_) => Some(backup(&path, backup_dir)?),
+        None => None,
+    };
+    match after {
+        Some(content) => write_file(&path, &content)?,
+        None => std::fs::remove_file(&path)
+            .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?,
+    }
+
+    let action = if path.exists() { "Updated" } else { "Removed" };
+    Ok(match backup {
+        Some(backup) => format!("{} {} (backup: {})", action, path.display(), backup.display()),
+        None => format!("{} {}", action, path.display()),
+    })
+}
+
+pub fn write_file(path: &Path, content: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    std::fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn diff(path: &Path, before: Option<&str>, after: Option<&str>) -> String {
+    let name = path.display().to_string();
+    let old_name = if before.is_some() { name.as_str() } else { "/dev/null" };
+    let new_name = if after.is_some() { name.as_str() } else { "/dev/null" };
+    TextDiff::from_lines(before.unwrap_or(""), after.unwrap_or(""))
+        .unified_diff()
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// The name backups of `path` start with: its absolute path with the
+/// separators replaced, so that files of the same name in different projects
+/// don't mix.
+fn backup_prefix(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let flat: String = path
+        .to_string_lossy()
+        .trim_start_matches(['/', '\\'])
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':') { '_' } else { c })
+        .collect();
+    format!("{}{}", flat, BACKUP_MARKER)
+}
+
+/// Copies `path` into `dir` as `<flattened path>.reattach-backup-<timestamp>`
+/// and prunes old backups.
+fn backup(path: &Path, dir: &Path) -> Result<PathBuf, String> {
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let backup = dir.join(format!("{}{}", backup_prefix(path), stamp));
+    std::fs::copy(path, &backup)
+        .map_err(|e| format!("Failed to back up {} to {}: {}", path.display(), backup.display(), e))?;
+
+    let existing = backups(path, dir);
+    if existing.len() > KEEP_BACKUPS {
+        for old in &existing[..existing.len() - KEEP_BACKUPS] {
+            let _ = std::fs::remove_file(old);
+        }
+    }
+    Ok(backup)
+}
+
+/// Backups of `path` in `dir`, oldest first.
+pub fn backups(path: &Path, dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let prefix = backup_prefix(path);
+    let mut found: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix))
+        })
+        .collect();
+    // The timestamp format sorts lexicographically.
+    found.sort();
+    found
+}
+
+/// Puts a backup of `path` back in place: `from` if given, otherwise the
+/// most recent one. The current file is backed up first, so a restore can
+/// itself be undone.
+pub fn restore(path: &Path, from: Option<&Path>, backup_dir: &Path) -> Result<String, String> {
+    let source = match from {
+        Some(from) => from.to_path_buf(),
+        None => backups(path, backup_dir)
+            .pop()
+            .ok_or_else(|| format!("No backups found for {}", path.display()))?,
+    };
+    let content = std::fs::read_to_string(&source)
+        .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+    let replaced = if path.exists() {
+        Some(backup(path, backup_dir)?)
+    } else {
+        None
+    };
+    write_file(path, &content)?;
+    Ok(match replaced {
+        Some(replaced) => format!(
+            "Restored {} from {} (backup of the replaced file: {})",
+            path.display(),
+            source.display(),
+            replaced.display()
+        ),
+        None => format!("Restored {} from {}", path.display(), source.display()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_prints_diff_without_writing() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("settings.json");
+        std::fs::write(&path, "a\nb\n").unwrap();
+
+        let plan = Plan::edit(path.clone(), Some("a\nb\n".into()), Some("a\nc\n".into()));
+        let out = apply(plan, true, &dir.join(BACKUP_DIR)).unwrap();
+        assert!(out.contains("-b\n") && out.contains("+c\n"), "{}", out);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\n");
+        assert!(backups(&path, &dir.join(BACKUP_DIR)).is_empty());
+    }
+
+    #[test]
+    fn writes_back_up_and_restore() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let backup_dir = dir.join(BACKUP_DIR);
+        let path = dir.join("project/.claude/settings.json");
+        write_file(&path, "old\n").unwrap();
+
+        apply(Plan::edit(path.clone(), Some("old\n".into()), Some("new\n".into())), false, &backup_dir).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        let saved = backups(&path, &backup_dir);
+        assert_eq!(saved.len(), 1);
+        assert!(saved[0].starts_with(&backup_dir), "{}", saved[0].display());
+        assert_eq!(std::fs::read_to_string(&saved[0]).unwrap(), "old\n");
+        // Nothing is left in the project.
+        assert_eq!(std::fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let message = restore(&path, None, &backup_dir).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+        // The file the restore replaced is backed up too.
+        let saved = backups(&path, &backup_dir);
+        assert_eq!(saved.len(), 2);
+        assert_eq!(std::fs::read_to_string(&saved[1]).unwrap(), "new\n");
+        assert!(message.contains(&saved[1].display().to_string()), "{}", message);
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_backups() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("config.toml");
+        for i in 0..(KEEP_BACKUPS + 2) {
+            std::fs::write(&path, format!("{}\n", i)).unwrap();
+            backup(&path, &dir.join(BACKUP_DIR)).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let saved = backups(&path, &dir.join(BACKUP_DIR));
+        assert_eq!(saved.len(), KEEP_BACKUPS);
+        assert_eq!(std::fs::read_to_string(saved.last().unwrap()).unwrap(), format!("{}\n", KEEP_BACKUPS + 1));
+    }
+}
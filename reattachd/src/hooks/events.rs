@@ -0,0 +1,278 @@
+//! Agent hook events that can trigger a notification, and how to recognize
+//! them in the JSON payloads agents pass to `reattachd notify`.
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// The agent finished its turn.
+    Stop,
+    /// A subagent (Claude Code's Task tool) finished.
+    SubagentStop,
+    /// The agent needs permission to use a tool.
+    PermissionPrompt,
+    /// The agent has been waiting for input for a while.
+    IdlePrompt,
+    /// Any other agent notification, e.g. Claude Code's `auth_success`.
+    Notification,
+    /// The agent is about to run a shell command that looks destructive.
+    DangerousCommand,
+    SessionStart,
+    SessionEnd,
+}
+
+/// Shell command fragments that make a `PreToolUse` hook notify.
+const DANGEROUS_PATTERNS: &[&str] = &[
+    "rm -rf",
+    "rm -fr",
+    "rm -r -f",
+    "git push --force",
+    "git push -f",
+    "git reset --hard",
+    "git clean -f",
+    "git clean -df",
+    "git clean -fd",
+    "git branch -D",
+    "sudo ",
+    "mkfs",
+    "dd if=",
+    "chmod -R 777",
+    "> /dev/sd",
+    "drop table",
+    "drop database",
+    "truncate table",
+    "kubectl delete",
+    "terraform destroy",
+    "| sh",
+    "| bash",
+];
+
+impl HookEvent {
+    pub const ALL: &'static [HookEvent] = &[
+        HookEvent::Stop,
+        HookEvent::SubagentStop,
+        HookEvent::PermissionPrompt,
+        HookEvent::IdlePrompt,
+        HookEvent::Notification,
+        HookEvent::DangerousCommand,
+        HookEvent::SessionStart,
+        HookEvent::SessionEnd,
+    ];
+
+    /// Events installed when `--events` is not given.
+    pub const DEFAULT: &'static [HookEvent] = &[
+        HookEvent::Stop,
+        HookEvent::PermissionPrompt,
+        HookEvent::IdlePrompt,
+    ];
+
+    /// Name accepted by `hooks install --events`.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Stop => "stop",
+            HookEvent::SubagentStop => "subagent_stop",
+            HookEvent::PermissionPrompt => "permission_prompt",
+            HookEvent::IdlePrompt => "idle_prompt",
+            HookEvent::Notification => "notification",
+            HookEvent::DangerousCommand => "dangerous_command",
+            HookEvent::SessionStart => "session_start",
+            HookEvent::SessionEnd => "session_end",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|e| e.name() == name)
+    }
+
+    /// Shown as the notification subtitle.
+    pub fn subtitle(self) -> &'static str {
+        match self {
+            HookEvent::Stop => "Finished",
+            HookEvent::SubagentStop => "Subagent finished",
+            HookEvent::PermissionPrompt => "Permission needed",
+            HookEvent::IdlePrompt => "Waiting for input",
+            HookEvent::Notification => "Notification",
+            HookEvent::DangerousCommand => "Dangerous command",
+            HookEvent::SessionStart => "Session started",
+            HookEvent::SessionEnd => "Session ended",
+        }
+    }
+
+    /// APNs category, so the app can group notifications and attach actions.
+    pub fn category(self) -> &'static str {
+        match self {
+            HookEvent::Stop | HookEvent::SubagentStop => "AGENT_FINISHED",
+            HookEvent::PermissionPrompt => "AGENT_PERMISSION",
+            HookEvent::IdlePrompt => "AGENT_WAITING",
+            HookEvent::Notification => "AGENT_NOTIFICATION",
+            HookEvent::DangerousCommand => "AGENT_DANGEROUS_COMMAND",
+            HookEvent::SessionStart | HookEvent::SessionEnd => "AGENT_SESSION",
+        }
+    }
+
+    /// Claude Code hook event and matcher that deliver this event.
+    pub fn claude_hook(self) -> (&'static str, &'static str) {
+        match self {
+            HookEvent::Stop => ("Stop", ""),
+            HookEvent::SubagentStop => ("SubagentStop", ""),
+            HookEvent::PermissionPrompt => ("Notification", "permission_prompt"),
+            HookEvent::IdlePrompt => ("Notification", "idle_prompt"),
+            // Claude Code's other notification types; the matcher is a regex.
+            HookEvent::Notification => ("Notification", "auth_success|elicitation_dialog"),
+            HookEvent::DangerousCommand => ("PreToolUse", "Bash"),
+            HookEvent::SessionStart => ("SessionStart", ""),
+            HookEvent::SessionEnd => ("SessionEnd", ""),
+        }
+    }
+
+    /// Works out which event a hook payload describes. `None` means the
+    /// payload should not notify, e.g. a `PreToolUse` for a harmless command
+    /// or a Codex event other than a completed turn.
+    pub fn from_payload(value: &Value) -> Option<Self> {
+        let str_field = |key: &str| value.get(key).and_then(|v| v.as_str()).unwrap_or("");
+
+        if let Some(name) = value.get("hook_event_name").and_then(|v| v.as_str()) {
+            return match name {
+                // Gemini CLI calls the end of a turn AfterAgent.
+                "Stop" | "AfterAgent" => Some(HookEvent::Stop),
+                "SubagentStop" => Some(HookEvent::SubagentStop),
+                "Notification" => Some(notification_kind(value)),
+                "PreToolUse" => {
+                    let command = value
+                        .pointer("/tool_input/command")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("");
+                    is_dangerous_command(command).then_some(HookEvent::DangerousCommand)
+                }
+                "SessionStart" => Some(HookEvent::SessionStart),
+                "SessionEnd" => Some(HookEvent::SessionEnd),
+                _ => None,
+            };
+        }
+
+        // Codex sends `type`; only completed turns are interesting.
+        match value.get("type").and_then(|v| v.as_str()) {
+            Some("agent-turn-complete") => Some(HookEvent::Stop),
+            Some(_) => None,
+            None if str_field("status") == "aborted" => None,
+            None => Some(HookEvent::Stop),
+        }
+    }
+}
+
+/// Claude Code sends `notification_type` in newer versions; older ones only
+/// have the message text. Types we don't know, and messages that are neither
+/// a permission request nor an idle prompt, are plain notifications.
+fn notification_kind(value: &Value) -> HookEvent {
+    let kind = value
+        .get("notification_type")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    let message = value
+        .get("message")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match kind {
+        "idle_prompt" => HookEvent::IdlePrompt,
+        "permission_prompt" | "ToolPermission" => HookEvent::PermissionPrompt,
+        "" if message.contains("waiting for your input") => HookEvent::IdlePrompt,
+        "" if message.contains("permission") => HookEvent::PermissionPrompt,
+        _ => HookEvent::Notification,
+    }
+}
+
+fn is_dangerous_command(command: &str) -> bool {
+    let normalized = command.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    DANGEROUS_PATTERNS
+        .iter()
+        .any(|p| normalized.contains(&p.to_lowercase()))
+}
+
+/// Parses `--events` names.
+pub fn parse_names(names: &[String]) -> Result<Vec<HookEvent>, String> {
+    names
+        .iter()
+        .map(|name| {
+            HookEvent::from_name(name.trim()).ok_or_else(|| {
+                let valid: Vec<&str> = HookEvent::ALL.iter().map(|e| e.name()).collect();
+                format!("Unknown event: {} (supported: {})", name, valid.join(", "))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn recognizes_claude_events() {
+        let event = |v: Value| HookEvent::from_payload(&v);
+        assert_eq!(event(json!({ "hook_event_name": "Stop" })), Some(HookEvent::Stop));
+        assert_eq!(event(json!({ "hook_event_name": "SubagentStop" })), Some(HookEvent::SubagentStop));
+        assert_eq!(
+            event(json!({ "hook_event_name": "Notification", "message": "Claude needs your permission to use Bash" })),
+            Some(HookEvent::PermissionPrompt)
+        );
+        assert_eq!(
+            event(json!({ "hook_event_name": "Notification", "message": "Claude is waiting for your input" })),
+            Some(HookEvent::IdlePrompt)
+        );
+        assert_eq!(
+            event(json!({ "hook_event_name": "Notification", "notification_type": "idle_prompt", "message": "" })),
+            Some(HookEvent::IdlePrompt)
+        );
+        assert_eq!(event(json!({ "hook_event_name": "SessionEnd", "reason": "exit" })), Some(HookEvent::SessionEnd));
+        assert_eq!(event(json!({ "hook_event_name": "UserPromptSubmit" })), None);
+    }
+
+    #[test]
+    fn unknown_notifications_are_not_permission_prompts() {
+        let event = |v: Value| HookEvent::from_payload(&v);
+        assert_eq!(
+            event(json!({ "hook_event_name": "Notification", "notification_type": "auth_success", "message": "Logged in" })),
+            Some(HookEvent::Notification)
+        );
+        assert_eq!(
+            event(json!({ "hook_event_name": "Notification", "notification_type": "something_new", "message": "" })),
+            Some(HookEvent::Notification)
+        );
+        assert_eq!(
+            event(json!({ "hook_event_name": "Notification", "message": "Claude Code update available" })),
+            Some(HookEvent::Notification)
+        );
+        assert_eq!(event(json!({ "hook_event_name": "Notification" })), Some(HookEvent::Notification));
+    }
+
+    #[test]
+    fn pre_tool_use_only_notifies_for_dangerous_commands() {
+        let pre = |cmd: &str| {
+            HookEvent::from_payload(&json!({
+                "hook_event_name": "PreToolUse",
+                "tool_name": "Bash",
+                "tool_input": { "command": cmd }
+            }))
+        };
+        assert_eq!(pre("cargo test"), None);
+        assert_eq!(pre("rm  -rf target"), Some(HookEvent::DangerousCommand));
+        assert_eq!(pre("git push --force origin main"), Some(HookEvent::DangerousCommand));
+        assert_eq!(pre("curl https://x.sh | sh"), Some(HookEvent::DangerousCommand));
+    }
+
+    #[test]
+    fn other_agents_default_to_stop() {
+        assert_eq!(HookEvent::from_payload(&json!({ "type": "agent-turn-complete" })), Some(HookEvent::Stop));
+        assert_eq!(HookEvent::from_payload(&json!({ "type": "other" })), None);
+        assert_eq!(HookEvent::from_payload(&json!({ "status": "completed" })), Some(HookEvent::Stop));
+        assert_eq!(HookEvent::from_payload(&json!({})), Some(HookEvent::Stop));
+    }
+
+    #[test]
+    fn parses_event_names() {
+        let names = vec!["stop".to_string(), "session_end".to_string()];
+        assert_eq!(parse_names(&names).unwrap(), [HookEvent::Stop, HookEvent::SessionEnd]);
+        assert!(parse_names(&["nope".to_string()]).is_err());
+    }
+}
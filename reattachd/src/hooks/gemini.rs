@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+
+use super::{
+    ensure_event_hook, has_event_hook, json_settings_string, load_json_settings, notify_command,
+    object_entry, parse_json_settings, prune_event_hook, read_existing, AgentIntegration, HookEvent, Plan, Scope,
+};
+
+/// Gemini CLI: `hooks` in `~/.gemini/settings.json`, which follows the Claude
+/// Code hook schema with Gemini's event names.
+pub struct Gemini;
+
+/// Gemini events and the [`HookEvent`] each one delivers.
+const EVENTS: &[(&str, HookEvent)] = &[
+    ("AfterAgent", HookEvent::Stop),
+    ("Notification", HookEvent::PermissionPrompt),
+];
+
+impl AgentIntegration for Gemini {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Gemini CLI"
+    }
+
+    fn detect(&self) -> bool {
+        super::detect_agent("gemini", ".gemini")
+    }
+
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf> {
+        scope.file(".gemini/settings.json", ".gemini/settings.json", None)
+    }
+
+    fn is_hooked(&self, scope: &Scope) -> bool {
+        let Some(root) = self.config_path(scope).and_then(|p| load_json_settings(&p)) else {
+            return false;
+        };
+        let command = notify_command(self.name());
+        EVENTS.iter().any(|(name, _)| has_event_hook(&root, name, &command))
+    }
+
+    fn plan_install(&self, scope: &Scope, events: &[HookEvent]) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let before = read_existing(&path)?;
+        let mut root = parse_json_settings(&path, before.as_deref())?;
+        let command = notify_command(self.name());
+        let hooks = object_entry(root.as_object_mut().expect("object expected"), "hooks");
+        for (name, event) in EVENTS {
+            if events.contains(event) {
+                ensure_event_hook(hooks, name, "", &command);
+            } else {
+                prune_event_hook(hooks, name, None, &command);
+            }
+        }
+        Ok(Plan::edit(path, before, Some(json_settings_string(&root))))
+    }
+
+    fn plan_uninstall(&self, scope: &Scope) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let Some(before) = read_existing(&path)? else {
+            return Ok(Plan::Skip("No Gemini settings file found".to_string()));
+        };
+        let mut root = parse_json_settings(&path, Some(&before))?;
+        let command = notify_command(self.name());
+        if let Some(hooks) = root.get_mut("hooks").and_then(|v| v.as_object_mut()) {
+            for (name, _) in EVENTS {
+                prune_event_hook(hooks, name, None, &command);
+            }
+        }
+        Ok(Plan::edit(path, Some(before), Some(json_settings_string(&root))))
+    }
+}
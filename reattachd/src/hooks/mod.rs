@@ -0,0 +1,288 @@
+//! Notification hook integrations for coding agents.
+//!
+//! Each supported agent implements [`AgentIntegration`] and is listed in
+//! [`registry`], which is what `reattachd hooks` operates on.
+
+mod aider;
+mod claude;
+mod codex;
+mod copilot;
+mod cursor;
+mod edit;
+mod events;
+mod gemini;
+mod opencode;
+
+use std::path::{Path, PathBuf};
+
+pub use edit::{apply, backups, restore, Plan, BACKUP_DIR};
+pub use events::{parse_names as parse_event_names, HookEvent};
+use edit::read_existing;
+
+pub const HOOK_NOTIFY_COMMAND: &str = "reattachd notify";
+
+/// Where a hook is installed.
+#[derive(Debug, Clone)]
+pub enum Scope {
+    /// The user's home directory.
+    Global,
+    /// A project directory. `local` selects the agent's uncommitted settings
+    /// file, for agents that have one.
+    Project { dir: PathBuf, local: bool },
+}
+
+impl Scope {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Scope::Global => "global",
+            Scope::Project { local: false, .. } => "project",
+            Scope::Project { local: true, .. } => "local",
+        }
+    }
+
+    /// Picks the settings file for this scope: `global` relative to the home
+    /// directory, `project` or `local` relative to the project directory.
+    fn file(&self, global: &str, project: &str, local: Option<&str>) -> Option<PathBuf> {
+        match self {
+            Scope::Global => home_file(global),
+            Scope::Project { dir, local: false } => Some(dir.join(project)),
+            Scope::Project { dir, local: true } => local.map(|l| dir.join(l)),
+        }
+    }
+}
+
+pub trait AgentIntegration {
+    /// Identifier accepted by `--agent`.
+    fn name(&self) -> &'static str;
+    fn display_name(&self) -> &'static str;
+    /// Whether the agent appears to be installed on this machine.
+    fn detect(&self) -> bool;
+    /// File the hook lives in for `scope`, or `None` if the agent has no
+    /// settings file there.
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf>;
+    /// Whether the Reattach hook is currently present in `scope`.
+    fn is_hooked(&self, scope: &Scope) -> bool;
+    /// Works out the file change that installs the hook for `events`, for
+    /// agents that can tell them apart.
+    fn plan_install(&self, scope: &Scope, events: &[HookEvent]) -> Result<Plan, String>;
+    /// Works out the file change that removes the hook.
+    fn plan_uninstall(&self, scope: &Scope) -> Result<Plan, String>;
+
+    fn supports(&self, scope: &Scope) -> bool {
+        self.config_path(scope).is_some()
+    }
+
+    fn path_for(&self, scope: &Scope) -> Result<PathBuf, String> {
+        self.config_path(scope).ok_or_else(|| match scope {
+            Scope::Global => format!("Failed to resolve home directory for {} settings", self.display_name()),
+            Scope::Project { .. } => format!("{} has no {} settings file", self.display_name(), scope.label()),
+        })
+    }
+}
+
+pub fn registry() -> Vec<Box<dyn AgentIntegration>> {
+    vec![
+        Box::new(claude::Claude),
+        Box::new(codex::Codex),
+        Box::new(gemini::Gemini),
+        Box::new(cursor::Cursor),
+        Box::new(aider::Aider),
+        Box::new(opencode::OpenCode),
+        Box::new(copilot::Copilot),
+    ]
+}
+
+pub fn find(name: &str) -> Option<Box<dyn AgentIntegration>> {
+    registry().into_iter().find(|a| a.name() == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    registry().iter().map(|a| a.name()).collect()
+}
+
+/// Command an agent's hook runs; tagged with the agent name so that
+/// `reattachd notify` can title notifications without a JSON payload.
+fn notify_command(agent: &str) -> String {
+    format!("{} --agent {}", HOOK_NOTIFY_COMMAND, agent)
+}
+
+fn home_file(path: &str) -> Option<PathBuf> {
+    let mut home = dirs::home_dir()?;
+    home.push(path);
+    Some(home)
+}
+
+/// Whether `bin` is an executable on `PATH`.
+fn which(bin: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(bin).is_file()))
+        .unwrap_or(false)
+}
+
+fn detect_agent(bin: &str, config_dir: &str) -> bool {
+    which(bin) || home_file(config_dir).is_some_and(|p| p.is_dir())
+}
+
+/// Parses a JSON settings file. A missing or empty file is an empty object;
+/// anything else that isn't a JSON object is refused rather than replaced.
+fn parse_json_settings(path: &Path, content: Option<&str>) -> Result<serde_json::Value, String> {
+    let content = content.unwrap_or("");
+    if content.trim().is_empty() {
+        return Ok(serde_json::json!({}));
+    }
+    let root: serde_json::Value = serde_json::from_str(content)
+        .map_err(|e| format!("Refusing to modify {}: not valid JSON ({})", path.display(), e))?;
+    if !root.is_object() {
+        return Err(format!("Refusing to modify {}: expected a JSON object", path.display()));
+    }
+    Ok(root)
+}
+
+/// Reads and parses a JSON settings file, or `None` if it can't be.
+fn load_json_settings(path: &Path) -> Option<serde_json::Value> {
+    let content = read_existing(path).ok()??;
+    parse_json_settings(path, Some(&content)).ok()
+}
+
+fn json_settings_string(root: &serde_json::Value) -> String {
+    format!(
+        "{}\n",
+        serde_json::to_string_pretty(root).expect("JSON values always serialize")
+    )
+}
+
+/// Returns the object stored under `key`, replacing anything that is not an
+/// object.
+fn object_entry<'a>(
+    obj: &'a mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+) -> &'a mut serde_json::Map<String, serde_json::Value> {
+    let value = obj.entry(key.to_string()).or_insert_with(|| serde_json::json!({}));
+    if !value.is_object() {
+        *value = serde_json::json!({});
+    }
+    value.as_object_mut().expect("object expected")
+}
+
+fn is_command_hook(hook: &serde_json::Value, command: &str) -> bool {
+    hook.get("type").and_then(|v| v.as_str()) == Some("command")
+        && hook.get("command").and_then(|v| v.as_str()) == Some(command)
+}
+
+fn entry_has_command(entry: &serde_json::Value, command: &str) -> bool {
+    entry
+        .get("hooks")
+        .and_then(|v| v.as_array())
+        .map(|arr| arr.iter().any(|h| is_command_hook(h, command)))
+        .unwrap_or(false)
+}
+
+/// Adds a `{matcher, hooks: [{type: command, command}]}` entry to a Claude
+/// Code style hooks object unless one already exists.
+fn ensure_event_hook(
+    hooks_obj: &mut serde_json::Map<String, serde_json::Value>,
+    event_name: &str,
+    matcher: &str,
+    command: &str,
+) {
+    let event = hooks_obj
+        .entry(event_name.to_string())
+        .or_insert_with(|| serde_json::json!([]));
+    if !event.is_array() {
+        *event = serde_json::json!([]);
+    }
+    let event_arr = event.as_array_mut().expect("array expected");
+
+    let has_entry = event_arr.iter().any(|entry| {
+        entry.get("matcher").and_then(|v| v.as_str()) == Some(matcher) && entry_has_command(entry, command)
+    });
+
+    if !has_entry {
+        event_arr.push(serde_json::json!({
+            "matcher": matcher,
+            "hooks": [{
+                "type": "command",
+                "command": command,
+                "timeout": 10
+            }]
+        }));
+    }
+}
+
+/// Removes entries running `command` from an event, limited to `matcher`
+/// if given.
+fn prune_event_hook(
+    hooks_obj: &mut serde_json::Map<String, serde_json::Value>,
+    event_name: &str,
+    matcher: Option<&str>,
+    command: &str,
+) {
+    let Some(event) = hooks_obj.get_mut(event_name) else {
+        return;
+    };
+    let Some(event_arr) = event.as_array_mut() else {
+        return;
+    };
+
+    event_arr.retain(|entry| {
+        let matches = matcher.is_none_or(|m| entry.get("matcher").and_then(|v| v.as_str()).unwrap_or("") == m);
+        !(matches && entry_has_command(entry, command))
+    });
+}
+
+fn has_event_hook(root: &serde_json::Value, event_name: &str, command: &str) -> bool {
+    root.pointer(&format!("/hooks/{}", event_name))
+        .and_then(|v| v.as_array())
+        .is_some_and(|arr| arr.iter().any(|entry| entry_has_command(entry, command)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_names_are_unique() {
+        let mut names = names();
+        let len = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), len);
+        assert!(find("claude").is_some());
+        assert!(find("nope").is_none());
+    }
+
+    #[test]
+    fn project_scopes_resolve_inside_the_project() {
+        let project = Scope::Project {
+            dir: PathBuf::from("/repo"),
+            local: false,
+        };
+        let local = Scope::Project {
+            dir: PathBuf::from("/repo"),
+            local: true,
+        };
+        let claude = find("claude").unwrap();
+        assert_eq!(claude.config_path(&project), Some(PathBuf::from("/repo/.claude/settings.json")));
+        assert_eq!(claude.config_path(&local), Some(PathBuf::from("/repo/.claude/settings.local.json")));
+
+        let codex = find("codex").unwrap();
+        assert_eq!(codex.config_path(&project), Some(PathBuf::from("/repo/.codex/config.toml")));
+        assert!(!codex.supports(&local));
+        assert!(codex.path_for(&local).is_err());
+    }
+
+    #[test]
+    fn event_hooks_are_idempotent_and_prunable() {
+        let mut root = serde_json::json!({ "hooks": { "Stop": [{ "matcher": "", "hooks": [{ "type": "command", "command": "other" }] }] } });
+        let hooks = object_entry(root.as_object_mut().unwrap(), "hooks");
+        ensure_event_hook(hooks, "Stop", "", HOOK_NOTIFY_COMMAND);
+        ensure_event_hook(hooks, "Stop", "", HOOK_NOTIFY_COMMAND);
+        assert_eq!(root["hooks"]["Stop"].as_array().unwrap().len(), 2);
+        assert!(has_event_hook(&root, "Stop", HOOK_NOTIFY_COMMAND));
+
+        let hooks = object_entry(root.as_object_mut().unwrap(), "hooks");
+        prune_event_hook(hooks, "Stop", None, HOOK_NOTIFY_COMMAND);
+        assert!(!has_event_hook(&root, "Stop", HOOK_NOTIFY_COMMAND));
+        assert!(has_event_hook(&root, "Stop", "other"));
+    }
+}
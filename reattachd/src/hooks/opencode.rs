@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use super::{notify_command, read_existing, AgentIntegration, HookEvent, Plan, Scope};
+
+/// opencode: a plugin in `~/.config/opencode/plugin/` that runs on
+/// `session.idle`.
+pub struct OpenCode;
+
+const PLUGIN_MARKER: &str = "// Reattach push notification hook";
+
+impl OpenCode {
+    fn plugin_source(&self) -> String {
+        format!(
+            r#"{marker} (managed by `reattachd hooks install`)
+export const ReattachPlugin = async ({{ $ }}) => ({{
+  event: async ({{ event }}) => {{
+    if (event.type === "session.idle") {{
+      await $`{command}`.quiet().nothrow();
+    }}
+  }},
+}});
+"#,
+            marker = PLUGIN_MARKER,
+            command = notify_command(self.name())
+        )
+    }
+}
+
+impl AgentIntegration for OpenCode {
+    fn name(&self) -> &'static str {
+        "opencode"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "opencode"
+    }
+
+    fn detect(&self) -> bool {
+        super::detect_agent("opencode", ".config/opencode")
+    }
+
+    fn config_path(&self, scope: &Scope) -> Option<PathBuf> {
+        scope.file(".config/opencode/plugin/reattach.js", ".opencode/plugin/reattach.js", None)
+    }
+
+    fn is_hooked(&self, scope: &Scope) -> bool {
+        self.config_path(scope)
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .is_some_and(|s| s.starts_with(PLUGIN_MARKER))
+    }
+
+    fn plan_install(&self, scope: &Scope, _events: &[HookEvent]) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        let before = read_existing(&path)?;
+        if before.as_deref().is_some_and(|s| !s.starts_with(PLUGIN_MARKER)) {
+            return Ok(Plan::Skip(format!(
+                "Skipped opencode update: {} exists and was not created by Reattach",
+                path.display()
+            )));
+        }
+        Ok(Plan::edit(path, before, Some(self.plugin_source())))
+    }
+
+    fn plan_uninstall(&self, scope: &Scope) -> Result<Plan, String> {
+        let path = self.path_for(scope)?;
+        if !self.is_hooked(scope) {
+            return Ok(Plan::Skip("No Reattach plugin found for opencode".to_string()));
+        }
+        let before = read_existing(&path)?;
+        Ok(Plan::edit(path, before, None))
+    }
+}
@@ -0,0 +1,398 @@
+//! Everything one daemon fronts: its own tmux servers (see [`tmux::Server`])
+//! and peers, other reattachd daemons whose panes it proxies. A peer's ID
+//! qualifies targets on it the way a server ID does, so `mac::dev:0.1` is
+//! pane `dev:0.1` on the peer `mac`, and `mac::work::dev:0.1` the same pane
+//! on that peer's `work` server.
+//!
+//! Peers are added with `reattachd peers add`, which registers this daemon
+//! with the peer like a device and keeps the device token in `peers.json` in
+//! the data dir.
+
+use axum::{
+    body::{to_bytes, Bytes},
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::tmux;
+
+pub const PEERS_FILE: &str = "peers.json";
+/// Marks requests one daemon makes to another. `GET /sessions` with it lists
+/// only the peer's own servers, so that two daemons that are each other's
+/// peers don't list each other's sessions back and forth.
+pub const PROXIED_HEADER: &str = "x-reattachd-proxied";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_FORWARDED_BODY: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerConfig {
+    pub id: String,
+    pub url: String,
+    pub device_token: String,
+    /// CA certificate to trust for the peer, e.g. its `self-signed/ca.crt`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<PathBuf>,
+}
+
+pub fn peers_path(data_dir: &FsPath) -> PathBuf {
+    data_dir.join(PEERS_FILE)
+}
+
+/// Peers carry device tokens, so the file must not be readable by other
+/// users.
+pub fn load_peers(data_dir: &FsPath) -> Result<Vec<PeerConfig>, String> {
+    let path = peers_path(data_dir);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = crate::fsutil::read_private(&path)?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+}
+
+pub fn save_peers(data_dir: &FsPath, peers: &[PeerConfig]) -> Result<(), String> {
+    let content = serde_json::to_vec_pretty(peers).map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(data_dir).map_err(|e| format!("Failed to create {}: {}", data_dir.display(), e))?;
+    crate::fsutil::write_private(&peers_path(data_dir), &content)
+}
+
+fn http_client(ca_cert: Option<&FsPath>) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+    if let Some(path) = ca_cert {
+        let pem = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let cert = reqwest::Certificate::from_pem(&pem).map_err(|e| format!("{}: {}", path.display(), e))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+#[derive(Deserialize)]
+struct Registered {
+    device_token: String,
+    #[serde(default)]
+    client_identity: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Registers this daemon with the peer at `url` using a setup token from
+/// `reattachd setup` there, and returns the device token it hands out.
+pub async fn register(
+    url: &str,
+    setup_token: &str,
+    device_name: &str,
+    ca_cert: Option<&FsPath>,
+) -> Result<String, String> {
+    let response = http_client(ca_cert)?
+        .post(format!("{}/register", url))
+        .json(&serde_json::json!({
+            "setup_token": setup_token,
+            "device_name": device_name,
+        }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach {}: {}", url, e))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let error = response.json::<ErrorBody>().await.map(|body| body.error);
+        return Err(format!(
+            "{} refused the registration (HTTP {}): {}",
+            url,
+            status.as_u16(),
+            error.as_deref().unwrap_or("no reason")
+        ));
+    }
+    let registered: Registered = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid registration response from {}: {}", url, e))?;
+    if registered.client_identity.is_some() {
+        return Err(format!(
+            "{} requires client certificates (--mtls), which peers don't support",
+            url
+        ));
+    }
+    Ok(registered.device_token)
+}
+
+#[derive(Clone)]
+pub struct Peer {
+    pub id: String,
+    pub url: String,
+    device_token: String,
+    http: reqwest::Client,
+}
+
+impl Peer {
+    pub fn new(config: PeerConfig) -> Result<Self, String> {
+        Ok(Self {
+            http: http_client(config.ca_cert.as_deref())?,
+            id: config.id,
+            url: config.url,
+            device_token: config.device_token,
+        })
+    }
+
+    fn request(&self, method: Method, url: reqwest::Url) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, url)
+            .bearer_auth(&self.device_token)
+            .header(PROXIED_HEADER, "1")
+    }
+
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url, String> {
+        let mut url = reqwest::Url::parse(&self.url).map_err(|e| format!("{}: {}", self.url, e))?;
+        url.path_segments_mut()
+            .map_err(|_| format!("{} can't have a path", self.url))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// GETs the path made of `segments` from the peer and parses the JSON answer.
+    pub async fn get<T: DeserializeOwned>(&self, segments: &[&str]) -> Result<T, String> {
+        let response = self
+            .request(Method::GET, self.url(segments)?)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status().as_u16()));
+        }
+        response.json().await.map_err(|e| e.to_string())
+    }
+
+    /// Sends a request to the peer and passes its answer on as it is.
+    pub async fn forward(
+        &self,
+        method: Method,
+        segments: &[&str],
+        query: Option<&str>,
+        content_type: Option<&HeaderValue>,
+        body: Bytes,
+    ) -> Response {
+        let mut url = match self.url(segments) {
+            Ok(url) => url,
+            Err(e) => return peer_error(&self.id, e),
+        };
+        url.set_query(query);
+        let mut request = self.request(method, url).body(body);
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => return peer_error(&self.id, e),
+        };
+        let status = response.status();
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        match response.bytes().await {
+            Ok(body) => {
+                let mut response = (status, body).into_response();
+                if let Some(content_type) = content_type {
+                    response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+                }
+                response
+            }
+            Err(e) => peer_error(&self.id, e),
+        }
+    }
+}
+
+fn peer_error(id: &str, error: impl std::fmt::Display) -> Response {
+    tracing::warn!("Peer {} failed: {}", id, error);
+    let body = serde_json::json!({ "error": format!("Peer {} is not reachable: {}", id, error) });
+    (StatusCode::BAD_GATEWAY, axum::Json(body)).into_response()
+}
+
+/// Where a server ID from a client leads.
+pub enum Route<'a> {
+    Local(&'a tmux::Server),
+    /// A peer, and the server ID to pass on to it (`None` for its default).
+    Peer(&'a Peer, Option<&'a str>),
+}
+
+pub struct Hosts {
+    pub servers: tmux::Servers,
+    peers: Vec<Peer>,
+}
+
+pub type SharedHosts = Arc<Hosts>;
+
+impl Hosts {
+    /// Peers whose ID is taken by a local server are left out, since their
+    /// targets would be ambiguous.
+    pub fn new(servers: tmux::Servers, peers: Vec<Peer>) -> Self {
+        let peers = peers
+            .into_iter()
+            .filter(|peer| {
+                let taken = servers.get(&peer.id).is_some();
+                if taken {
+                    tracing::warn!("Ignoring peer {}: a tmux server has the same ID", peer.id);
+                }
+                !taken
+            })
+            .collect();
+        Self { servers, peers }
+    }
+
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    pub fn route<'a>(&'a self, server_id: &'a str) -> Option<Route<'a>> {
+        if let Some(server) = self.servers.get(server_id) {
+            return Some(Route::Local(server));
+        }
+        let (id, rest) = match server_id.split_once(tmux::SEPARATOR) {
+            Some((id, rest)) => (id, Some(rest)),
+            None => (server_id, None),
+        };
+        self.peers
+            .iter()
+            .find(|peer| peer.id == id)
+            .map(|peer| Route::Peer(peer, rest))
+    }
+}
+
+/// Middleware for the `/panes/{target}` routes that forwards requests for a
+/// peer's panes to the peer, with the peer's ID taken off the target.
+pub async fn proxy(
+    State(hosts): State<SharedHosts>,
+    Path(target): Path<String>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (Some(id), rest) = tmux::split_target(&target) else {
+        return next.run(request).await;
+    };
+    let Some(peer) = hosts.peers.iter().find(|peer| peer.id == id) else {
+        return next.run(request).await;
+    };
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_FORWARDED_BODY).await {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response(),
+    };
+    // `/panes/{target}` and whatever follows it.
+    let mut segments = vec!["panes", rest];
+    segments.extend(parts.uri.path().split('/').skip(3));
+    let content_type = parts.headers.get(header::CONTENT_TYPE);
+    peer.forward(parts.method.clone(), &segments, parts.uri.query(), content_type, body)
+        .await
+}
+
+/// Whether another daemon sent the request on a client's behalf.
+pub fn is_proxied(headers: &HeaderMap) -> bool {
+    headers.contains_key(PROXIED_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, router).await;
+        });
+        url
+    }
+
+    fn peer(id: &str, url: &str) -> Peer {
+        Peer::new(PeerConfig {
+            id: id.to_string(),
+            url: url.to_string(),
+            device_token: "peer-token".to_string(),
+            ca_cert: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn saves_peers_for_the_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        assert!(load_peers(data_dir).unwrap().is_empty());
+        let mac = PeerConfig {
+            id: "mac".to_string(),
+            url: "https://mac.local:8787".to_string(),
+            device_token: "peer-token".to_string(),
+            ca_cert: None,
+        };
+        save_peers(data_dir, std::slice::from_ref(&mac)).unwrap();
+        let path = peers_path(data_dir);
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(load_peers(data_dir).unwrap()[0].device_token, "peer-token");
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(load_peers(data_dir).unwrap_err().contains("chmod 600"));
+        save_peers(data_dir, &[mac]).unwrap();
+        assert_eq!(load_peers(data_dir).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn forwards_requests_for_peer_panes() {
+        // The peer echoes what it got.
+        let remote = Router::new().route(
+            "/panes/{target}/output",
+            get(|Path(target): Path<String>, request: Request| async move {
+                let headers = request.headers();
+                let authorization = headers.get(header::AUTHORIZATION).unwrap().to_str().unwrap();
+                let proxied = is_proxied(headers);
+                let query = request.uri().query().unwrap_or_default();
+                (StatusCode::ACCEPTED, format!("{} {} {} {}", target, query, authorization, proxied))
+            }),
+        );
+        let remote_url = serve(remote).await;
+
+        let servers = tmux::Servers::new(vec![tmux::Server::parse("work").unwrap()]);
+        let peers = vec![
+            peer("mac", &format!("{}/", remote_url)),
+            peer("work", &remote_url),
+            peer("gone", "http://127.0.0.1:1"),
+        ];
+        let hosts = Arc::new(Hosts::new(servers, peers));
+        // A local server's ID wins over a peer's.
+        assert_eq!(hosts.peers().iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["mac", "gone"]);
+        assert!(matches!(hosts.route("work"), Some(Route::Local(server)) if server.id == "work"));
+        assert!(matches!(hosts.route("mac::work"), Some(Route::Peer(peer, Some("work"))) if peer.id == "mac"));
+        assert!(matches!(hosts.route("mac"), Some(Route::Peer(_, None))));
+        assert!(hosts.route("nowhere").is_none());
+
+        let front = Router::new()
+            .route(
+                "/panes/{target}/output",
+                get(|Path(target): Path<String>| async move { format!("local {}", target) }),
+            )
+            .route_layer(middleware::from_fn_with_state(hosts, proxy));
+        let front_url = serve(front).await;
+        let http = reqwest::Client::new();
+
+        let response = http
+            .get(format!("{}/panes/mac::work::dev:0.1/output?lines=5", front_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.text().await.unwrap(), "work::dev:0.1 lines=5 Bearer peer-token true");
+
+        let response = http.get(format!("{}/panes/work::dev:0.1/output", front_url)).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "local work::dev:0.1");
+
+        let response = http.get(format!("{}/panes/gone::dev:0.1/output", front_url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+}
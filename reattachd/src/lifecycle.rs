@@ -0,0 +1,153 @@
+//! Signal handling for the daemon. SIGTERM and SIGINT stop it after open
+//! requests finish; SIGHUP reloads the configuration, TLS certificate and
+//! device stores from disk.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinSet;
+
+use crate::api::OutputLimits;
+use crate::apns::ApnsService;
+use crate::auth::SharedAuthService;
+use crate::config::{self, Config};
+
+pub enum Signal {
+    Terminate,
+    Reload,
+}
+
+pub struct Signals {
+    terminate: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    pub async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.terminate.recv() => Signal::Terminate,
+            _ = self.interrupt.recv() => Signal::Terminate,
+            _ = self.hangup.recv() => Signal::Reload,
+        }
+    }
+}
+
+/// Completes when the daemon starts shutting down; servers stop accepting
+/// connections then and finish the open ones.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// The sender starts the shutdown.
+    pub fn channel() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self(receiver))
+    }
+
+    pub async fn wait(mut self) {
+        // A dropped sender also means shutdown.
+        let _ = self.0.wait_for(|&stopping| stopping).await;
+    }
+}
+
+/// What SIGHUP reloads.
+pub struct Reloader {
+    pub config: Config,
+    pub auth: SharedAuthService,
+    pub apns: Option<Arc<ApnsService>>,
+    /// Wakes the TLS watcher, if the daemon serves TLS.
+    pub tls: Option<Arc<Notify>>,
+    pub output: OutputLimits,
+}
+
+impl Reloader {
+    /// Applies what it can; anything that fails to load is logged and the
+    /// current state kept.
+    pub async fn reload(&mut self) {
+        tracing::info!("Reloading configuration and stores");
+        match self.config.reload() {
+            Ok(config) => {
+                for key in self.config.changed(&config) {
+                    if !config::RELOADABLE.contains(&key) {
+                        tracing::warn!("{} changed; restart reattachd to apply it", key);
+                    }
+                }
+                self.output.max_lines.store(config.output_max_lines, Ordering::Relaxed);
+                self.config = config;
+            }
+            Err(errors) => {
+                for error in errors {
+                    tracing::error!("Invalid configuration: {}", error);
+                }
+                tracing::warn!("Keeping the current configuration");
+            }
+        }
+
+        match self.auth.reload().await {
+            Ok(()) => tracing::info!("Reloaded {} devices", self.auth.list_devices().await.len()),
+            Err(e) => tracing::error!("{}; keeping the current devices", e),
+        }
+        if let Some(ref apns) = self.apns {
+            match apns.reload(self.config.notify_max_bytes, self.config.notify_require_encryption).await {
+                Ok(count) => tracing::info!("Reloaded {} push tokens", count),
+                Err(e) => tracing::error!("{}; keeping the current push tokens", e),
+            }
+        }
+        if let Some(ref tls) = self.tls {
+            tls.notify_one();
+        }
+    }
+}
+
+/// Runs until a server fails, which is an error, or a SIGTERM/SIGINT, after
+/// which the servers get `drain_timeout` to finish open requests. A second
+/// SIGTERM/SIGINT stops waiting.
+pub async fn supervise(
+    mut servers: JoinSet<Result<(), String>>,
+    mut signals: Signals,
+    mut reloader: Reloader,
+    stop: watch::Sender<bool>,
+    drain_timeout: Duration,
+) -> Result<(), String> {
+    loop {
+        tokio::select! {
+            Some(result) = servers.join_next() => {
+                return Err(match result {
+                    Ok(Err(e)) => e,
+                    Ok(Ok(())) => "a listener stopped unexpectedly".to_string(),
+                    Err(e) => e.to_string(),
+                });
+            }
+            signal = signals.recv() => match signal {
+                Signal::Reload => reloader.reload().await,
+                Signal::Terminate => break,
+            },
+        }
+    }
+
+    tracing::info!("Shutting down; waiting up to {}s for open requests", drain_timeout.as_secs());
+    let _ = stop.send(true);
+    let drained = async { while servers.join_next().await.is_some() {} };
+    let terminated = async {
+        while let Signal::Reload = signals.recv().await {}
+    };
+    tokio::select! {
+        _ = drained => tracing::info!("All requests finished"),
+        _ = tokio::time::sleep(drain_timeout) => {
+            tracing::warn!("Requests still open after {}s; exiting anyway", drain_timeout.as_secs());
+        }
+        _ = terminated => tracing::warn!("Signalled again; exiting without waiting for open requests"),
+    }
+    Ok(())
+}
@@ -0,0 +1,250 @@
+//! Listeners for the API, given as `--listen` specs:
+//!
+//! - `https://100.64.0.1:8787` or `https://[fd7a:115c::1]:8787` — TLS with
+//!   the daemon's certificate
+//! - `http://127.0.0.1:8787` — plain HTTP, e.g. for a Cloudflare tunnel
+//! - `unix:/run/reattachd/api.sock` — Unix socket
+//!
+//! A `?routes=/healthz,/sessions` suffix limits a listener to those path
+//! prefixes; other paths get 404 there. The port defaults to the daemon
+//! port (`server.port`).
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::lifecycle::Shutdown;
+use crate::mtls::ClientCertAcceptor;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+    Tcp { addr: SocketAddr, tls: bool },
+    Unix(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub transport: Transport,
+    /// Path prefixes served on this listener; `None` serves everything.
+    pub routes: Option<Vec<String>>,
+}
+
+impl Listener {
+    /// Parses a `--listen` spec, using `default_port` where it has none.
+    pub fn parse(spec: &str, default_port: u16) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("Invalid listener '{}': {}", spec, reason);
+
+        let (address, routes) = match spec.split_once('?') {
+            Some((address, query)) => {
+                let routes = query
+                    .strip_prefix("routes=")
+                    .ok_or_else(|| invalid("the only option is ?routes="))?;
+                let routes: Vec<String> = routes
+                    .split(',')
+                    .filter(|route| !route.is_empty())
+                    .map(|route| route.trim_end_matches('/').to_string())
+                    .collect();
+                if routes.iter().any(|route| !route.starts_with('/')) {
+                    return Err(invalid("routes must start with /"));
+                }
+                (address, Some(routes))
+            }
+            None => (spec, None),
+        };
+
+        let transport = if let Some(path) = address.strip_prefix("unix:") {
+            let path = path.trim_start_matches("//");
+            if path.is_empty() {
+                return Err(invalid("missing socket path"));
+            }
+            Transport::Unix(PathBuf::from(path))
+        } else {
+            let (tls, host_port) = if let Some(rest) = address.strip_prefix("https://") {
+                (true, rest)
+            } else if let Some(rest) = address.strip_prefix("http://") {
+                (false, rest)
+            } else {
+                return Err(invalid("expected http://, https:// or unix:"));
+            };
+            let host_port = host_port.trim_end_matches('/');
+            // A host without a port, including an IPv6 address with or
+            // without brackets, gets the default.
+            let host_port = match host_port.rsplit_once(':') {
+                Some((host, port))
+                    if !host.is_empty()
+                        && (!host.contains(':') || host.ends_with(']'))
+                        && port.parse::<u16>().is_ok() =>
+                {
+                    host_port.to_string()
+                }
+                _ if host_port.starts_with('[') || !host_port.contains(':') => {
+                    format!("{}:{}", host_port, default_port)
+                }
+                _ => format!("[{}]:{}", host_port, default_port),
+            };
+            let addr = host_port
+                .to_socket_addrs()
+                .map_err(|e| invalid(&e.to_string()))?
+                .next()
+                .ok_or_else(|| invalid("host has no address"))?;
+            Transport::Tcp { addr, tls }
+        };
+
+        Ok(Self { transport, routes })
+    }
+
+    /// Wraps `app` so that only this listener's routes are reachable.
+    fn restrict(&self, app: Router) -> Router {
+        match self.routes {
+            Some(ref routes) => app.layer(middleware::from_fn_with_state(Arc::new(routes.clone()), restrict_routes)),
+            None => app,
+        }
+    }
+
+    /// Serves `app` until the listener fails, or until `shutdown` and the open
+    /// connections have finished. `tls` is required for `https://` listeners.
+    pub async fn serve(self, app: Router, tls: Option<RustlsConfig>, shutdown: Shutdown) -> Result<(), String> {
+        let app = self.restrict(app);
+        let error = |e: std::io::Error| format!("{}: {}", self, e);
+        match self.transport {
+            Transport::Tcp { addr, tls: true } => {
+                let config = tls.ok_or_else(|| format!("{} needs a TLS certificate", self))?;
+                tracing::info!("Starting reattachd with TLS on {}", addr);
+                let handle = axum_server::Handle::new();
+                tokio::spawn({
+                    let handle = handle.clone();
+                    async move {
+                        shutdown.wait().await;
+                        handle.graceful_shutdown(None);
+                    }
+                });
+                axum_server::bind(addr)
+                    .handle(handle)
+                    .acceptor(ClientCertAcceptor::new(config))
+                    .serve(app.into_make_service())
+                    .await
+                    .map_err(error)
+            }
+            Transport::Tcp { addr, tls: false } => {
+                tracing::info!("Starting reattachd on {}", addr);
+                let listener = tokio::net::TcpListener::bind(addr).await.map_err(error)?;
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown.wait())
+                    .await
+                    .map_err(error)
+            }
+            Transport::Unix(ref path) => {
+                tracing::info!("Starting reattachd on {}", path.display());
+                let listener = crate::local::bind(path).await.map_err(error)?;
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown.wait())
+                    .await
+                    .map_err(error)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.transport {
+            Transport::Tcp { addr, tls } => write!(f, "{}://{}", if tls { "https" } else { "http" }, addr)?,
+            Transport::Unix(ref path) => write!(f, "unix:{}", path.display())?,
+        }
+        if let Some(ref routes) = self.routes {
+            write!(f, "?routes={}", routes.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+fn route_allowed(routes: &[String], path: &str) -> bool {
+    routes.iter().any(|route| {
+        path.strip_prefix(route.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+async fn restrict_routes(State(routes): State<Arc<Vec<String>>>, request: Request, next: Next) -> Response {
+    if route_allowed(&routes, request.uri().path()) {
+        next.run(request).await
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listener_specs() {
+        let tcp = |spec: &str| match Listener::parse(spec, 8787).unwrap().transport {
+            Transport::Tcp { addr, tls } => (addr.to_string(), tls),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(tcp("https://100.64.0.1:9000"), ("100.64.0.1:9000".into(), true));
+        assert_eq!(tcp("http://127.0.0.1"), ("127.0.0.1:8787".into(), false));
+        assert_eq!(tcp("https://[fd7a:115c::1]:443/"), ("[fd7a:115c::1]:443".into(), true));
+        assert_eq!(tcp("https://[::1]"), ("[::1]:8787".into(), true));
+        assert_eq!(tcp("http://::1"), ("[::1]:8787".into(), false));
+
+        let unix = Listener::parse("unix:/run/reattachd/api.sock?routes=/healthz,/sessions/", 8787).unwrap();
+        assert_eq!(unix.transport, Transport::Unix("/run/reattachd/api.sock".into()));
+        assert_eq!(unix.routes, Some(vec!["/healthz".to_string(), "/sessions".to_string()]));
+        assert_eq!(unix.to_string(), "unix:/run/reattachd/api.sock?routes=/healthz,/sessions");
+
+        assert!(Listener::parse("ftp://127.0.0.1", 8787).is_err());
+        assert!(Listener::parse("http://127.0.0.1?only=/x", 8787).is_err());
+        assert!(Listener::parse("unix:", 8787).is_err());
+    }
+
+    #[tokio::test]
+    async fn finishes_open_requests_on_shutdown() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let socket = crate::local::socket_path(data_dir);
+        let listener = Listener::parse(&format!("unix:{}", socket.display()), 8787).unwrap();
+        let app = Router::new().route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                "done"
+            }),
+        );
+        let (stop, shutdown) = Shutdown::channel();
+        let server = tokio::spawn(listener.serve(app, None, shutdown));
+        while !socket.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let request = tokio::spawn({
+            let data_dir = data_dir.to_path_buf();
+            async move { crate::local::call(&data_dir, None, axum::http::Method::GET, "/slow", None).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        stop.send(true).unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        server.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn restricts_to_route_prefixes() {
+        let routes = vec!["/panes".to_string(), "/healthz".to_string()];
+        assert!(route_allowed(&routes, "/healthz"));
+        assert!(route_allowed(&routes, "/panes/dev:0.0/output"));
+        assert!(!route_allowed(&routes, "/panesx"));
+        assert!(!route_allowed(&routes, "/sessions"));
+    }
+}
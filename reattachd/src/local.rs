@@ -0,0 +1,216 @@
+//! Local control channel between the CLI and the daemon: a Unix socket in
+//! the data dir that only the owning user can connect to. Unlike the TCP
+//! listener it works whatever the bind address, TLS setup or port, and needs
+//! no device token.
+
+use axum::http::{Method, Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper_util::rt::TokioIo;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::{UnixListener, UnixStream};
+
+/// How long a request may take. `/notify` answers within a few seconds even
+/// when APNs is slow, and agent hooks kill `reattachd notify` after 10.
+const CALL_TIMEOUT: Duration = Duration::from_secs(8);
+
+#[derive(Debug, thiserror::Error)]
+pub enum LocalError {
+    /// Neither the socket nor the TCP port accepted a connection.
+    #[error("reattachd is not running: {0}")]
+    Unreachable(String),
+    #[error("{0}")]
+    Request(String),
+}
+
+pub fn socket_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("reattachd.sock")
+}
+
+/// Binds the control socket, replacing a stale one left by a daemon that
+/// didn't shut down cleanly.
+pub async fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("another reattachd is listening on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    crate::restrict_permissions(path, 0o600);
+    Ok(listener)
+}
+
+/// A daemon response: status and raw body.
+pub struct Response {
+    pub status: StatusCode,
+    pub body: Bytes,
+}
+
+impl Response {
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, LocalError> {
+        serde_json::from_slice(&self.body).map_err(|e| LocalError::Request(format!("Invalid response: {}", e)))
+    }
+}
+
+/// Sends a request to the daemon over the control socket.
+///
+/// With a `fallback_port`, a missing socket means trying
+/// `http://localhost:<port>` instead, for daemons that predate the socket and
+/// serve the route over TCP. Only `/notify` was ever served that way; current
+/// daemons answer it with 404 over TCP, which counts as unreachable too.
+pub async fn call(
+    data_dir: &Path,
+    fallback_port: Option<u16>,
+    method: Method,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<Response, LocalError> {
+    let socket = socket_path(data_dir);
+    let socket_error = match UnixStream::connect(&socket).await {
+        Ok(stream) => {
+            return tokio::time::timeout(CALL_TIMEOUT, over_socket(stream, method, path, body))
+                .await
+                .unwrap_or_else(|_| Err(LocalError::Request(format!("No answer within {:?}", CALL_TIMEOUT))))
+        }
+        Err(e) => e,
+    };
+    let Some(port) = fallback_port else {
+        return Err(LocalError::Unreachable(format!("{}: {}", socket.display(), socket_error)));
+    };
+
+    let url = format!("http://localhost:{}{}", port, path);
+    let client = reqwest::Client::builder()
+        .timeout(CALL_TIMEOUT)
+        .build()
+        .map_err(|e| LocalError::Request(e.to_string()))?;
+    let mut request = client.request(method, &url);
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+    match request.send().await {
+        Ok(response) if response.status() == StatusCode::NOT_FOUND => Err(LocalError::Unreachable(format!(
+            "{}: {}; port {} doesn't serve {}",
+            socket.display(),
+            socket_error,
+            port,
+            path
+        ))),
+        Ok(response) => Ok(Response {
+            status: response.status(),
+            body: response
+                .bytes()
+                .await
+                .map_err(|e| LocalError::Request(e.to_string()))?,
+        }),
+        Err(e) if e.is_connect() => Err(LocalError::Unreachable(format!(
+            "{}: {}; port {}: {}",
+            socket.display(),
+            socket_error,
+            port,
+            e
+        ))),
+        Err(e) => Err(LocalError::Request(e.to_string())),
+    }
+}
+
+async fn over_socket(
+    stream: UnixStream,
+    method: Method,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<Response, LocalError> {
+    let error = |e: &dyn std::fmt::Display| LocalError::Request(e.to_string());
+
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|e| error(&e))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::debug!("Control socket connection error: {}", e);
+        }
+    });
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header("Host", "localhost");
+    let content = match body {
+        Some(body) => {
+            request = request.header("Content-Type", "application/json");
+            serde_json::to_vec(body).map_err(|e| error(&e))?
+        }
+        None => Vec::new(),
+    };
+    let request = request
+        .body(Full::new(Bytes::from(content)))
+        .map_err(|e| error(&e))?;
+
+    let response = sender.send_request(request).await.map_err(|e| error(&e))?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| error(&e))?
+        .to_bytes();
+    Ok(Response { status, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+
+    #[tokio::test]
+    async fn calls_daemon_over_socket() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let socket = socket_path(data_dir);
+
+        let listener = bind(&socket).await.unwrap();
+        let app = Router::new().route("/ping", get(|| async { "pong" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let response = call(data_dir, None, Method::GET, "/ping", None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(&response.body[..], b"pong");
+
+        assert!(bind(&socket).await.is_err(), "socket in use is not replaced");
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn reports_unreachable_daemon() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let result = call(data_dir, None, Method::GET, "/ping", None).await;
+        assert!(matches!(result, Err(LocalError::Unreachable(_))));
+        // Port 1 is privileged and never has a daemon on it.
+        let result = call(data_dir, Some(1), Method::GET, "/ping", None).await;
+        assert!(matches!(result, Err(LocalError::Unreachable(_))));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_tcp_only_where_it_serves_the_route() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/notify", axum::routing::post(|| async { "sent" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let response = call(data_dir, Some(port), Method::POST, "/notify", None).await.unwrap();
+        assert_eq!(&response.body[..], b"sent");
+        // A daemon that serves the route only on its socket.
+        let result = call(data_dir, Some(port), Method::GET, "/devices", None).await;
+        assert!(matches!(result, Err(LocalError::Unreachable(_))));
+    }
+}
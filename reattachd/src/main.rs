@@ -1,12 +1,28 @@
 mod api;
 mod apns;
 mod auth;
+mod config;
+mod detect;
+mod fsutil;
+mod hooks;
+mod hosts;
+mod lifecycle;
+mod listen;
+mod local;
+mod mtls;
+mod spool;
+mod summary;
+mod telemetry;
+mod tls;
 mod tmux;
+mod transcript;
+mod watch;
 
 use std::sync::Arc;
 
 use apns::{ApnsConfig, ApnsService};
 use auth::{AuthService, SharedAuthService};
+use watch::WatchService;
 use axum::{
     extract::{Request, State},
     http::StatusCode,
@@ -18,10 +34,7 @@ use axum::{
 use clap::{Parser, Subcommand};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-const DEFAULT_PORT: u16 = 8787;
 const DEFAULT_BIND_ADDR: &str = "127.0.0.1";
-const HOOK_NOTIFY_COMMAND: &str = "reattachd notify";
-const CODEX_NOTIFY_LINE: &str = "notify = [\"reattachd\", \"notify\"]";
 
 #[derive(Parser)]
 #[command(name = "reattachd")]
@@ -42,6 +55,27 @@ struct Cli {
     /// Auto-detect Tailscale hostname and generate TLS certs
     #[arg(long, global = true)]
     tailscale: bool,
+
+    /// Serve a certificate from a local CA kept in the data dir, and add the
+    /// CA fingerprint to setup URLs so the app can pin it
+    #[arg(long, global = true)]
+    self_signed: bool,
+
+    /// Require devices to authenticate with a client certificate issued at
+    /// registration (needs --tailscale, --self-signed or --tls-cert)
+    #[arg(long, global = true)]
+    mtls: bool,
+
+    /// Listen on this address instead of the default; repeatable. One of
+    /// https://IP:PORT, http://IP:PORT or unix:PATH, optionally followed by
+    /// ?routes=/prefix,/prefix to serve only those paths there
+    #[arg(long = "listen", global = true, value_name = "SPEC")]
+    listen: Vec<String>,
+
+    /// Serve Prometheus metrics on this address (e.g., 127.0.0.1:9187).
+    /// The endpoint is unauthenticated; keep it off public interfaces.
+    #[arg(long, global = true)]
+    metrics_addr: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -55,9 +89,10 @@ enum Commands {
         /// Create a reusable token that can be used multiple times
         #[arg(long)]
         reusable: bool,
-        /// Token expiration time (e.g., 10m, 1h, 1d, never). Default: 10m
-        #[arg(long, default_value = "10m")]
-        expires: String,
+        /// Token expiration time (e.g., 10m, 1h, 1d, never). Default: the
+        /// setup.expires setting, 10m unless configured
+        #[arg(long)]
+        expires: Option<String>,
     },
     /// Manage registered devices
     Devices {
@@ -65,33 +100,137 @@ enum Commands {
         action: Option<DeviceAction>,
     },
     /// Send a push notification to registered devices
-    Notify {
-        /// Agent event JSON payload. If omitted, JSON is read from stdin.
-        #[arg(long)]
-        from_agent_json: Option<String>,
-        /// Agent event JSON payload (positional compatibility)
-        agent_json: Option<String>,
-        /// Manual notification body (debug override)
-        #[arg(long)]
-        body: Option<String>,
-        /// Manual notification title (debug override)
-        #[arg(short, long)]
-        title: Option<String>,
-        /// Tmux pane target (e.g., "dev:0.0"). Auto-detected if running inside tmux.
-        #[arg(long)]
-        target: Option<String>,
-        /// Server port (default: 8787)
-        #[arg(short, long, default_value = "8787")]
-        port: u16,
-        /// Print success output (default is silent)
-        #[arg(short, long)]
-        verbose: bool,
-    },
+    Notify(NotifyArgs),
     /// Manage coding agent notification hooks
     Hooks {
         #[command(subcommand)]
         action: Option<HookAction>,
     },
+    /// Manage output watchers that notify on pane activity
+    Watch {
+        #[command(subcommand)]
+        action: Option<WatchAction>,
+    },
+    /// Show the running daemon's status; exits non-zero if something is wrong
+    Status {
+        /// Print the raw JSON status
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show or check the settings from config.toml, the environment and flags
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// Set up and check the credentials for push notifications
+    Apns {
+        #[command(subcommand)]
+        action: ApnsAction,
+    },
+    /// Manage peers, other daemons whose sessions this one lists and whose
+    /// panes it proxies
+    Peers {
+        #[command(subcommand)]
+        action: Option<PeersAction>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApnsAction {
+    /// Store the APNs signing key and write its IDs to config.toml
+    Configure {
+        /// The .p8 signing key downloaded from the Apple developer portal
+        #[arg(long, value_name = "FILE")]
+        key: std::path::PathBuf,
+        /// The key's ID (the XXXXXXXXXX in AuthKey_XXXXXXXXXX.p8)
+        #[arg(long)]
+        key_id: Option<String>,
+        /// Apple developer team ID
+        #[arg(long)]
+        team_id: Option<String>,
+        /// Bundle ID of the iOS app
+        #[arg(long)]
+        bundle_id: Option<String>,
+        /// Store the key in the OS keyring instead of the data directory
+        #[arg(long)]
+        keyring: bool,
+    },
+    /// Check that APNs accepts the configured credentials, or that the
+    /// paired relay accepts this daemon
+    Test,
+    /// Send pushes through a relay that holds the APNs key
+    Pair {
+        /// The relay's URL
+        #[arg(long, value_name = "URL")]
+        relay: String,
+        /// One-time code from 'reattach-relay pair'
+        #[arg(long)]
+        code: String,
+        /// How the relay lists this daemon (default: hostname)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Forget the relay pairing
+    Unpair,
+}
+
+#[derive(Subcommand)]
+enum PeersAction {
+    /// List peers and check that they answer
+    List,
+    /// Register with another daemon and add it as a peer
+    Add {
+        /// Qualifies the peer's pane targets, as in ID::session:window.pane
+        id: String,
+        /// The setup URL that 'reattachd setup' prints on the peer
+        setup_url: String,
+        /// CA certificate to trust for the peer, e.g. the self-signed/ca.crt
+        /// in its data dir when it runs with --self-signed
+        #[arg(long, value_name = "FILE")]
+        ca_cert: Option<std::path::PathBuf>,
+    },
+    /// Remove a peer
+    Remove { id: String },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective settings and where each one comes from
+    Show,
+    /// Validate the settings; exits non-zero if any is invalid
+    Check,
+}
+
+#[derive(clap::Args)]
+struct NotifyArgs {
+    /// Agent event JSON payload. If omitted, JSON is read from stdin.
+    #[arg(long)]
+    from_agent_json: Option<String>,
+    /// Agent event JSON payload (positional compatibility)
+    agent_json: Option<String>,
+    /// Manual notification body (debug override)
+    #[arg(long)]
+    body: Option<String>,
+    /// Manual notification title (debug override)
+    #[arg(short, long)]
+    title: Option<String>,
+    /// Tmux pane target (e.g., "dev:0.0"). Auto-detected if running inside tmux.
+    #[arg(long)]
+    target: Option<String>,
+    /// Port to send to if the daemon has no control socket, like versions
+    /// from before it (default: server.port from config.toml, or 8787)
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// Print success output (default is silent)
+    #[arg(short, long)]
+    verbose: bool,
+    /// Agent that invoked the hook, used when it sends no JSON payload
+    #[arg(long)]
+    agent: Option<String>,
+    /// Command to run after notifying, with the same trailing arguments
+    /// (used to chain an existing Codex `notify` command)
+    #[arg(last = true)]
+    chain: Vec<String>,
 }
 
 #[derive(Subcommand)]
@@ -107,10 +246,111 @@ enum DeviceAction {
 
 #[derive(Subcommand)]
 enum HookAction {
-    /// Install Claude Code + Codex hooks
-    Install,
-    /// Uninstall Claude Code + Codex hooks
-    Uninstall,
+    /// Install hooks for detected coding agents
+    Install {
+        /// Agent to install for (repeatable). Default: every detected agent
+        #[arg(long = "agent")]
+        agents: Vec<String>,
+        #[command(flatten)]
+        scope: HookScopeArgs,
+        /// Events that notify, comma-separated (stop, subagent_stop, permission_prompt,
+        /// idle_prompt, notification, dangerous_command, session_start, session_end).
+        /// Default: stop,permission_prompt,idle_prompt
+        #[arg(long, value_delimiter = ',')]
+        events: Vec<String>,
+        /// Print the changes as a diff without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Uninstall hooks
+    Uninstall {
+        /// Agent to uninstall from (repeatable). Default: every agent with a hook
+        #[arg(long = "agent")]
+        agents: Vec<String>,
+        #[command(flatten)]
+        scope: HookScopeArgs,
+        /// Print the changes as a diff without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show which agents are detected and where their hooks are installed
+    Status {
+        /// Project directory to check (default: current directory)
+        #[arg(long)]
+        project: Option<std::path::PathBuf>,
+    },
+    /// Restore an agent's settings file from a backup taken before a hook change
+    Restore {
+        /// Agent to restore (repeatable). Default: every agent with backups
+        #[arg(long = "agent")]
+        agents: Vec<String>,
+        #[command(flatten)]
+        scope: HookScopeArgs,
+        /// List available backups instead of restoring
+        #[arg(long)]
+        list: bool,
+        /// Backup file to restore (default: the most recent one)
+        #[arg(long, requires = "agents")]
+        from: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(clap::Args, Default)]
+struct HookScopeArgs {
+    /// Use the project's settings files in this directory instead of the global ones
+    #[arg(long)]
+    project: Option<std::path::PathBuf>,
+    /// With --project, use the uncommitted local settings file (e.g. .claude/settings.local.json)
+    #[arg(long, requires = "project")]
+    local: bool,
+}
+
+impl HookScopeArgs {
+    fn resolve(self) -> hooks::Scope {
+        match self.project {
+            Some(dir) => hooks::Scope::Project {
+                dir,
+                local: self.local,
+            },
+            None => hooks::Scope::Global,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum WatchAction {
+    /// List all watchers
+    List,
+    /// Add a watcher to a pane
+    #[command(group(clap::ArgGroup::new("trigger").required(true).args(["pattern", "exited", "idle", "waiting"])))]
+    Add {
+        /// Tmux pane target (e.g., "dev:0.0"). Auto-detected if running inside tmux.
+        #[arg(long)]
+        target: Option<String>,
+        /// Notify when a new output line matches this regex
+        #[arg(long)]
+        pattern: Option<String>,
+        /// Notify when the running program exits back to the shell
+        #[arg(long)]
+        exited: bool,
+        /// Notify when the pane produces no output for this many minutes
+        #[arg(long, value_name = "MINUTES")]
+        idle: Option<u64>,
+        /// Notify when the pane starts waiting for input (prompt detection)
+        #[arg(long)]
+        waiting: bool,
+        /// Notification title (default: the pane target)
+        #[arg(short, long)]
+        title: Option<String>,
+        /// Remove the watcher after it fires once
+        #[arg(long)]
+        once: bool,
+    },
+    /// Remove a watcher by ID
+    Remove {
+        /// Watcher ID to remove
+        id: String,
+    },
 }
 
 struct TailscaleInfo {
@@ -254,69 +494,93 @@ async fn main() {
         .init();
 
     let data_dir = get_data_dir();
-
-    let tailscale_info = if cli.tailscale {
-        if cli.tls_cert.is_some() || cli.tls_key.is_some() {
-            eprintln!("--tailscale cannot be combined with --tls-cert/--tls-key");
-            std::process::exit(1);
-        }
-        match get_tailscale_info() {
-            Ok(info) => Some(info),
-            Err(e) => {
-                eprintln!("{}", e);
-                std::process::exit(1);
-            }
-        }
-    } else {
-        None
+    let flags = config::Flags {
+        tailscale: cli.tailscale,
+        self_signed: cli.self_signed,
+        mtls: cli.mtls,
+        tls_cert: cli.tls_cert,
+        tls_key: cli.tls_key,
+        listen: cli.listen,
+        metrics_addr: cli.metrics_addr,
     };
 
     match cli.command {
         Some(Commands::Setup { url, reusable, expires }) => {
-            let url = match (url, &tailscale_info) {
+            let config = load_config(&data_dir, flags);
+            let url = match (url, tailscale_info(&config)) {
                 (Some(u), _) => u,
-                (None, Some(info)) => {
-                    let port = std::env::var("REATTACHD_PORT")
-                        .or_else(|_| std::env::var("PORT"))
-                        .ok()
-                        .and_then(|p| p.parse::<u16>().ok())
-                        .unwrap_or(DEFAULT_PORT);
-                    format!("https://{}:{}", info.hostname, port)
-                }
+                (None, Some(info)) => format!("https://{}:{}", info.hostname, config.port),
                 (None, None) => {
                     eprintln!("--url is required (or use --tailscale to auto-detect)");
                     std::process::exit(1);
                 }
             };
-            run_setup_mode(data_dir, url, reusable, expires).await;
+            let cert_fingerprint = if config.self_signed {
+                // The leaf must cover the host the app will connect to; a
+                // running daemon picks up the reissued certificate.
+                let host = reqwest::Url::parse(&url)
+                    .ok()
+                    .and_then(|u| u.host().map(|host| host.to_string()))
+                    .map(|host| host.trim_matches(['[', ']']).to_string());
+                match tls::ensure_self_signed(&data_dir, host.as_slice()) {
+                    Ok(self_signed) => Some(self_signed.ca_fingerprint),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+            let expires = expires.unwrap_or(config.setup_expires);
+            run_setup_mode(data_dir, url, reusable, expires, cert_fingerprint).await;
         }
         Some(Commands::Devices { action }) => {
             run_device_command(data_dir, action).await;
         }
-        Some(Commands::Notify {
-            from_agent_json,
-            agent_json,
-            body,
-            title,
-            target,
-            port,
-            verbose,
-        }) => {
-            run_notify_command(
-                from_agent_json.or(agent_json),
-                body,
-                title,
-                target,
-                port,
-                verbose,
-            )
-            .await;
+        Some(Commands::Notify(args)) => {
+            let chained = spawn_chained_command(&args.chain);
+            run_notify_command(data_dir, flags, args).await;
+            if let Some(mut child) = chained {
+                let _ = child.wait();
+            }
         }
         Some(Commands::Hooks { action }) => {
-            run_hooks_command(action);
+            run_hooks_command(&data_dir.join(hooks::BACKUP_DIR), action);
+        }
+        Some(Commands::Watch { action }) => {
+            let config = load_config(&data_dir, flags);
+            run_watch_command(data_dir, config.tmux_servers, action).await;
+        }
+        Some(Commands::Status { json }) => {
+            run_status_command(data_dir, json).await;
+        }
+        Some(Commands::Apns { action }) => {
+            let config = load_config(&data_dir, flags);
+            run_apns_command(&data_dir, config, action).await;
+        }
+        Some(Commands::Peers { action }) => {
+            let config = load_config(&data_dir, flags);
+            run_peers_command(&data_dir, &config.tmux_servers, action.unwrap_or(PeersAction::List)).await;
+        }
+        Some(Commands::Config { action }) => {
+            let config = load_config(&data_dir, flags);
+            match action {
+                Some(ConfigAction::Check) if config.file.exists() => {
+                    println!("{} is valid", config.file.display());
+                }
+                Some(ConfigAction::Check) => {
+                    println!("No {}; the environment and flags are valid", config.file.display());
+                }
+                Some(ConfigAction::Show) | None => print!("{}", config.show()),
+            }
         }
         None => {
+            let config = load_config(&data_dir, flags);
+            let tailscale_info = tailscale_info(&config);
+            let mut tls_renewal = None;
             let tls_config = if let Some(ref info) = tailscale_info {
+                tls_renewal = Some(tls::Renewal::Tailscale(info.hostname.clone()));
                 match generate_tailscale_certs(&info.hostname, &data_dir) {
                     Ok(paths) => Some(paths),
                     Err(e) => {
@@ -324,19 +588,295 @@ async fn main() {
                         std::process::exit(1);
                     }
                 }
+            } else if config.self_signed {
+                tls_renewal = Some(tls::Renewal::SelfSigned);
+                let names: Vec<String> = config
+                    .bind_addr
+                    .clone()
+                    .filter(|addr| !addr.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_unspecified()))
+                    .into_iter()
+                    .collect();
+                match tls::ensure_self_signed(&data_dir, &names) {
+                    Ok(self_signed) => Some((
+                        self_signed.cert.to_string_lossy().to_string(),
+                        self_signed.key.to_string_lossy().to_string(),
+                    )),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
             } else {
-                let tls_cert = cli.tls_cert.or_else(|| std::env::var("REATTACHD_TLS_CERT").ok());
-                let tls_key = cli.tls_key.or_else(|| std::env::var("REATTACHD_TLS_KEY").ok());
-                match (tls_cert, tls_key) {
-                    (Some(cert), Some(key)) => Some((cert, key)),
-                    (Some(_), None) | (None, Some(_)) => {
-                        eprintln!("Both --tls-cert and --tls-key must be provided together");
+                config.tls_cert.clone().zip(config.tls_key.clone())
+            };
+            let client_ca = match (config.mtls, &tls_config) {
+                (true, Some(_)) => match mtls::ensure_client_ca(&data_dir) {
+                    Ok(path) => Some(path),
+                    Err(e) => {
+                        eprintln!("{}", e);
                         std::process::exit(1);
                     }
-                    (None, None) => None,
+                },
+                _ => None,
+            };
+            let tls_config = tls_config.map(|(cert, key)| tls::CertFiles {
+                cert: cert.into(),
+                key: key.into(),
+                renewal: tls_renewal,
+                client_ca,
+                data_dir: data_dir.clone(),
+            });
+            let listeners = config
+                .listen
+                .iter()
+                .map(|spec| listen::Listener::parse(spec, config.port))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            run_daemon(data_dir, config, tls_config, tailscale_info, listeners).await;
+        }
+    }
+}
+
+/// Resolves the settings, exiting with every problem found if any is invalid.
+fn load_config(data_dir: &std::path::Path, flags: config::Flags) -> config::Config {
+    config::Config::load(data_dir, flags).unwrap_or_else(|errors| {
+        eprintln!("Invalid configuration:");
+        for error in errors {
+            eprintln!("  {}", error);
+        }
+        std::process::exit(1);
+    })
+}
+
+async fn run_apns_command(data_dir: &std::path::Path, config: config::Config, action: ApnsAction) {
+    use apns::{credentials, relay, Pairing};
+
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+    match action {
+        ApnsAction::Configure {
+            key,
+            key_id,
+            team_id,
+            bundle_id,
+            keyring,
+        } => {
+            let pem = std::fs::read_to_string(&key)
+                .unwrap_or_else(|e| fail(format!("Failed to read {}: {}", key.display(), e)));
+            credentials::validate_key(&pem).unwrap_or_else(|e| fail(format!("{}: {}", key.display(), e)));
+
+            if keyring {
+                credentials::store_in_keyring(&pem).unwrap_or_else(|e| fail(e));
+                println!("Stored the APNs key in the OS keyring");
+                if config.apns.key_file.exists() {
+                    println!(
+                        "Note: {} takes precedence over the keyring; remove it to use the keyring",
+                        config.apns.key_file.display()
+                    );
                 }
+            } else {
+                credentials::store_in_file(&config.apns.key_file, &pem).unwrap_or_else(|e| fail(e));
+                println!("Stored the APNs key in {}", config.apns.key_file.display());
+            }
+
+            let ids: Vec<(&str, String)> = [("key_id", key_id), ("team_id", team_id), ("bundle_id", bundle_id)]
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|value| (name, value)))
+                .collect();
+            if !ids.is_empty() {
+                config::set(&config.file, "apns", &ids).unwrap_or_else(|e| fail(e));
+                println!("Updated [apns] in {}", config.file.display());
+            }
+            if std::env::var_os("APNS_KEY_BASE64").is_some() {
+                println!("Note: APNS_KEY_BASE64 is set and takes precedence over the stored key");
+            }
+            println!("\nRun 'reattachd apns test' to check the credentials, then restart the daemon.");
+        }
+        ApnsAction::Test => {
+            let credentials = match credentials::load(&config.apns) {
+                Ok(Some(credentials)) => credentials,
+                Ok(None) => match Pairing::load(data_dir) {
+                    Ok(Some(pairing)) => return test_relay(&pairing).await,
+                    Ok(None) => fail(
+                        "No APNs key found. Run 'reattachd apns configure --key AuthKey_XXXXXXXXXX.p8 \
+                         --key-id XXXXXXXXXX --team-id XXXXXXXXXX --bundle-id <app bundle ID>', \
+                         or pair with a relay with 'reattachd apns pair'"
+                            .to_string(),
+                    ),
+                    Err(e) => fail(e),
+                },
+                Err(e) => fail(e),
+            };
+            println!("Key:        {}", credentials.source);
+            println!("Key ID:     {}", credentials.key_id);
+            println!("Team ID:    {}", credentials.team_id);
+            println!("Bundle ID:  {}", credentials.bundle_id);
+            println!();
+
+            let mut accepted = true;
+            for (name, url) in [("Sandbox", &config.apns.sandbox_url), ("Production", &config.apns.production_url)] {
+                match credentials::verify(&credentials, url).await {
+                    Ok(()) => println!("{}: accepted by {}", name, url),
+                    Err(e) => {
+                        accepted = false;
+                        println!("{}: {} ({})", name, e, url);
+                    }
+                }
+            }
+            if !accepted {
+                std::process::exit(1);
+            }
+        }
+        ApnsAction::Pair { relay, code, name } => {
+            let name = name.unwrap_or_else(local_hostname);
+            let pairing = relay::pair(&relay, &code, &name).await.unwrap_or_else(|e| fail(e));
+            pairing.save(data_dir).unwrap_or_else(|e| fail(e));
+            println!("Paired with {} as {} ({})", pairing.url, name, pairing.daemon_id);
+            if matches!(credentials::load(&config.apns), Ok(Some(_))) {
+                println!("Note: an APNs key is configured and takes precedence over the relay");
+            }
+            println!("\nRun 'reattachd apns test' to check the pairing, then restart the daemon.");
+        }
+        ApnsAction::Unpair => {
+            if !Pairing::remove(data_dir).unwrap_or_else(|e| fail(e)) {
+                fail("Not paired with a relay".to_string());
+            }
+            println!("Removed the relay pairing; restart the daemon to apply it.");
+        }
+    }
+}
+
+async fn run_peers_command(data_dir: &std::path::Path, servers: &tmux::Servers, action: PeersAction) {
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+    let mut peers = hosts::load_peers(data_dir).unwrap_or_else(|e| fail(e));
+    match action {
+        PeersAction::List => {
+            if peers.is_empty() {
+                println!("No peers. Add one with 'reattachd peers add ID SETUP_URL'.");
+            }
+            for config in peers {
+                let (id, url) = (config.id.clone(), config.url.clone());
+                let answer = match hosts::Peer::new(config) {
+                    Ok(peer) => peer.get::<Vec<api::SessionResponse>>(&["sessions"]).await,
+                    Err(e) => Err(e),
+                };
+                match answer {
+                    Ok(sessions) => println!("{}  {}  {} sessions", id, url, sessions.len()),
+                    Err(e) => println!("{}  {}  not reachable: {}", id, url, e),
+                }
+            }
+        }
+        PeersAction::Add { id, setup_url, ca_cert } => {
+            if !tmux::is_valid_id(&id) {
+                fail(format!("'{}' is not a valid peer ID (letters, digits, '-' and '_')", id));
+            }
+            if servers.get(&id).is_some() {
+                fail(format!("'{}' is already the ID of a tmux server", id));
+            }
+            if peers.iter().any(|peer| peer.id == id) {
+                fail(format!("There is already a peer '{}'; remove it first", id));
+            }
+            let mut url = reqwest::Url::parse(&setup_url)
+                .unwrap_or_else(|e| fail(format!("'{}' is not a URL: {}", setup_url, e)));
+            let query: std::collections::HashMap<String, String> = url.query_pairs().into_owned().collect();
+            let setup_token = query
+                .get("setup_token")
+                .cloned()
+                .unwrap_or_else(|| fail("The setup URL has no setup_token".to_string()));
+            if query.contains_key("cert_sha256") && ca_cert.is_none() {
+                fail("The peer uses a self-signed certificate; pass its CA with --ca-cert".to_string());
+            }
+            url.set_query(None);
+            let url = url.as_str().trim_end_matches('/').to_string();
+            let ca_cert = ca_cert.map(|path| std::path::absolute(&path).unwrap_or(path));
+
+            let name = format!("reattachd on {}", local_hostname());
+            let device_token = hosts::register(&url, &setup_token, &name, ca_cert.as_deref())
+                .await
+                .unwrap_or_else(|e| fail(e));
+            peers.push(hosts::PeerConfig {
+                id: id.clone(),
+                url: url.clone(),
+                device_token,
+                ca_cert,
+            });
+            hosts::save_peers(data_dir, &peers).unwrap_or_else(|e| fail(e));
+            println!("Added peer {} ({}) as device '{}'", id, url, name);
+            println!("Restart the daemon to apply it.");
+        }
+        PeersAction::Remove { id } => {
+            let Some(position) = peers.iter().position(|peer| peer.id == id) else {
+                fail(format!("No peer {}", id));
             };
-            run_daemon(data_dir, tls_config, tailscale_info).await;
+            let removed = peers.remove(position);
+            hosts::save_peers(data_dir, &peers).unwrap_or_else(|e| fail(e));
+            println!("Removed peer {}; restart the daemon to apply it.", id);
+            println!(
+                "This daemon stays registered with {}; revoke it there with 'reattachd devices revoke'.",
+                removed.url
+            );
+        }
+    }
+}
+
+async fn test_relay(pairing: &apns::Pairing) {
+    println!("Relay:      {}", pairing.url);
+    println!("Daemon ID:  {}", pairing.daemon_id);
+    println!();
+
+    let mut accepted = true;
+    for (name, sandbox) in [("Sandbox", true), ("Production", false)] {
+        match apns::relay::verify(pairing, sandbox).await {
+            Ok(()) => println!("{}: accepted by the relay", name),
+            Err(e) => {
+                accepted = false;
+                println!("{}: {}", name, e);
+            }
+        }
+    }
+    if !accepted {
+        std::process::exit(1);
+    }
+}
+
+fn local_hostname() -> String {
+    std::process::Command::new("hostname")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "reattachd".to_string())
+}
+
+fn tailscale_info(config: &config::Config) -> Option<TailscaleInfo> {
+    if !config.tailscale {
+        return None;
+    }
+    match get_tailscale_info() {
+        Ok(info) => Some(info),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn spawn_chained_command(chain: &[String]) -> Option<std::process::Child> {
+    let (program, args) = chain.split_first()?;
+    match std::process::Command::new(program).args(args).spawn() {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("Failed to run chained command {}: {}", program, e);
+            None
         }
     }
 }
@@ -390,7 +930,22 @@ fn token_note_from_expires(expires: &str, reusable: bool) -> String {
     notes.join(", ")
 }
 
-async fn run_setup_mode(data_dir: std::path::PathBuf, url: String, reusable: bool, expires: String) {
+/// The URL the setup QR code encodes. With `--self-signed` it carries the
+/// SHA-256 fingerprint of the local CA for the app to pin.
+fn setup_url(url: &str, setup_token: &str, cert_fingerprint: Option<&str>) -> String {
+    match cert_fingerprint {
+        Some(fingerprint) => format!("{}?setup_token={}&cert_sha256={}", url, setup_token, fingerprint),
+        None => format!("{}?setup_token={}", url, setup_token),
+    }
+}
+
+async fn run_setup_mode(
+    data_dir: std::path::PathBuf,
+    url: String,
+    reusable: bool,
+    expires: String,
+    cert_fingerprint: Option<String>,
+) {
     let duration = parse_duration(&expires).unwrap_or_else(|| {
         eprintln!("Invalid expiration format: {}. Using default 10m.", expires);
         chrono::Duration::minutes(10)
@@ -401,27 +956,54 @@ async fn run_setup_mode(data_dir: std::path::PathBuf, url: String, reusable: boo
         .expect("Failed to initialize auth service");
 
     let setup_token = auth_service.generate_setup_token(reusable, duration).await;
-    let setup_url = format!("{}?setup_token={}", url, setup_token);
+    let setup_url = setup_url(&url, &setup_token, cert_fingerprint.as_deref());
 
     print_setup_qr(&setup_url, &token_note_from_expires(&expires, reusable));
     println!("  Make sure reattachd daemon is running.\n");
 }
 
+/// Runs a device admin request through the daemon if it is running, so its
+/// in-memory state stays current; `None` means it isn't.
+async fn call_daemon(data_dir: &std::path::Path, method: axum::http::Method, path: &str) -> Option<local::Response> {
+    match local::call(data_dir, None, method, path, None).await {
+        Ok(response) => Some(response),
+        Err(local::LocalError::Unreachable(_)) => None,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 async fn run_device_command(data_dir: std::path::PathBuf, action: Option<DeviceAction>) {
-    let auth_service = AuthService::new(data_dir)
-        .await
-        .expect("Failed to initialize auth service");
+    use axum::http::{Method, StatusCode};
 
     match action {
         Some(DeviceAction::Revoke { id }) => {
-            if auth_service.revoke_device(&id).await {
+            let revoked = match call_daemon(&data_dir, Method::DELETE, &format!("/devices/{}", id)).await {
+                Some(response) => response.status == StatusCode::NO_CONTENT,
+                None => local_auth_service(data_dir).await.revoke_device(&id).await,
+            };
+            if revoked {
                 println!("Device {} revoked successfully", id);
             } else {
                 println!("Device {} not found", id);
             }
         }
         Some(DeviceAction::List) | None => {
-            let devices = auth_service.list_devices().await;
+            let devices = match call_daemon(&data_dir, Method::GET, "/devices").await {
+                Some(response) => response.json::<Vec<api::DeviceInfo>>().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }),
+                None => local_auth_service(data_dir)
+                    .await
+                    .list_devices()
+                    .await
+                    .into_iter()
+                    .map(api::DeviceInfo::from)
+                    .collect(),
+            };
             if devices.is_empty() {
                 println!("No registered devices");
                 println!("\nRun 'reattachd setup --url <URL>' to register a device");
@@ -434,6 +1016,163 @@ async fn run_device_command(data_dir: std::path::PathBuf, action: Option<DeviceA
                     if let Some(last_seen) = device.last_seen_at {
                         println!("  Last seen:   {}", last_seen);
                     }
+                    if device.client_cert {
+                        println!("  Auth:        client certificate");
+                    }
+                    println!();
+                }
+            }
+        }
+    }
+}
+
+fn format_uptime(secs: i64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+async fn run_status_command(data_dir: std::path::PathBuf, json: bool) {
+    let response = match local::call(&data_dir, None, axum::http::Method::GET, "/status", None).await {
+        Ok(response) if response.status.is_success() => response,
+        Ok(response) => {
+            eprintln!("Failed to get status: HTTP {}", response.status);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let status = response.json::<api::StatusResponse>().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    if json {
+        println!("{}", String::from_utf8_lossy(&response.body));
+    } else {
+        println!("reattachd {}, up {}\n", status.version, format_uptime(status.uptime_secs));
+        for (i, bind) in status.binds.iter().enumerate() {
+            println!("  {:<12} {}", if i == 0 { "Listening:" } else { "" }, bind);
+        }
+        if let Some(ref tls) = status.tls {
+            let expiry = match tls.expires_in_days {
+                Some(days) => format!("expires in {} days", days),
+                None => "unreadable".to_string(),
+            };
+            println!("  {:<12} {} ({})", "TLS:", tls.cert_path.display(), expiry);
+        }
+        let tmux = match status.tmux.error {
+            Some(ref error) => format!("unreachable ({})", error.trim()),
+            None => format!("{} session(s)", status.tmux.sessions),
+        };
+        println!("  {:<12} {}", "tmux:", tmux);
+        let apns = if status.apns.configured {
+            format!("configured, {} push token(s)", status.apns.push_tokens)
+        } else {
+            "not configured".to_string()
+        };
+        println!("  {:<12} {}", "APNs:", apns);
+        println!("  {:<12} {}", "Devices:", status.devices);
+        for (label, items) in [("Problems", &status.problems), ("Warnings", &status.warnings)] {
+            if !items.is_empty() {
+                println!("\n{}:", label);
+                for item in items {
+                    println!("  - {}", item);
+                }
+            }
+        }
+    }
+
+    if !status.problems.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+async fn local_auth_service(data_dir: std::path::PathBuf) -> AuthService {
+    AuthService::new(data_dir)
+        .await
+        .expect("Failed to initialize auth service")
+}
+
+async fn run_watch_command(data_dir: std::path::PathBuf, servers: tmux::Servers, action: Option<WatchAction>) {
+    let watch_service = WatchService::new(data_dir, servers.clone())
+        .await
+        .expect("Failed to initialize watch service");
+
+    match action {
+        Some(WatchAction::Add {
+            target,
+            pattern,
+            exited,
+            idle,
+            waiting,
+            title,
+            once,
+        }) => {
+            let target = target.or_else(|| auto_detect_tmux_target_from_env(&servers)).unwrap_or_else(|| {
+                eprintln!("--target is required when not running inside tmux");
+                std::process::exit(2);
+            });
+            let trigger = match (pattern, exited, idle) {
+                (Some(pattern), _, _) => watch::WatchTrigger::Match { pattern },
+                (None, true, _) => watch::WatchTrigger::Exited,
+                (None, false, Some(minutes)) => watch::WatchTrigger::Idle { minutes },
+                (None, false, None) if waiting => watch::WatchTrigger::WaitingForInput,
+                (None, false, None) => unreachable!("clap requires one trigger"),
+            };
+            match watch_service
+                .add(watch::NewWatcher {
+                    target,
+                    trigger,
+                    title,
+                    once,
+                })
+                .await
+            {
+                Ok(watcher) => println!(
+                    "Watcher {} added: notify when {} on {}",
+                    watcher.id,
+                    watcher.trigger.describe(),
+                    watcher.target
+                ),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(WatchAction::Remove { id }) => {
+            if watch_service.remove(&id).await {
+                println!("Watcher {} removed", id);
+            } else {
+                println!("Watcher {} not found", id);
+            }
+        }
+        Some(WatchAction::List) | None => {
+            let watchers = watch_service.list().await;
+            if watchers.is_empty() {
+                println!("No watchers");
+                println!("\nRun 'reattachd watch add --target <pane> --pattern <regex>' to add one");
+            } else {
+                println!("Watchers:\n");
+                for watcher in watchers {
+                    println!("  ID:          {}", watcher.id);
+                    println!("  Target:      {}", watcher.target);
+                    println!("  Trigger:     {}", watcher.trigger.describe());
+                    if let Some(ref title) = watcher.title {
+                        println!("  Title:       {}", title);
+                    }
+                    if watcher.once {
+                        println!("  Once:        yes");
+                    }
+                    if let Some(last) = watcher.last_triggered_at {
+                        println!("  Last fired:  {}", last);
+                    }
                     println!();
                 }
             }
@@ -446,88 +1185,96 @@ struct NotifyPayload {
     body: String,
     cwd: Option<String>,
     pane_target: Option<String>,
+    event: Option<hooks::HookEvent>,
 }
 
-fn extract_last_assistant_message(transcript_path: &str) -> Option<String> {
-    let content = std::fs::read_to_string(transcript_path).ok()?;
-    for line in content.lines().rev() {
-        let value: serde_json::Value = serde_json::from_str(line).ok()?;
-        let role = value.get("type").and_then(|v| v.as_str()).unwrap_or("");
-        if role != "assistant" {
-            continue;
-        }
-
-        let texts = value
-            .pointer("/message/content")
-            .and_then(|v| v.as_array())
-            .map(|items| {
-                items
-                    .iter()
-                    .filter_map(|item| {
-                        let t = item.get("type").and_then(|v| v.as_str())?;
-                        if t != "text" {
-                            return None;
-                        }
-                        item.get("text").and_then(|v| v.as_str()).map(str::to_string)
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .unwrap_or_default();
-
-        if !texts.is_empty() {
-            return Some(texts.join("\n"));
-        }
-    }
-    None
-}
-
-fn parse_agent_notify_payload(input: &str) -> Result<Option<NotifyPayload>, String> {
+/// Parses the JSON an agent hook passes to `reattachd notify`. `agent` is
+/// the `--agent` the hook was installed with, used as a title fallback.
+fn parse_agent_notify_payload(input: &str, agent: Option<&str>) -> Result<Option<NotifyPayload>, String> {
     let value: serde_json::Value =
         serde_json::from_str(input).map_err(|e| format!("Invalid JSON input: {}", e))?;
 
+    let Some(event) = hooks::HookEvent::from_payload(&value) else {
+        return Ok(None);
+    };
     let event_type = value.get("type").and_then(|v| v.as_str());
-    if let Some(t) = event_type {
-        if t != "agent-turn-complete" {
-            return Ok(None);
-        }
-    }
 
+    // Cursor passes the workspace instead of a cwd.
     let cwd = value
         .get("cwd")
+        .or_else(|| value.pointer("/workspace_roots/0"))
         .and_then(|v| v.as_str())
         .map(|s| s.to_string())
         .filter(|s| !s.is_empty());
 
+    let fallback_title = || {
+        if event_type.is_some() {
+            "Codex".to_string()
+        } else if let Some(integration) = agent.and_then(hooks::find) {
+            integration.display_name().to_string()
+        } else {
+            "Coding Agent".to_string()
+        }
+    };
     let mut title = if let Some(agent) = value.get("agent").and_then(|v| v.as_str()) {
         if !agent.is_empty() {
             agent.to_string()
-        } else if event_type.is_some() {
-            "Codex".to_string()
         } else {
-            "Coding Agent".to_string()
+            fallback_title()
         }
-    } else if event_type.is_some() {
-        "Codex".to_string()
     } else {
-        "Coding Agent".to_string()
+        fallback_title()
     };
 
-    let mut body = value
-        .get("last-assistant-message")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string())
-        .filter(|s| !s.is_empty())
-        .unwrap_or_else(|| "Waiting for input".to_string());
-
-    if body == "Waiting for input" {
-        if let Some(path) = value.get("transcript_path").and_then(|v| v.as_str()) {
-            if !path.is_empty() {
-                if let Some(last) = extract_last_assistant_message(path) {
-                    body = last;
-                }
+    let text_field = |key: &str| {
+        value
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    let tail_of = |key: &str| {
+        text_field(key)
+            .and_then(|path| transcript::read_tail(std::path::Path::new(&path), transcript::DEFAULT_SCAN_BYTES).ok())
+            .unwrap_or_default()
+    };
+    let body = match event {
+        // Codex sends `last-assistant-message`, Gemini CLI `prompt_response`.
+        hooks::HookEvent::Stop => text_field("last-assistant-message")
+            .or_else(|| text_field("prompt_response"))
+            .or_else(|| {
+                let tail = tail_of("transcript_path");
+                let tools = tail.tool_summary();
+                tail.last_message.or_else(|| tools.map(|tools| format!("Used {}", tools)))
+            })
+            .unwrap_or_else(|| "Waiting for input".to_string()),
+        hooks::HookEvent::SubagentStop => tail_of("agent_transcript_path")
+            .last_message
+            .unwrap_or_else(|| "Subagent finished".to_string()),
+        hooks::HookEvent::PermissionPrompt => {
+            let message = text_field("message").unwrap_or_else(|| event.subtitle().to_string());
+            match tail_of("transcript_path").pending_tool_use {
+                Some(tool_use) => format!("{}\n{}", message, tool_use),
+                None => message,
             }
         }
-    }
+        hooks::HookEvent::IdlePrompt | hooks::HookEvent::Notification => {
+            text_field("message").unwrap_or_else(|| event.subtitle().to_string())
+        }
+        hooks::HookEvent::DangerousCommand => value
+            .pointer("/tool_input/command")
+            .and_then(|v| v.as_str())
+            .map(|c| format!("$ {}", c.trim()))
+            .unwrap_or_default(),
+        hooks::HookEvent::SessionStart => match text_field("source") {
+            Some(source) => format!("Session started ({})", source),
+            None => "Session started".to_string(),
+        },
+        hooks::HookEvent::SessionEnd => match text_field("reason") {
+            Some(reason) => format!("Session ended ({})", reason),
+            None => "Session ended".to_string(),
+        },
+    };
 
     if let Some(ref c) = cwd {
         if let Some(dir_name) = std::path::Path::new(c).file_name().and_then(|v| v.to_str()) {
@@ -540,10 +1287,13 @@ fn parse_agent_notify_payload(input: &str) -> Result<Option<NotifyPayload>, Stri
         body,
         cwd,
         pane_target: None,
+        event: Some(event),
     }))
 }
 
-fn auto_detect_tmux_target_from_env() -> Option<String> {
+/// The pane the command runs in, qualified with its server's ID if `$TMUX`
+/// points at one of the sockets in `tmux.sockets`.
+fn auto_detect_tmux_target_from_env(servers: &tmux::Servers) -> Option<String> {
     let tmux_pane = std::env::var("TMUX_PANE").ok()?;
     let output = std::process::Command::new("tmux")
         .args([
@@ -558,10 +1308,17 @@ fn auto_detect_tmux_target_from_env() -> Option<String> {
     if !output.status.success() {
         return None;
     }
-    String::from_utf8(output.stdout)
+    let target = String::from_utf8(output.stdout)
         .ok()
         .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
+        .filter(|s| !s.is_empty())?;
+    // $TMUX is "socket path,server pid,session index".
+    let socket = std::env::var("TMUX").ok();
+    let socket = socket.as_deref().and_then(|tmux| tmux.rsplitn(3, ',').nth(2));
+    match socket.and_then(|socket| servers.find_socket(std::path::Path::new(socket))) {
+        Some(server) => Some(server.qualify(&target)),
+        None => Some(target),
+    }
 }
 
 fn auto_detect_tmux_target_from_cwd(cwd: &str) -> Option<String> {
@@ -620,281 +1377,226 @@ fn read_stdin_if_available() -> Option<String> {
     None
 }
 
-fn run_hooks_command(action: Option<HookAction>) {
-    match action.unwrap_or(HookAction::Install) {
-        HookAction::Install => {
-            install_claude_hooks();
-            install_codex_hooks();
-        }
-        HookAction::Uninstall => {
-            uninstall_claude_hooks();
-            uninstall_codex_hooks();
-        }
-    }
-}
-
-fn home_file(path: &str) -> Option<std::path::PathBuf> {
-    let mut home = dirs::home_dir()?;
-    home.push(path);
-    Some(home)
-}
-
-fn ensure_claude_event_hook(
-    hooks_obj: &mut serde_json::Map<String, serde_json::Value>,
-    event_name: &str,
-    matcher: &str,
-) {
-    let event = hooks_obj
-        .entry(event_name.to_string())
-        .or_insert_with(|| serde_json::json!([]));
-    if !event.is_array() {
-        *event = serde_json::json!([]);
-    }
-    let event_arr = event.as_array_mut().expect("array expected");
-
-    let has_entry = event_arr.iter().any(|entry| {
-        entry.get("matcher").and_then(|v| v.as_str()) == Some(matcher)
-            && entry
-                .get("hooks")
-                .and_then(|v| v.as_array())
-                .map(|arr| {
-                    arr.iter().any(|h| {
-                        h.get("type").and_then(|v| v.as_str()) == Some("command")
-                            && h.get("command").and_then(|v| v.as_str()) == Some(HOOK_NOTIFY_COMMAND)
-                    })
-                })
-                .unwrap_or(false)
-    });
-
-    if !has_entry {
-        event_arr.push(serde_json::json!({
-            "matcher": matcher,
-            "hooks": [{
-                "type": "command",
-                "command": HOOK_NOTIFY_COMMAND,
-                "timeout": 10
-            }]
-        }));
-    }
-}
-
-fn prune_claude_event_hook(
-    hooks_obj: &mut serde_json::Map<String, serde_json::Value>,
-    event_name: &str,
-) {
-    let Some(event) = hooks_obj.get_mut(event_name) else {
-        return;
-    };
-    let Some(event_arr) = event.as_array_mut() else {
-        return;
+fn run_hooks_command(backup_dir: &std::path::Path, action: Option<HookAction>) {
+    let default = HookAction::Install {
+        agents: Vec::new(),
+        scope: HookScopeArgs::default(),
+        events: Vec::new(),
+        dry_run: false,
     };
-
-    event_arr.retain(|entry| {
-        let has_reattach = entry
-            .get("hooks")
-            .and_then(|v| v.as_array())
-            .map(|arr| {
-                arr.iter().any(|h| {
-                    h.get("type").and_then(|v| v.as_str()) == Some("command")
-                        && h.get("command").and_then(|v| v.as_str()) == Some(HOOK_NOTIFY_COMMAND)
+    let mut failed = false;
+    match action.unwrap_or(default) {
+        HookAction::Install {
+            agents,
+            scope,
+            events,
+            dry_run,
+        } => {
+            let scope = scope.resolve();
+            let events = if events.is_empty() {
+                hooks::HookEvent::DEFAULT.to_vec()
+            } else {
+                hooks::parse_event_names(&events).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
                 })
-            })
-            .unwrap_or(false);
-        !has_reattach
-    });
-}
-
-fn install_claude_hooks() {
-    let claude_file = match home_file(".claude/settings.json") {
-        Some(p) => p,
-        None => {
-            eprintln!("Failed to resolve home directory for Claude settings");
-            return;
-        }
-    };
-    if let Some(dir) = claude_file.parent() {
-        if let Err(e) = std::fs::create_dir_all(dir) {
-            eprintln!("Failed to create {}: {}", dir.display(), e);
-            return;
+            };
+            let selected = select_agents(&agents, |a| {
+                a.supports(&scope) && (a.detect() || a.is_hooked(&scope))
+            });
+            if selected.is_empty() {
+                println!("No supported coding agents detected. Use --agent to install anyway.");
+                return;
+            }
+            for agent in selected {
+                let result = agent
+                    .plan_install(&scope, &events)
+                    .and_then(|plan| hooks::apply(plan, dry_run, backup_dir));
+                failed |= !report_hook_result(agent.display_name(), result);
+            }
         }
-    }
-
-    let mut root: serde_json::Value = if claude_file.exists() {
-        match std::fs::read_to_string(&claude_file)
-            .ok()
-            .and_then(|s| serde_json::from_str(&s).ok())
-        {
-            Some(v) => v,
-            None => serde_json::json!({}),
+        HookAction::Uninstall { agents, scope, dry_run } => {
+            let scope = scope.resolve();
+            let selected = select_agents(&agents, |a| a.is_hooked(&scope));
+            if selected.is_empty() {
+                println!("No Reattach hooks installed ({} scope)", scope.label());
+                return;
+            }
+            for agent in selected {
+                let result = agent
+                    .plan_uninstall(&scope)
+                    .and_then(|plan| hooks::apply(plan, dry_run, backup_dir));
+                failed |= !report_hook_result(agent.display_name(), result);
+            }
         }
-    } else {
-        serde_json::json!({})
-    };
-
-    if !root.is_object() {
-        root = serde_json::json!({});
-    }
-    let root_obj = root.as_object_mut().expect("object expected");
-    let hooks = root_obj
-        .entry("hooks")
-        .or_insert_with(|| serde_json::json!({}));
-    if !hooks.is_object() {
-        *hooks = serde_json::json!({});
-    }
-    let hooks_obj = hooks.as_object_mut().expect("object expected");
-    ensure_claude_event_hook(hooks_obj, "Stop", "");
-    ensure_claude_event_hook(hooks_obj, "Notification", "permission_prompt");
-
-    match serde_json::to_string_pretty(&root)
-        .ok()
-        .and_then(|s| std::fs::write(&claude_file, format!("{}\n", s)).ok())
-    {
-        Some(_) => println!("Updated {}", claude_file.display()),
-        None => eprintln!("Failed to write {}", claude_file.display()),
-    }
-}
-
-fn uninstall_claude_hooks() {
-    let claude_file = match home_file(".claude/settings.json") {
-        Some(p) => p,
-        None => {
-            eprintln!("Failed to resolve home directory for Claude settings");
-            return;
+        HookAction::Status { project } => {
+            let dir = project
+                .or_else(|| std::env::current_dir().ok())
+                .unwrap_or_else(|| std::path::PathBuf::from("."));
+            let scopes = [
+                hooks::Scope::Global,
+                hooks::Scope::Project {
+                    dir: dir.clone(),
+                    local: false,
+                },
+                hooks::Scope::Project {
+                    dir: dir.clone(),
+                    local: true,
+                },
+            ];
+            println!("Project: {}", dir.display());
+            println!(
+                "{:<10} {:<14} {:<10} {:<11} {:<11} LOCAL",
+                "AGENT", "NAME", "DETECTED", "GLOBAL", "PROJECT"
+            );
+            for agent in hooks::registry() {
+                let cells: Vec<&str> = scopes
+                    .iter()
+                    .map(|scope| {
+                        if !agent.supports(scope) {
+                            "n/a"
+                        } else if agent.is_hooked(scope) {
+                            "installed"
+                        } else {
+                            "-"
+                        }
+                    })
+                    .collect();
+                println!(
+                    "{:<10} {:<14} {:<10} {:<11} {:<11} {}",
+                    agent.name(),
+                    agent.display_name(),
+                    if agent.detect() { "yes" } else { "no" },
+                    cells[0],
+                    cells[1],
+                    cells[2]
+                );
+            }
         }
-    };
-    if !claude_file.exists() {
-        println!("No Claude settings file found");
-        return;
-    }
-
-    let mut root: serde_json::Value = match std::fs::read_to_string(&claude_file)
-        .ok()
-        .and_then(|s| serde_json::from_str(&s).ok())
-    {
-        Some(v) => v,
-        None => {
-            eprintln!("Failed to parse {}", claude_file.display());
-            return;
+        HookAction::Restore { agents, scope, list, from } => {
+            if from.is_some() && agents.len() != 1 {
+                eprintln!("--from requires exactly one --agent");
+                std::process::exit(2);
+            }
+            let scope = scope.resolve();
+            let selected = select_agents(&agents, |a| {
+                a.config_path(&scope)
+                    .is_some_and(|p| !hooks::backups(&p, backup_dir).is_empty())
+            });
+            if selected.is_empty() {
+                println!("No backups found ({} scope)", scope.label());
+                return;
+            }
+            for agent in selected {
+                let path = match agent.path_for(&scope) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        failed |= !report_hook_result(agent.display_name(), Err(e));
+                        continue;
+                    }
+                };
+                if list {
+                    println!("{} ({}):", agent.display_name(), path.display());
+                    for backup in hooks::backups(&path, backup_dir).iter().rev() {
+                        println!("  {}", backup.display());
+                    }
+                } else {
+                    let result = hooks::restore(&path, from.as_deref(), backup_dir);
+                    failed |= !report_hook_result(agent.display_name(), result);
+                }
+            }
         }
-    };
-    if let Some(hooks) = root.get_mut("hooks").and_then(|v| v.as_object_mut()) {
-        prune_claude_event_hook(hooks, "Stop");
-        prune_claude_event_hook(hooks, "Notification");
     }
-
-    match serde_json::to_string_pretty(&root)
-        .ok()
-        .and_then(|s| std::fs::write(&claude_file, format!("{}\n", s)).ok())
-    {
-        Some(_) => println!("Updated {}", claude_file.display()),
-        None => eprintln!("Failed to write {}", claude_file.display()),
+    if failed {
+        std::process::exit(1);
     }
 }
 
-fn install_codex_hooks() {
-    let codex_file = match home_file(".codex/config.toml") {
-        Some(p) => p,
-        None => {
-            eprintln!("Failed to resolve home directory for Codex config");
-            return;
-        }
-    };
-    if let Some(dir) = codex_file.parent() {
-        if let Err(e) = std::fs::create_dir_all(dir) {
-            eprintln!("Failed to create {}: {}", dir.display(), e);
-            return;
-        }
-    }
-    let existing = std::fs::read_to_string(&codex_file).unwrap_or_default();
-    let has_other_notify = existing.lines().any(|line| {
-        let t = line.trim();
-        t.starts_with("notify =") && t != CODEX_NOTIFY_LINE
-    });
-    if has_other_notify {
-        println!(
-            "Skipped Codex update: notify is already configured in {}",
-            codex_file.display()
-        );
-        println!("Add Reattach manually if needed: {}", CODEX_NOTIFY_LINE);
-        return;
+/// Resolves `--agent` names, or picks registry entries matching `default`
+/// when none were given.
+fn select_agents(
+    names: &[String],
+    default: impl Fn(&dyn hooks::AgentIntegration) -> bool,
+) -> Vec<Box<dyn hooks::AgentIntegration>> {
+    if names.is_empty() {
+        return hooks::registry()
+            .into_iter()
+            .filter(|a| default(a.as_ref()))
+            .collect();
     }
-
-    let filtered: Vec<&str> = existing
-        .lines()
-        .filter(|line| {
-            let t = line.trim();
-            t != "# Reattach push notification hook" && t != CODEX_NOTIFY_LINE
+    names
+        .iter()
+        .map(|name| {
+            hooks::find(name).unwrap_or_else(|| {
+                eprintln!(
+                    "Unknown agent: {} (supported: {})",
+                    name,
+                    hooks::names().join(", ")
+                );
+                std::process::exit(2);
+            })
         })
-        .collect();
-    let mut out = format!("# Reattach push notification hook\n{}\n", CODEX_NOTIFY_LINE);
-    if !filtered.is_empty() {
-        out.push('\n');
-        out.push_str(&filtered.join("\n"));
-        out.push('\n');
-    }
-    match std::fs::write(&codex_file, out) {
-        Ok(_) => println!("Updated {}", codex_file.display()),
-        Err(e) => eprintln!("Failed to write {}: {}", codex_file.display(), e),
-    }
+        .collect()
 }
 
-fn uninstall_codex_hooks() {
-    let codex_file = match home_file(".codex/config.toml") {
-        Some(p) => p,
-        None => {
-            eprintln!("Failed to resolve home directory for Codex config");
-            return;
+/// Prints the outcome for one agent and returns whether it succeeded.
+fn report_hook_result(display_name: &str, result: Result<String, String>) -> bool {
+    match result {
+        Ok(message) => {
+            println!("{}: {}", display_name, message);
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: {}", display_name, e);
+            false
         }
-    };
-    if !codex_file.exists() {
-        println!("No Codex config file found");
-        return;
-    }
-    let existing = std::fs::read_to_string(&codex_file).unwrap_or_default();
-    let filtered: Vec<&str> = existing
-        .lines()
-        .filter(|line| {
-            let t = line.trim();
-            t != "# Reattach push notification hook" && t != CODEX_NOTIFY_LINE
-        })
-        .collect();
-    let mut out = filtered.join("\n");
-    if !out.is_empty() {
-        out.push('\n');
-    }
-    match std::fs::write(&codex_file, out) {
-        Ok(_) => println!("Updated {}", codex_file.display()),
-        Err(e) => eprintln!("Failed to write {}: {}", codex_file.display(), e),
     }
 }
 
-async fn run_notify_command(
-    from_agent_json: Option<String>,
-    body: Option<String>,
-    title: Option<String>,
-    target: Option<String>,
-    port: u16,
-    verbose: bool,
-) {
+async fn run_notify_command(data_dir: std::path::PathBuf, flags: config::Flags, args: NotifyArgs) {
     use serde_json::json;
 
+    let NotifyArgs {
+        from_agent_json,
+        agent_json,
+        body,
+        title,
+        target,
+        port,
+        verbose,
+        agent,
+        chain,
+    } = args;
+    // Codex appends its event JSON after the chained command.
+    let from_agent_json = from_agent_json.or(agent_json).or_else(|| chain.last().cloned());
+
     let mut payload = if body.is_some() || title.is_some() {
         NotifyPayload {
             title: title.unwrap_or_else(|| "Reattach".to_string()),
             body: body.unwrap_or_else(|| "Notification".to_string()),
             cwd: None,
             pane_target: None,
+            event: None,
         }
     } else {
-        let input = from_agent_json.or_else(read_stdin_if_available).unwrap_or_else(|| {
-            eprintln!("No input provided.");
-            eprintln!(
-                "Use --from-agent-json '<json>' or pipe JSON via stdin, or pass --body/--title for debug."
-            );
-            std::process::exit(2);
-        });
+        let input = from_agent_json.or_else(read_stdin_if_available);
+        let input = match (input, agent.as_deref()) {
+            (Some(input), _) => input,
+            // Some agents (aider, opencode) run the hook without a payload.
+            (None, Some(_)) => {
+                let cwd = std::env::current_dir().ok().map(|d| d.display().to_string());
+                json!({ "cwd": cwd }).to_string()
+            }
+            (None, None) => {
+                eprintln!("No input provided.");
+                eprintln!(
+                    "Use --from-agent-json '<json>' or pipe JSON via stdin, or pass --body/--title for debug."
+                );
+                std::process::exit(2);
+            }
+        };
 
-        match parse_agent_notify_payload(&input) {
+        match parse_agent_notify_payload(&input, agent.as_deref()) {
             Ok(Some(p)) => p,
             Ok(None) => {
                 // Non-target event type; skip as success.
@@ -907,75 +1609,144 @@ async fn run_notify_command(
         }
     };
 
+    // Hooks must not fail over a broken config.toml; it only matters for
+    // the port and naming the tmux server.
+    let (config_port, servers) = match config::Config::load(&data_dir, flags) {
+        Ok(config) => (config.port, config.tmux_servers),
+        Err(_) => (config::DEFAULT_PORT, tmux::Servers::default()),
+    };
+    let port = port.unwrap_or(config_port);
     let pane_target = target
         .or(payload.pane_target.clone())
-        .or_else(auto_detect_tmux_target_from_env)
+        .or_else(|| auto_detect_tmux_target_from_env(&servers))
         .or_else(|| payload.cwd.as_deref().and_then(auto_detect_tmux_target_from_cwd));
 
     if let Some(ref t) = pane_target {
         payload.title = title_for_target_and_cwd(t, payload.cwd.as_deref());
     }
 
-    let url = format!("http://localhost:{}/notify", port);
+    let notification = spool::QueuedNotification {
+        queued_at: chrono::Utc::now(),
+        title: payload.title,
+        body: payload.body,
+        subtitle: payload.event.map(|e| e.subtitle().to_string()),
+        category: payload.event.map(|e| e.category().to_string()),
+        pane_target: pane_target.clone(),
+    };
     let body = json!({
-        "title": payload.title,
-        "body": payload.body,
-        "pane_target": pane_target,
+        "title": notification.title,
+        "body": notification.body,
+        "subtitle": notification.subtitle,
+        "category": notification.category,
+        "pane_target": notification.pane_target,
     });
 
-    let client = reqwest::Client::new();
-    match client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
-        .await
-    {
+    match local::call(&data_dir, Some(port), axum::http::Method::POST, "/notify", Some(&body)).await {
         Ok(response) => {
-            if response.status().is_success() {
+            let status = response.status;
+            let deliveries = response
+                .json::<NotifyResponse>()
+                .map(|r| r.deliveries)
+                .unwrap_or_default();
+            if status.is_success() {
                 if verbose {
                     if let Some(ref t) = pane_target {
                         println!("Notification sent successfully (target: {})", t);
                     } else {
                         println!("Notification sent successfully");
                     }
+                    print_delivery_outcomes(&deliveries, false);
                 }
             } else {
-                eprintln!("Failed to send notification: HTTP {}", response.status());
+                eprintln!("Failed to send notification: HTTP {}", status);
+                print_delivery_outcomes(&deliveries, true);
                 std::process::exit(1);
             }
         }
+        // The daemon is down (e.g. restarting); it sends queued
+        // notifications when it starts.
+        Err(e @ local::LocalError::Unreachable(_)) => match spool::Spool::new(&data_dir).push(&notification) {
+            Ok(pending) => {
+                if verbose {
+                    println!("reattachd is not running; notification queued ({} pending)", pending);
+                }
+            }
+            Err(spool_error) => {
+                eprintln!("Failed to connect to reattachd: {}", e);
+                eprintln!("Failed to queue notification: {}", spool_error);
+                std::process::exit(1);
+            }
+        },
         Err(e) => {
-            eprintln!("Failed to connect to reattachd: {}", e);
-            eprintln!("Make sure reattachd daemon is running on port {}", port);
+            eprintln!("Failed to send notification: {}", e);
             std::process::exit(1);
         }
     }
 }
 
-async fn run_daemon(data_dir: std::path::PathBuf, tls_config: Option<(String, String)>, tailscale_info: Option<TailscaleInfo>) {
-    let auth_service = AuthService::new(data_dir.clone())
+#[derive(serde::Deserialize)]
+struct NotifyResponse {
+    #[serde(default)]
+    deliveries: Vec<apns::DeliveryOutcome>,
+}
+
+fn print_delivery_outcomes(deliveries: &[apns::DeliveryOutcome], to_stderr: bool) {
+    for d in deliveries {
+        let status = match d.status {
+            apns::DeliveryStatus::Delivered => "delivered",
+            apns::DeliveryStatus::Removed => "removed",
+            apns::DeliveryStatus::Failed => "failed",
+            apns::DeliveryStatus::Pending => "retrying",
+        };
+        let device = if d.device_id.is_empty() { &d.token } else { &d.device_id };
+        let mut line = format!(
+            "  {} ({}): {} after {} attempt(s)",
+            device,
+            if d.sandbox { "sandbox" } else { "production" },
+            status,
+            d.attempts
+        );
+        if let Some(ref reason) = d.reason {
+            line.push_str(&format!(" [{}]", reason));
+        }
+        if to_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+async fn run_daemon(
+    data_dir: std::path::PathBuf,
+    config: config::Config,
+    tls_config: Option<tls::CertFiles>,
+    tailscale_info: Option<TailscaleInfo>,
+    mut listeners: Vec<listen::Listener>,
+) {
+    let started_at = chrono::Utc::now();
+    // Installed first so that nothing recorded during startup is lost.
+    let metrics_handle = config.metrics_addr.as_ref().map(|_| {
+        telemetry::install().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+    let mut auth_service = AuthService::new(data_dir.clone())
         .await
         .expect("Failed to initialize auth service");
+    if tls_config.as_ref().is_some_and(|files| files.client_ca.is_some()) {
+        auth_service = auth_service.with_client_certs();
+    }
     let auth_service = Arc::new(auth_service);
 
     if !auth_service.has_devices().await {
         if let Some(ref info) = tailscale_info {
-            let port = std::env::var("REATTACHD_PORT")
-                .or_else(|_| std::env::var("PORT"))
-                .ok()
-                .and_then(|p| p.parse::<u16>().ok())
-                .unwrap_or(DEFAULT_PORT);
-            let url = format!("https://{}:{}", info.hostname, port);
-            let expires = std::env::var("REATTACHD_SETUP_EXPIRES")
-                .unwrap_or_else(|_| "10m".to_string());
-            let duration = parse_duration(&expires).unwrap_or_else(|| {
-                tracing::warn!("Invalid REATTACHD_SETUP_EXPIRES '{}', using 10m", expires);
-                chrono::Duration::minutes(10)
-            });
+            let url = format!("https://{}:{}", info.hostname, config.port);
+            let duration = parse_duration(&config.setup_expires).unwrap_or_else(|| chrono::Duration::minutes(10));
             let setup_token = auth_service.generate_setup_token(false, duration).await;
-            let setup_url = format!("{}?setup_token={}", url, setup_token);
-            print_setup_qr(&setup_url, &token_note_from_expires(&expires, false));
+            let setup_url = setup_url(&url, &setup_token, None);
+            print_setup_qr(&setup_url, &token_note_from_expires(&config.setup_expires, false));
             println!();
         } else {
             tracing::warn!("No devices registered. Run 'reattachd setup --url <URL>' to register a device.");
@@ -983,87 +1754,229 @@ async fn run_daemon(data_dir: std::path::PathBuf, tls_config: Option<(String, St
         tracing::info!("Starting in open mode (no authentication required)");
     }
 
-    let apns_service = init_apns_service(data_dir).await;
+    let apns_service = init_apns_service(data_dir.clone(), &config).await;
+    if let Some(ref apns) = apns_service {
+        let spool = spool::Spool::new(&data_dir);
+        tokio::spawn({
+            let apns = apns.clone();
+            async move { spool.drain(apns).await }
+        });
+    }
+
+    let watch_service = WatchService::new(data_dir.clone(), config.tmux_servers.clone())
+        .await
+        .expect("Failed to initialize watch service");
+    let watch_service = Arc::new(watch_service);
+    tokio::spawn(watch_service.clone().run(apns_service.clone()));
+
+    if let (Some(addr), Some(handle)) = (config.metrics_addr.clone(), metrics_handle) {
+        let stores = telemetry::Stores {
+            auth: auth_service.clone(),
+            apns: apns_service.clone(),
+            watch: watch_service.clone(),
+            data_dir: data_dir.clone(),
+        };
+        tokio::spawn(telemetry::serve(addr, handle, stores));
+    }
+
+    let default_bind: &str = match &tailscale_info {
+        Some(info) => &info.ipv4,
+        None => DEFAULT_BIND_ADDR,
+    };
+    let bind_addr = config.bind_addr.as_deref().unwrap_or(default_bind);
+    if listeners.is_empty() {
+        let scheme = if tls_config.is_some() { "https" } else { "http" };
+        let listener = listen::Listener::parse(&format!("{}://{}", scheme, bind_addr), config.port).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        listeners.push(listener);
+    }
+    let needs_tls = listeners
+        .iter()
+        .any(|listener| matches!(listener.transport, listen::Transport::Tcp { tls: true, .. }));
+    if needs_tls && tls_config.is_none() {
+        eprintln!("https:// listeners need --tailscale, --self-signed or --tls-cert/--tls-key");
+        std::process::exit(1);
+    }
+    let socket_path = local::socket_path(&data_dir);
+
+    let mut binds: Vec<String> = listeners.iter().map(|listener| listener.to_string()).collect();
+    binds.push(format!("unix:{}", socket_path.display()));
+    let status_context = Arc::new(api::StatusContext {
+        started_at,
+        binds,
+        tls_cert: tls_config.as_ref().map(|files| files.cert.clone()),
+        servers: config.tmux_servers.clone(),
+        auth: auth_service.clone(),
+        apns: apns_service.clone(),
+    });
+    let status_route = Router::new()
+        .route("/status", get(api::get_status))
+        .with_state(status_context);
 
     let auth_for_middleware = auth_service.clone();
+    let output_limits = api::OutputLimits {
+        max_lines: Arc::new(std::sync::atomic::AtomicU32::new(config.output_max_lines)),
+    };
+
+    let peers = hosts::load_peers(&data_dir)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+        .into_iter()
+        .filter_map(|peer| {
+            let id = peer.id.clone();
+            hosts::Peer::new(peer)
+                .inspect_err(|e| tracing::warn!("Ignoring peer {}: {}", id, e))
+                .ok()
+        })
+        .collect();
+    let hosts = Arc::new(hosts::Hosts::new(config.tmux_servers.clone(), peers));
+    for peer in hosts.peers() {
+        tracing::info!("Proxying peer {} at {}", peer.id, peer.url);
+    }
+    let sessions_context = Arc::new(api::SessionsContext {
+        hosts: hosts.clone(),
+        detector: Arc::new(detect::StatusDetector::default()),
+    });
+    let pane_context = api::PaneContext {
+        servers: config.tmux_servers.clone(),
+        output: output_limits.clone(),
+    };
 
     // Base routes with authentication
     let base_routes = Router::new()
-        .route("/sessions", get(api::list_sessions))
-        .route("/sessions", post(api::create_session))
-        .route("/panes/{target}", delete(api::delete_pane))
-        .route("/panes/{target}/input", post(api::send_input))
-        .route("/panes/{target}/escape", post(api::send_escape))
-        .route("/panes/{target}/output", get(api::get_output))
+        .route(
+            "/sessions",
+            get(api::list_sessions)
+                .post(api::create_session)
+                .with_state(sessions_context),
+        )
+        .merge(
+            Router::new()
+                .route("/panes/{target}", delete(api::delete_pane))
+                .route("/panes/{target}/input", post(api::send_input))
+                .route("/panes/{target}/escape", post(api::send_escape))
+                .route("/panes/{target}/output", get(api::get_output))
+                .route_layer(middleware::from_fn_with_state(hosts, hosts::proxy))
+                .with_state(pane_context),
+        )
+        .merge(
+            Router::new()
+                .route("/watchers", get(api::list_watchers).post(api::create_watcher))
+                .route("/watchers/{id}", delete(api::delete_watcher))
+                .with_state(watch_service),
+        )
+        .merge(status_route.clone())
         .layer(middleware::from_fn_with_state(
             auth_for_middleware,
             auth_middleware,
         ));
 
-    // Registration endpoint (no auth required)
+    // Registration and health endpoints (no auth required)
     let register_routes = Router::new()
         .route("/register", post(api::register_with_setup_token))
+        .route("/healthz", get(api::healthz))
         .with_state(auth_service.clone());
 
+    // Local control over the Unix socket, whose file permissions take the
+    // place of device tokens.
+    let mut local_routes = Router::new()
+        .route("/devices", get(api::list_devices))
+        .route("/devices/{id}", delete(api::revoke_device))
+        .route("/healthz", get(api::healthz))
+        .with_state(auth_service.clone())
+        .merge(status_route);
+
+    let apns_for_reload = apns_service.clone();
     let app = if let Some(apns) = apns_service {
         let devices_route = Router::new()
             .route("/devices", post(api::register_apns_device))
+            .route("/notifications", get(api::list_notifications))
+            .route("/notifications/{id}", get(api::get_notification))
             .with_state(Arc::clone(&apns))
             .layer(middleware::from_fn_with_state(
                 auth_service.clone(),
                 auth_middleware,
             ));
-        let notify_route = Router::new()
-            .route("/notify", post(api::send_notification))
-            .with_state(apns);
-        base_routes
-            .merge(devices_route)
-            .merge(notify_route)
-            .merge(register_routes)
+        // `reattachd notify` runs on this machine, so /notify is only on the
+        // control socket; over TCP anyone who can reach the port could push.
+        local_routes = local_routes.merge(
+            Router::new()
+                .route("/notify", post(api::send_notification))
+                .with_state(apns),
+        );
+        base_routes.merge(devices_route).merge(register_routes)
     } else {
         base_routes.merge(register_routes)
     };
+    let app = app.layer(middleware::from_fn(telemetry::track_requests));
 
-    let port = std::env::var("REATTACHD_PORT")
-        .or_else(|_| std::env::var("PORT"))
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(DEFAULT_PORT);
+    let (stop, shutdown) = lifecycle::Shutdown::channel();
+    let signals = lifecycle::Signals::new().unwrap_or_else(|e| {
+        eprintln!("Failed to install signal handlers: {}", e);
+        std::process::exit(1);
+    });
 
-    let default_bind: &str = match &tailscale_info {
-        Some(info) => &info.ipv4,
-        None => DEFAULT_BIND_ADDR,
-    };
-    let bind_addr = std::env::var("REATTACHD_BIND_ADDR")
-        .unwrap_or_else(|_| default_bind.to_string());
-    let addr = format!("{}:{}", bind_addr, port);
-
-    if let Some((cert_path, key_path)) = tls_config {
-        tracing::info!("Starting reattachd with TLS on {}", addr);
-        let rustls_config = axum_server::tls_rustls::RustlsConfig::from_pem_file(&cert_path, &key_path)
-            .await
-            .unwrap_or_else(|e| {
-                eprintln!("Failed to load TLS cert/key: {}", e);
-                std::process::exit(1);
+    let control_socket = match local::bind(&socket_path).await {
+        Ok(listener) => {
+            tracing::info!("Listening for local control on {}", socket_path.display());
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                let server = axum::serve(listener, local_routes).with_graceful_shutdown(shutdown.wait());
+                if let Err(e) = server.await {
+                    tracing::error!("Control socket server failed: {}", e);
+                }
             });
-        let socket_addr: std::net::SocketAddr = addr.parse().unwrap_or_else(|e| {
-            eprintln!("Invalid bind address {}: {}", addr, e);
+            Some(socket_path)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to bind control socket {}: {}", socket_path.display(), e);
+            None
+        }
+    };
+
+    let tls_reload = Arc::new(tokio::sync::Notify::new());
+    let rustls_config = tls_config.map(|cert_files| {
+        let server_config = tls::server_config(&cert_files).unwrap_or_else(|e| {
+            eprintln!("Failed to load TLS cert/key: {}", e);
             std::process::exit(1);
         });
-        axum_server::bind_rustls(socket_addr, rustls_config)
-            .serve(app.into_make_service())
-            .await
-            .unwrap();
-    } else {
-        tracing::info!("Starting reattachd on {}", addr);
-        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-        axum::serve(listener, app).await.unwrap();
+        let rustls_config = axum_server::tls_rustls::RustlsConfig::from_config(server_config);
+        tokio::spawn(tls::watch(rustls_config.clone(), cert_files, tls_reload.clone()));
+        rustls_config
+    });
+
+    let mut servers = tokio::task::JoinSet::new();
+    for listener in listeners {
+        servers.spawn(listener.serve(app.clone(), rustls_config.clone(), shutdown.clone()));
+    }
+    let drain_timeout = config.drain_timeout;
+    let reloader = lifecycle::Reloader {
+        config,
+        auth: auth_service,
+        apns: apns_for_reload,
+        tls: rustls_config.as_ref().map(|_| tls_reload),
+        output: output_limits,
+    };
+    // Listeners only return on their own when they fail, e.g. because the
+    // address is taken; the daemon doesn't run with part of them missing.
+    let result = lifecycle::supervise(servers, signals, reloader, stop, drain_timeout).await;
+    // Only our own socket; a failed bind may mean another daemon owns it.
+    if let Some(path) = control_socket {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Err(e) = result {
+        eprintln!("Failed to serve {}", e);
+        std::process::exit(1);
     }
 }
 
 async fn auth_middleware(
     State(auth_service): State<SharedAuthService>,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
     // If no devices registered, allow all requests
@@ -1071,93 +1984,83 @@ async fn auth_middleware(
         return Ok(next.run(request).await);
     }
 
-    // Check Authorization header
-    let auth_header = request
-        .headers()
-        .get("Authorization")
-        .and_then(|v| v.to_str().ok());
-
-    let token = match auth_header {
-        Some(header) if header.starts_with("Bearer ") => &header[7..],
-        _ => return Err(StatusCode::UNAUTHORIZED),
+    let reject = |reason: &'static str| {
+        metrics::counter!("reattachd_auth_failures_total", "reason" => reason).increment(1);
+        Err(StatusCode::UNAUTHORIZED)
     };
 
-    match auth_service.validate_device_token(token).await {
-        Some(device) => {
-            auth_service.update_last_seen(&device.id).await;
-            Ok(next.run(request).await)
+    // With --mtls the client certificate is the credential
+    let device = if auth_service.requires_client_certs() {
+        let fingerprint = request
+            .extensions()
+            .get::<mtls::ClientCert>()
+            .and_then(|cert| cert.0.as_deref());
+        let Some(fingerprint) = fingerprint else {
+            return reject("missing_client_cert");
+        };
+        match auth_service.validate_client_cert(fingerprint).await {
+            Some(device) => device,
+            None => return reject("invalid_client_cert"),
         }
-        None => Err(StatusCode::UNAUTHORIZED),
-    }
-}
-
-include!(concat!(env!("OUT_DIR"), "/apns_config.rs"));
-
-const XOR_KEY: &[u8] = b"reattachd_obfuscation_key_2026";
-
-fn xor_decode(hex_input: &str) -> Option<String> {
-    let bytes: Vec<u8> = (0..hex_input.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&hex_input[i..i + 2], 16))
-        .collect::<Result<Vec<u8>, _>>()
-        .ok()?;
-
-    let decoded: Vec<u8> = bytes
-        .iter()
-        .enumerate()
-        .map(|(i, b)| b ^ XOR_KEY[i % XOR_KEY.len()])
-        .collect();
-
-    String::from_utf8(decoded).ok()
-}
+    } else {
+        // Check Authorization header
+        let auth_header = request
+            .headers()
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok());
+
+        let token = match auth_header {
+            Some(header) if header.starts_with("Bearer ") => &header[7..],
+            _ => return reject("missing_token"),
+        };
+        match auth_service.validate_device_token(token).await {
+            Some(device) => device,
+            None => return reject("invalid_token"),
+        }
+    };
 
-fn get_apns_config() -> Option<(String, String, String, String)> {
-    let key_base64 = APNS_KEY_BASE64_OBFUSCATED
-        .and_then(xor_decode)
-        .or_else(|| std::env::var("APNS_KEY_BASE64").ok())?;
-    let key_id = APNS_KEY_ID_OBFUSCATED
-        .and_then(xor_decode)
-        .or_else(|| std::env::var("APNS_KEY_ID").ok())?;
-    let team_id = APNS_TEAM_ID_OBFUSCATED
-        .and_then(xor_decode)
-        .or_else(|| std::env::var("APNS_TEAM_ID").ok())?;
-    let bundle_id = APNS_BUNDLE_ID_OBFUSCATED
-        .and_then(xor_decode)
-        .or_else(|| std::env::var("APNS_BUNDLE_ID").ok())?;
-
-    Some((key_base64, key_id, team_id, bundle_id))
+    auth_service.update_last_seen(&device.id).await;
+    request.extensions_mut().insert(device);
+    Ok(next.run(request).await)
 }
 
-async fn init_apns_service(data_dir: std::path::PathBuf) -> Option<Arc<ApnsService>> {
-    let (key_base64, key_id, team_id, bundle_id) = match get_apns_config() {
-        Some(config) => config,
-        None => {
-            tracing::info!("APNs not configured");
-            return None;
+async fn init_apns_service(data_dir: std::path::PathBuf, config: &config::Config) -> Option<Arc<ApnsService>> {
+    // A key of our own wins over a relay pairing.
+    let upstream = match apns::credentials::load(&config.apns) {
+        Ok(Some(credentials)) => {
+            tracing::info!("Using the APNs key from {}", credentials.source);
+            apns::Upstream::Direct {
+                key: credentials.key,
+                key_id: credentials.key_id,
+                team_id: credentials.team_id,
+                bundle_id: credentials.bundle_id,
+                sandbox_url: config.apns.sandbox_url.clone(),
+                production_url: config.apns.production_url.clone(),
+            }
         }
-    };
-
-    use base64::{engine::general_purpose::STANDARD, Engine as _};
-    let key = match STANDARD.decode(&key_base64) {
-        Ok(bytes) => match String::from_utf8(bytes) {
-            Ok(s) => s,
+        Ok(None) => match apns::Pairing::load(&data_dir) {
+            Ok(Some(pairing)) => apns::Upstream::Relay(pairing),
+            Ok(None) => {
+                tracing::info!("APNs not configured");
+                return None;
+            }
             Err(e) => {
-                tracing::error!("Invalid UTF-8 in APNS key: {}", e);
+                tracing::error!("{}", e);
                 return None;
             }
         },
         Err(e) => {
-            tracing::error!("Invalid base64 in APNS key: {}", e);
+            tracing::error!("{}", e);
             return None;
         }
     };
 
     let apns_config = ApnsConfig {
-        key,
-        key_id,
-        team_id,
-        bundle_id,
+        upstream,
         data_dir,
+        retry_policy: apns::RetryPolicy::default(),
+        body_max_bytes: config.notify_max_bytes,
+        require_encryption: config.notify_require_encryption,
     };
 
     match ApnsService::new(apns_config).await {
@@ -1234,4 +2137,53 @@ mod tests {
     fn parse_tailscale_info_rejects_invalid_json() {
         assert!(parse_tailscale_info(b"not json").is_err());
     }
+
+    #[test]
+    fn parse_agent_notify_payload_handles_gemini_and_cursor() {
+        let gemini = r#"{ "hook_event_name": "AfterAgent", "cwd": "/work/app", "prompt_response": "Done." }"#;
+        let payload = parse_agent_notify_payload(gemini, Some("gemini")).unwrap().unwrap();
+        assert_eq!(payload.body, "Done.");
+        assert_eq!(payload.title, "app");
+
+        let cursor = r#"{ "status": "completed", "workspace_roots": ["/work/site"] }"#;
+        let payload = parse_agent_notify_payload(cursor, Some("cursor")).unwrap().unwrap();
+        assert_eq!(payload.cwd.as_deref(), Some("/work/site"));
+        assert_eq!(payload.body, "Waiting for input");
+
+        let payload = parse_agent_notify_payload("{}", Some("aider")).unwrap().unwrap();
+        assert_eq!(payload.title, "aider");
+    }
+
+    #[test]
+    fn parse_agent_notify_payload_uses_claude_hook_event() {
+        let notification = r#"{ "hook_event_name": "Notification", "cwd": "/work/app", "message": "Claude needs your permission to use Bash" }"#;
+        let payload = parse_agent_notify_payload(notification, None).unwrap().unwrap();
+        assert_eq!(payload.event, Some(hooks::HookEvent::PermissionPrompt));
+        assert_eq!(payload.body, "Claude needs your permission to use Bash");
+
+        let harmless = r#"{ "hook_event_name": "PreToolUse", "tool_name": "Bash", "tool_input": { "command": "ls" } }"#;
+        assert!(parse_agent_notify_payload(harmless, None).unwrap().is_none());
+
+        let dangerous = r#"{ "hook_event_name": "PreToolUse", "tool_name": "Bash", "tool_input": { "command": "git reset --hard" } }"#;
+        let payload = parse_agent_notify_payload(dangerous, None).unwrap().unwrap();
+        assert_eq!(payload.body, "$ git reset --hard");
+
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/transcripts");
+        let permission = format!(
+            r#"{{ "hook_event_name": "Notification", "message": "Claude needs your permission to use Bash", "transcript_path": "{}/permission_request.jsonl" }}"#,
+            fixtures
+        );
+        let payload = parse_agent_notify_payload(&permission, None).unwrap().unwrap();
+        assert_eq!(payload.body, "Claude needs your permission to use Bash\nBash: rm -rf target/debug");
+
+        let end = r#"{ "hook_event_name": "SessionEnd", "reason": "logout" }"#;
+        assert_eq!(parse_agent_notify_payload(end, None).unwrap().unwrap().body, "Session ended (logout)");
+    }
+
+    #[test]
+    fn format_uptime_uses_two_largest_units() {
+        assert_eq!(format_uptime(59), "0m");
+        assert_eq!(format_uptime(3 * 3600 + 12 * 60), "3h 12m");
+        assert_eq!(format_uptime(2 * 86400 + 5 * 3600 + 7), "2d 5h");
+    }
 }
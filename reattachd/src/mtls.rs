@@ -0,0 +1,229 @@
+//! Optional mutual TLS (`--mtls`): `/register` issues each device a client
+//! certificate signed by a CA kept in `client-ca/` in the data dir, and the
+//! device is then identified by the SHA-256 fingerprint of the certificate
+//! it presents instead of by a bearer token. Revoking the device in
+//! `auth.json` is enough to lock its certificate out.
+
+use axum::middleware::AddExtension;
+use axum::Extension;
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, PKeyRef, Private};
+use openssl::stack::Stack;
+use openssl::x509::extension::{AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage};
+use openssl::x509::{X509NameBuilder, X509Ref, X509};
+use rand::Rng;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+
+use crate::tls;
+
+const CA_NAME: &str = "Reattach client CA";
+
+/// Fingerprint of the certificate the client presented on this connection,
+/// added to every request it carries.
+#[derive(Clone, Debug)]
+pub struct ClientCert(pub Option<String>);
+
+fn ca_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("client-ca")
+}
+
+/// Creates the client CA on first use and returns the path of its
+/// certificate, for the TLS verifier.
+pub fn ensure_client_ca(data_dir: &Path) -> Result<PathBuf, String> {
+    let dir = ca_dir(data_dir);
+    tls::ensure_ca(&dir, CA_NAME)?;
+    Ok(dir.join("ca.crt"))
+}
+
+/// A client certificate and its key, bundled for the app to import.
+pub struct ClientIdentity {
+    /// DER-encoded PKCS#12 with the key, certificate and CA.
+    pub pkcs12: Vec<u8>,
+    pub password: String,
+    pub fingerprint: String,
+}
+
+pub fn issue_client_identity(data_dir: &Path, device_id: &str, device_name: &str) -> Result<ClientIdentity, String> {
+    let (ca_cert, ca_key) = tls::ensure_ca(&ca_dir(data_dir), CA_NAME)?;
+    let (cert, key) = generate_client(&ca_cert, &ca_key, device_id)
+        .map_err(|e| format!("Failed to issue client certificate: {}", e))?;
+
+    let password = base64::Engine::encode(
+        &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+        rand::thread_rng().gen::<[u8; 16]>(),
+    );
+    let mut ca_chain = Stack::new().map_err(|e| e.to_string())?;
+    ca_chain.push(ca_cert).map_err(|e| e.to_string())?;
+    let pkcs12 = Pkcs12::builder()
+        .name(device_name)
+        .pkey(&key)
+        .cert(&cert)
+        .ca(ca_chain)
+        .build2(&password)
+        .and_then(|p| p.to_der())
+        .map_err(|e| format!("Failed to bundle client certificate: {}", e))?;
+
+    Ok(ClientIdentity {
+        pkcs12,
+        password,
+        fingerprint: tls::fingerprint(&cert)?,
+    })
+}
+
+fn generate_client(
+    ca_cert: &X509Ref,
+    ca_key: &PKeyRef<Private>,
+    device_id: &str,
+) -> Result<(X509, PKey<Private>), openssl::error::ErrorStack> {
+    let key = tls::new_key()?;
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", device_id)?;
+    let name = name.build();
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    let serial = tls::serial_number()?;
+    cert.set_serial_number(&serial)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(ca_cert.subject_name())?;
+    cert.set_pubkey(&key)?;
+    // Revocation goes through the device list, so the certificate can live
+    // as long as its CA.
+    let (not_before, not_after) = (Asn1Time::days_from_now(0)?, Asn1Time::days_from_now(tls::CA_VALID_DAYS)?);
+    cert.set_not_before(&not_before)?;
+    cert.set_not_after(&not_after)?;
+    cert.append_extension(BasicConstraints::new().build()?)?;
+    cert.append_extension(KeyUsage::new().critical().digital_signature().build()?)?;
+    cert.append_extension(ExtendedKeyUsage::new().client_auth().build()?)?;
+    let authority_key_id = AuthorityKeyIdentifier::new()
+        .keyid(false)
+        .build(&cert.x509v3_context(Some(ca_cert), None))?;
+    cert.append_extension(authority_key_id)?;
+    cert.sign(ca_key, MessageDigest::sha256())?;
+    Ok((cert.build(), key))
+}
+
+/// TLS acceptor that tags each connection's requests with [`ClientCert`].
+#[derive(Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(config: RustlsConfig) -> Self {
+        Self {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+type AcceptFuture<I, S> = Pin<Box<dyn Future<Output = std::io::Result<(TlsStream<I>, AddExtension<S, ClientCert>)>> + Send>>;
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, ClientCert>;
+    type Future = AcceptFuture<I, S>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let handshake = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = handshake.await?;
+            let fingerprint = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .map(|cert| tls::der_fingerprint(cert));
+            Ok((stream, Extension(ClientCert(fingerprint)).layer(service)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthService;
+    use axum::{middleware, routing::get, Router};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn identifies_devices_by_client_certificate() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let server = tls::ensure_self_signed(data_dir, &[]).unwrap();
+        let files = tls::CertFiles {
+            cert: server.cert.clone(),
+            key: server.key.clone(),
+            renewal: None,
+            client_ca: Some(ensure_client_ca(data_dir).unwrap()),
+            data_dir: data_dir.to_path_buf(),
+        };
+
+        let auth = AuthService::new(data_dir.to_path_buf()).await.unwrap().with_client_certs();
+        let setup_token = auth.generate_setup_token(false, chrono::Duration::minutes(1)).await;
+        let device = auth.register_device(&setup_token, "phone", None).await.unwrap();
+        let identity = auth.issue_client_identity(&device.id, &device.name).await.unwrap();
+        let auth = Arc::new(auth);
+
+        let app = Router::new()
+            .route("/sessions", get(|| async { "sessions" }))
+            .layer(middleware::from_fn_with_state(auth.clone(), crate::auth_middleware));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = RustlsConfig::from_config(tls::server_config(&files).unwrap());
+        let server_task = axum_server::from_tcp(listener)
+            .unwrap()
+            .acceptor(ClientCertAcceptor::new(config))
+            .serve(app.into_make_service());
+        tokio::spawn(server_task);
+
+        // The app imports the PKCS#12 bundle; reqwest wants PEM.
+        let parsed = Pkcs12::from_der(&identity.pkcs12)
+            .unwrap()
+            .parse2(&identity.password)
+            .unwrap();
+        let mut pem = parsed.cert.unwrap().to_pem().unwrap();
+        pem.extend(parsed.pkey.unwrap().private_key_to_pem_pkcs8().unwrap());
+
+        let root = reqwest::Certificate::from_pem(&std::fs::read(data_dir.join("self-signed/ca.crt")).unwrap()).unwrap();
+        let client = |identity: Option<reqwest::Identity>| {
+            let mut builder = reqwest::Client::builder()
+                .use_rustls_tls()
+                .add_root_certificate(root.clone());
+            if let Some(identity) = identity {
+                builder = builder.identity(identity);
+            }
+            builder.build().unwrap()
+        };
+        let url = format!("https://localhost:{}/sessions", port);
+        let with_cert = client(Some(reqwest::Identity::from_pem(&pem).unwrap()));
+        let without_cert = client(None);
+
+        assert_eq!(with_cert.get(&url).send().await.unwrap().status(), 200);
+        assert_eq!(without_cert.get(&url).send().await.unwrap().status(), 401);
+        let bearer = without_cert.get(&url).bearer_auth(&device.token).send().await.unwrap();
+        assert_eq!(bearer.status(), 401, "tokens alone are not enough with --mtls");
+
+        // Keep one device registered so the API doesn't fall back to open mode.
+        let other_token = auth.generate_setup_token(false, chrono::Duration::minutes(1)).await;
+        auth.register_device(&other_token, "tablet", None).await.unwrap();
+        assert!(auth.revoke_device(&device.id).await);
+        // Checked per request, so this holds on the already open connection.
+        assert_eq!(with_cert.get(&url).send().await.unwrap().status(), 401);
+    }
+}
@@ -0,0 +1,207 @@
+//! Queue for notifications that `reattachd notify` could not hand to the
+//! daemon. Each one is a JSON file in `notify-queue/` in the data dir, named
+//! so that sorting by name gives the order they were queued in; the daemon
+//! sends them on startup.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::apns::{ApnsError, ApnsService, Notification};
+
+/// The oldest entries are dropped once the queue holds this many.
+const MAX_QUEUED: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QueuedNotification {
+    pub queued_at: DateTime<Utc>,
+    pub title: String,
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pane_target: Option<String>,
+}
+
+impl From<QueuedNotification> for Notification {
+    fn from(queued: QueuedNotification) -> Self {
+        Notification {
+            title: queued.title,
+            body: queued.body,
+            subtitle: queued.subtitle,
+            category: queued.category,
+            pane_target: queued.pane_target,
+            queued_at: Some(queued.queued_at),
+        }
+    }
+}
+
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            dir: data_dir.join("notify-queue"),
+        }
+    }
+
+    /// Queues a notification and returns how many are now waiting.
+    pub fn push(&self, notification: &QueuedNotification) -> std::io::Result<usize> {
+        std::fs::create_dir_all(&self.dir)?;
+        let stamp = notification.queued_at.timestamp_nanos_opt().unwrap_or_default();
+        let name = format!("{:020}-{}.json", stamp, uuid::Uuid::new_v4());
+        // Written under a temporary name first so that a draining daemon
+        // never sees half a file.
+        let tmp = self.dir.join(format!(".{}", name));
+        std::fs::write(&tmp, serde_json::to_vec(notification)?)?;
+        std::fs::rename(&tmp, self.dir.join(name))?;
+
+        let mut pending = self.pending();
+        if pending.len() > MAX_QUEUED {
+            for old in pending.drain(..pending.len() - MAX_QUEUED) {
+                let _ = std::fs::remove_file(old);
+            }
+        }
+        Ok(pending.len())
+    }
+
+    /// Queued files, oldest first.
+    pub fn pending(&self) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| !n.starts_with('.') && n.ends_with(".json"))
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Sends queued notifications in order, removing each once it has been
+    /// handed to APNs. Stops at the first one that can't be sent (e.g. no
+    /// device registered yet) so the rest keep their place.
+    pub async fn drain(&self, apns: Arc<ApnsService>) {
+        let pending = self.pending();
+        if pending.is_empty() {
+            return;
+        }
+        tracing::info!("Sending {} queued notification(s)", pending.len());
+
+        for path in pending {
+            let queued = std::fs::read(&path)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<QueuedNotification>(&content).ok());
+            let Some(queued) = queued else {
+                tracing::warn!("Discarding unreadable queued notification {}", path.display());
+                let _ = std::fs::remove_file(&path);
+                continue;
+            };
+            match apns.send_notification(&queued.into()).await {
+                Ok(_) => {
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        tracing::error!("Failed to remove {}: {}", path.display(), e);
+                    }
+                }
+                Err(ApnsError::NoDeviceToken) => {
+                    tracing::warn!("No device registered; keeping queued notifications");
+                    return;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to send queued notification: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(body: &str, minutes_ago: i64) -> QueuedNotification {
+        QueuedNotification {
+            queued_at: Utc::now() - chrono::Duration::minutes(minutes_ago),
+            title: "dev:0".into(),
+            body: body.into(),
+            subtitle: None,
+            category: Some("AGENT_WAITING".into()),
+            pane_target: Some("dev:0.0".into()),
+        }
+    }
+
+    fn read(path: &Path) -> QueuedNotification {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn pending_is_in_queue_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let spool = Spool::new(data_dir);
+        let first = queued("first", 2);
+        let second = queued("second", 1);
+        spool.push(&second).unwrap();
+        assert_eq!(spool.push(&first).unwrap(), 2);
+
+        let pending = spool.pending();
+        assert_eq!(read(&pending[0]), first);
+        assert_eq!(read(&pending[1]), second);
+    }
+
+    #[tokio::test]
+    async fn drain_sends_in_order_with_original_timestamps() {
+        use apns_mock::MockApns;
+
+        let sandbox = MockApns::start().await.expect("mock starts");
+        let production = MockApns::start().await.expect("mock starts");
+        let (service, temp) = crate::apns::tests::service_for(&sandbox, &production).await;
+        let service = Arc::new(service);
+        let spool = Spool::new(temp.path());
+        let first = queued("first", 5);
+        spool.push(&first).unwrap();
+        spool.push(&queued("second", 1)).unwrap();
+
+        // Nothing is lost while no device is registered.
+        spool.drain(service.clone()).await;
+        assert_eq!(spool.pending().len(), 2);
+
+        service
+            .register_device("token".into(), false, String::new(), String::new(), None)
+            .await;
+        spool.drain(service).await;
+        assert!(spool.pending().is_empty());
+
+        let requests = production.requests();
+        let bodies: Vec<&str> = requests
+            .iter()
+            .map(|r| r.payload["aps"]["alert"]["body"].as_str().unwrap())
+            .collect();
+        assert_eq!(bodies, ["first", "second"]);
+        assert_eq!(requests[0].payload["queuedAt"], first.queued_at.to_rfc3339());
+    }
+
+    #[test]
+    fn drops_oldest_beyond_limit() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let spool = Spool::new(data_dir);
+        for i in 0..(MAX_QUEUED + 3) {
+            spool.push(&queued(&i.to_string(), (MAX_QUEUED + 3 - i) as i64)).unwrap();
+        }
+        let pending = spool.pending();
+        assert_eq!(pending.len(), MAX_QUEUED);
+        assert_eq!(read(&pending[0]).body, "3");
+    }
+}
@@ -0,0 +1,211 @@
+//! Turning agent messages (markdown, often several paragraphs) into a short
+//! plain-text notification body.
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+static IMAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap());
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([^\]]+)\]\([^)]*\)").unwrap());
+static EMPHASIS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\*\*|__|~~)(\S(?:.*?\S)?)(\*\*|__|~~)|\*(\S(?:[^*]*?\S)?)\*").unwrap());
+static CODE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"`([^`]+)`").unwrap());
+static BLOCK_PREFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:#{1,6}\s+|>\s?|[-*+]\s+\[[ xX]\]\s+|[-*+]\s+|\d+[.)]\s+)").unwrap());
+static RULE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*(?:[-*_]\s*){3,}$|^\s*\|?[\s:|-]+\|[\s:|-]*$").unwrap());
+
+pub const ELLIPSIS: &str = "…";
+
+/// Converts markdown to plain text: code blocks and rules are dropped,
+/// inline markup is unwrapped, and the lines of a paragraph are joined.
+/// Paragraphs and list items end up on separate lines.
+pub fn strip_markdown(text: &str) -> String {
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_fence = false;
+
+    let mut flush = |current: &mut String| {
+        if !current.is_empty() {
+            paragraphs.push(std::mem::take(current));
+        }
+    };
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            flush(&mut current);
+            continue;
+        }
+        if in_fence || RULE.is_match(line) {
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush(&mut current);
+            continue;
+        }
+
+        let starts_block = BLOCK_PREFIX.is_match(line) || trimmed.starts_with('|');
+        let mut plain = BLOCK_PREFIX.replace(line, "").into_owned();
+        if trimmed.starts_with('|') {
+            plain = plain
+                .split('|')
+                .map(str::trim)
+                .filter(|cell| !cell.is_empty())
+                .collect::<Vec<_>>()
+                .join(", ");
+        }
+        let plain = IMAGE.replace_all(&plain, "$1");
+        let plain = LINK.replace_all(&plain, "$1");
+        let plain = CODE.replace_all(&plain, "$1");
+        let plain = EMPHASIS.replace_all(&plain, "$2$4");
+        let plain = plain.trim();
+        if plain.is_empty() {
+            continue;
+        }
+
+        // Headings and list items stand on their own.
+        if starts_block {
+            flush(&mut current);
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(plain);
+    }
+    flush(&mut current);
+    paragraphs.join("\n")
+}
+
+/// Splits plain text into sentences, keeping their punctuation. A line
+/// break always ends a sentence.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    for line in text.lines() {
+        let mut start = 0;
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if !matches!(c, '.' | '?' | '!') {
+                continue;
+            }
+            // "e.g. foo" and "v1.2" are not sentence ends.
+            let next = line[i + c.len_utf8()..].chars().nth(1);
+            let at_space = chars.peek().is_some_and(|(_, n)| n.is_whitespace());
+            if at_space && !next.is_some_and(char::is_lowercase) {
+                out.push(line[start..=i].trim());
+                start = i + 1;
+            }
+        }
+        let rest = line[start..].trim();
+        if !rest.is_empty() {
+            out.push(rest);
+        }
+    }
+    out.retain(|s| !s.is_empty());
+    out
+}
+
+/// Cuts `text` to at most `max_bytes`, preferring a word boundary and
+/// marking the cut with an ellipsis.
+pub fn truncate(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let Some(room) = max_bytes.checked_sub(ELLIPSIS.len()) else {
+        return String::new();
+    };
+    let mut end = room;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &text[..end];
+    // Back up to the last space unless that would throw away most of it.
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(space) if space > end / 2 => &cut[..space],
+        _ => cut,
+    };
+    format!("{}{}", cut.trim_end(), ELLIPSIS)
+}
+
+/// Plain-text body of at most `max_bytes` for `text`.
+///
+/// Short messages are only stripped of markdown. Longer ones are cut down
+/// to the sentence that matters most: the last question, since agents end a
+/// turn by asking what to do next, or otherwise the opening sentence, which
+/// usually states the outcome. Neighbouring sentences are added back while
+/// they fit.
+pub fn summarize(text: &str, max_bytes: usize) -> String {
+    let plain = strip_markdown(text);
+    if plain.len() <= max_bytes {
+        return plain;
+    }
+
+    let sentences = sentences(&plain);
+    let Some(question) = sentences.iter().rposition(|s| s.ends_with('?')) else {
+        let mut body = String::new();
+        for sentence in &sentences {
+            if !body.is_empty() && body.len() + 1 + sentence.len() > max_bytes {
+                break;
+            }
+            if !body.is_empty() {
+                body.push(' ');
+            }
+            body.push_str(sentence);
+        }
+        return truncate(&body, max_bytes);
+    };
+
+    // Give the question as much context as fits before it.
+    let mut start = question;
+    let mut len = sentences[question].len();
+    while start > 0 && len + 1 + sentences[start - 1].len() <= max_bytes {
+        start -= 1;
+        len += 1 + sentences[start].len();
+    }
+    truncate(&sentences[start..=question].join(" "), max_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_markdown() {
+        let text = "## Summary\n\nI updated **two** files in `src/`:\n\n- [main.rs](src/main.rs): added *retry*\n- lib.rs\n\n```rust\nfn main() {}\n```\n\n---\n\n| File | Lines |\n|------|------:|\n| main.rs | 12 |\n\n> Note: run `cargo test`\nbefore merging.";
+        assert_eq!(
+            strip_markdown(text),
+            "Summary\nI updated two files in src/:\nmain.rs: added retry\nlib.rs\nFile, Lines\nmain.rs, 12\nNote: run cargo test before merging."
+        );
+        assert_eq!(strip_markdown("keep snake_case and 2 * 3 * 4"), "keep snake_case and 2 * 3 * 4");
+    }
+
+    #[test]
+    fn short_messages_are_kept_whole() {
+        assert_eq!(summarize("All **42** tests pass.", 100), "All 42 tests pass.");
+    }
+
+    #[test]
+    fn prefers_the_last_question() {
+        let text = "I refactored the parser. See e.g. the new `Lexer` type. \
+                    Tests were updated too.\n\nShould I also bump the version? \
+                    Let me know.";
+        let summary = summarize(text, 60);
+        assert_eq!(summary, "Tests were updated too. Should I also bump the version?");
+    }
+
+    #[test]
+    fn falls_back_to_the_opening_sentences() {
+        let text = "The build is green. I fixed three flaky tests in the scheduler module. \
+                    The remaining failures are unrelated.";
+        assert_eq!(summarize(text, 30), "The build is green.");
+    }
+
+    #[test]
+    fn stays_within_budget_on_char_boundaries() {
+        let text = "日本語のテキスト".repeat(100);
+        let summary = summarize(&text, 50);
+        assert!(summary.len() <= 50, "{} bytes", summary.len());
+        assert!(summary.ends_with(ELLIPSIS));
+        assert_eq!(truncate("abc", 2), "");
+        assert_eq!(truncate("hello wonderful world", 19), "hello wonderful…");
+    }
+}
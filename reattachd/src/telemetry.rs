@@ -0,0 +1,149 @@
+//! Optional Prometheus metrics, served on their own listener
+//! (`--metrics-addr`). Without it no recorder is installed and the
+//! `metrics` macros used across the daemon do nothing.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+    routing::get,
+    Router,
+};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::apns::ApnsService;
+use crate::auth::SharedAuthService;
+use crate::spool::Spool;
+use crate::watch::SharedWatchService;
+
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+/// Latency buckets in seconds, from a quick API call to a slow tmux capture
+/// or APNs round trip.
+const DURATION_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Services whose persisted store sizes are reported at scrape time.
+pub struct Stores {
+    pub auth: SharedAuthService,
+    pub apns: Option<Arc<ApnsService>>,
+    pub watch: SharedWatchService,
+    pub data_dir: PathBuf,
+}
+
+struct MetricsState {
+    handle: PrometheusHandle,
+    stores: Stores,
+}
+
+/// Installs the global recorder. Must run before any metric is recorded
+/// for that metric to be exported.
+pub fn install() -> Result<PrometheusHandle, String> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets(DURATION_BUCKETS)
+        .map_err(|e| e.to_string())?
+        .install_recorder()
+        .map_err(|e| format!("Failed to install metrics recorder: {}", e))?;
+
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+    Ok(handle)
+}
+
+/// Serves `GET /metrics` on `addr` until the daemon exits.
+pub async fn serve(addr: String, handle: PrometheusHandle, stores: Stores) {
+    let app = Router::new()
+        .route("/metrics", get(render))
+        .with_state(Arc::new(MetricsState { handle, stores }));
+    match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => {
+            tracing::info!("Serving metrics on http://{}/metrics", addr);
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("Metrics server failed: {}", e);
+            }
+        }
+        Err(e) => tracing::error!("Failed to bind metrics listener {}: {}", addr, e),
+    }
+}
+
+async fn render(State(state): State<Arc<MetricsState>>) -> String {
+    let stores = &state.stores;
+    let gauge = |store: &'static str, size: usize| {
+        metrics::gauge!("reattachd_store_entries", "store" => store).set(size as f64);
+    };
+    gauge("devices", stores.auth.list_devices().await.len());
+    gauge("watchers", stores.watch.list().await.len());
+    gauge("notify_queue", Spool::new(&stores.data_dir).pending().len());
+    if let Some(ref apns) = stores.apns {
+        gauge("push_tokens", apns.token_count().await);
+        gauge("notification_history", apns.history_len().await);
+    }
+    state.handle.render()
+}
+
+/// Counts requests and records their latency per route template (e.g.
+/// `/panes/{target}/output`), so pane names don't explode label cardinality.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+
+    let in_flight = metrics::gauge!("reattachd_http_requests_in_flight");
+    in_flight.increment(1.0);
+    let start = Instant::now();
+    let response = next.run(request).await;
+    in_flight.decrement(1.0);
+
+    let status = response.status().as_u16().to_string();
+    metrics::counter!("reattachd_http_requests_total", "method" => method.clone(), "route" => route.clone(), "status" => status)
+        .increment(1);
+    metrics::histogram!("reattachd_http_request_duration_seconds", "method" => method, "route" => route)
+        .record(start.elapsed().as_secs_f64());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn labels_requests_by_route_template() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        // The test runtime is single-threaded, so the server task below
+        // records into this thread's recorder.
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let app = Router::new()
+            .route("/panes/{target}/output", get(|| async { "output" }))
+            .layer(axum::middleware::from_fn(track_requests));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for pane in ["dev:0.0", "dev:0.1"] {
+            let url = format!("http://{}/panes/{}/output", addr, pane);
+            assert!(reqwest::get(url).await.unwrap().status().is_success());
+        }
+
+        let rendered = handle.render();
+        assert!(
+            rendered.contains(
+                r#"reattachd_http_requests_total{method="GET",route="/panes/{target}/output",status="200"} 2"#
+            ),
+            "{}",
+            rendered
+        );
+        assert!(!rendered.contains("dev:0.0"));
+    }
+}
@@ -0,0 +1,440 @@
+//! Keeps the served TLS certificate current while the daemon runs. Tailscale
+//! and self-signed certificates are renewed before they expire, and the
+//! rustls config is reloaded whenever the certificate or key file changes,
+//! whether renewed here or by an external tool such as certbot. Reloading
+//! only affects new handshakes; established connections keep going.
+//!
+//! Self-signed mode (`--self-signed`) keeps a local CA and a leaf
+//! certificate signed by it in `self-signed/` in the data dir. The app pins
+//! the CA by the fingerprint in the setup URL, so the leaf can be reissued
+//! (on expiry, or to cover a new hostname) without pairing again.
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, PKeyRef, Private};
+use openssl::x509::extension::{
+    AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName,
+    SubjectKeyIdentifier,
+};
+use openssl::x509::{X509NameBuilder, X509Ref, X509};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Notify;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// A failed renewal is retried no more often than this.
+const RENEW_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) const CA_VALID_DAYS: u32 = 3650;
+/// Within the 398-day limit Apple applies to server certificates.
+const LEAF_VALID_DAYS: u32 = 397;
+/// Names every self-signed leaf covers.
+const DEFAULT_NAMES: &[&str] = &["localhost", "127.0.0.1", "::1"];
+
+/// How an expiring certificate gets renewed; `None` in [`CertFiles`] leaves
+/// renewal to whoever provided it.
+pub enum Renewal {
+    /// `tailscale cert` for this hostname.
+    Tailscale(String),
+    SelfSigned,
+}
+
+pub struct CertFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub renewal: Option<Renewal>,
+    /// CA whose client certificates identify devices (`--mtls`).
+    pub client_ca: Option<PathBuf>,
+    pub data_dir: PathBuf,
+}
+
+/// Builds the rustls config for `files`. With a client CA, clients may
+/// present a certificate signed by it; one is not required at the TLS layer
+/// so that a new device can reach `/register`, and `auth_middleware` requires
+/// it everywhere else.
+pub fn server_config(files: &CertFiles) -> Result<Arc<ServerConfig>, String> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e));
+    let chain = CertificateDer::pem_slice_iter(&read(&files.cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate {}: {}", files.cert.display(), e))?;
+    let key = PrivateKeyDer::from_pem_slice(&read(&files.key)?)
+        .map_err(|e| format!("Invalid private key {}: {}", files.key.display(), e))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match files.client_ca {
+        Some(ref ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_slice_iter(&read(ca)?) {
+                let cert = cert.map_err(|e| format!("Invalid client CA {}: {}", ca.display(), e))?;
+                roots.add(cert).map_err(|e| format!("Invalid client CA {}: {}", ca.display(), e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .allow_unauthenticated()
+                .build()
+                .map_err(|e| e.to_string())?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(chain, key).map_err(|e| e.to_string())?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Modification time and size of the certificate and key files.
+type Stamp = [(SystemTime, u64); 2];
+
+fn stamp(files: &CertFiles) -> Option<Stamp> {
+    let stat = |path: &PathBuf| {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    };
+    Some([stat(&files.cert)?, stat(&files.key)?])
+}
+
+/// Runs until the daemon exits. Notifying `reload` reloads the files right
+/// away, whether or not they look changed.
+pub async fn watch(config: RustlsConfig, files: CertFiles, reload: Arc<Notify>) {
+    let mut loaded = stamp(&files);
+    let mut last_renewal: Option<Instant> = None;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        let forced = tokio::select! {
+            _ = interval.tick() => false,
+            _ = reload.notified() => true,
+        };
+
+        if let Some(ref renewal) = files.renewal {
+            let expiring = crate::cert_remaining_days(&files.cert)
+                .is_none_or(|days| days < crate::CERT_MIN_REMAINING_DAYS);
+            if expiring && last_renewal.is_none_or(|at| at.elapsed() >= RENEW_RETRY_INTERVAL) {
+                last_renewal = Some(Instant::now());
+                let data_dir = files.data_dir.clone();
+                let result = match renewal {
+                    Renewal::Tailscale(hostname) => {
+                        let hostname = hostname.clone();
+                        tokio::task::spawn_blocking(move || {
+                            crate::generate_tailscale_certs(&hostname, &data_dir).map(|_| ())
+                        })
+                        .await
+                    }
+                    Renewal::SelfSigned => {
+                        tokio::task::spawn_blocking(move || ensure_self_signed(&data_dir, &[]).map(|_| ())).await
+                    }
+                };
+                match result {
+                    Ok(Ok(())) => tracing::info!("Renewed TLS certificate {}", files.cert.display()),
+                    Ok(Err(e)) => tracing::error!("Failed to renew TLS certificate: {}", e),
+                    Err(e) => tracing::error!("Failed to renew TLS certificate: {}", e),
+                }
+            }
+        }
+
+        loaded = reload_if_changed(&config, &files, loaded, forced).await;
+    }
+}
+
+/// Reloads `config` if the files differ from the `loaded` ones, or always if
+/// `forced`, and returns the stamp of whatever is now being served. A pair
+/// that fails to load (say, the certificate was replaced but the key not yet)
+/// leaves the current config in place and is tried again on the next check.
+async fn reload_if_changed(
+    config: &RustlsConfig,
+    files: &CertFiles,
+    loaded: Option<Stamp>,
+    forced: bool,
+) -> Option<Stamp> {
+    let current = stamp(files);
+    if current.is_none() || (current == loaded && !forced) {
+        return loaded;
+    }
+    match server_config(files) {
+        Ok(server_config) => {
+            config.reload_from_config(server_config);
+            tracing::info!("Reloaded TLS certificate {}", files.cert.display());
+            current
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Failed to reload TLS certificate {}: {}; keeping the current one",
+                files.cert.display(),
+                e
+            );
+            loaded
+        }
+    }
+}
+
+/// Paths of the self-signed leaf certificate (followed by its CA) and key,
+/// and the SHA-256 fingerprint of the CA for the app to pin.
+pub struct SelfSigned {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub ca_fingerprint: String,
+}
+
+/// Creates the local CA on first use and (re)issues the leaf certificate if
+/// it is missing, about to expire, or doesn't cover all of `names`. Names
+/// the current leaf already covers are kept.
+pub fn ensure_self_signed(data_dir: &Path, names: &[String]) -> Result<SelfSigned, String> {
+    let dir = data_dir.join("self-signed");
+    let (ca_cert, ca_key) = ensure_ca(&dir, "Reattach local CA")?;
+    let cert_path = dir.join("server.crt");
+    let key_path = dir.join("server.key");
+
+    let current = load_pair(&cert_path, &key_path)
+        .map(|(cert, _)| cert)
+        .filter(|cert| cert.verify(&ca_key).unwrap_or(false));
+    let mut wanted: Vec<String> = Vec::new();
+    let known = DEFAULT_NAMES.iter().map(|name| name.to_string());
+    for name in known.chain(current.iter().flat_map(|cert| alt_names(cert))).chain(names.iter().cloned()) {
+        if !wanted.contains(&name) {
+            wanted.push(name);
+        }
+    }
+
+    let usable = current.as_ref().is_some_and(|cert| {
+        let covered = alt_names(cert);
+        wanted.iter().all(|name| covered.contains(name))
+            && crate::cert_remaining_days(&cert_path).is_some_and(|days| days >= crate::CERT_MIN_REMAINING_DAYS)
+    });
+    if !usable {
+        tracing::info!("Issuing self-signed TLS certificate for {}", wanted.join(", "));
+        let (cert, key) =
+            generate_leaf(&ca_cert, &ca_key, &wanted).map_err(|e| format!("Failed to issue certificate: {}", e))?;
+        let mut chain = cert.to_pem().map_err(|e| e.to_string())?;
+        chain.extend(ca_cert.to_pem().map_err(|e| e.to_string())?);
+        crate::fsutil::write_with_mode(&cert_path, &chain, 0o644)?;
+        crate::fsutil::write_private(&key_path, &key.private_key_to_pem_pkcs8().map_err(|e| e.to_string())?)?;
+    }
+
+    Ok(SelfSigned {
+        cert: cert_path,
+        key: key_path,
+        ca_fingerprint: fingerprint(&ca_cert)?,
+    })
+}
+
+/// Loads the CA kept as `ca.crt`/`ca.key` in `dir`, creating it on first use.
+pub(crate) fn ensure_ca(dir: &Path, common_name: &str) -> Result<(X509, PKey<Private>), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    crate::restrict_permissions(dir, 0o700);
+
+    let cert_path = dir.join("ca.crt");
+    let key_path = dir.join("ca.key");
+    if let Some(pair) = load_pair(&cert_path, &key_path) {
+        return Ok(pair);
+    }
+    tracing::info!("Creating {} in {}", common_name, dir.display());
+    let (cert, key) = generate_ca(common_name).map_err(|e| format!("Failed to create CA: {}", e))?;
+    crate::fsutil::write_with_mode(&cert_path, &cert.to_pem().map_err(|e| e.to_string())?, 0o644)?;
+    crate::fsutil::write_private(&key_path, &key.private_key_to_pem_pkcs8().map_err(|e| e.to_string())?)?;
+    Ok((cert, key))
+}
+
+/// Lowercase hex SHA-256 of the certificate's DER encoding.
+pub fn fingerprint(cert: &X509Ref) -> Result<String, String> {
+    Ok(der_fingerprint(&cert.to_der().map_err(|e| e.to_string())?))
+}
+
+pub(crate) fn der_fingerprint(der: &[u8]) -> String {
+    openssl::sha::sha256(der).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn load_pair(cert_path: &Path, key_path: &Path) -> Option<(X509, PKey<Private>)> {
+    let cert = X509::from_pem(&std::fs::read(cert_path).ok()?).ok()?;
+    let key = PKey::private_key_from_pem(&std::fs::read(key_path).ok()?).ok()?;
+    Some((cert, key))
+}
+
+/// DNS names and IP addresses in the certificate's subjectAltName.
+fn alt_names(cert: &X509Ref) -> Vec<String> {
+    let Some(names) = cert.subject_alt_names() else {
+        return Vec::new();
+    };
+    names
+        .iter()
+        .filter_map(|name| {
+            if let Some(dns) = name.dnsname() {
+                return Some(dns.to_string());
+            }
+            let ip: IpAddr = match name.ipaddress()? {
+                &[a, b, c, d] => [a, b, c, d].into(),
+                bytes => <[u8; 16]>::try_from(bytes).ok()?.into(),
+            };
+            Some(ip.to_string())
+        })
+        .collect()
+}
+
+pub(crate) fn new_key() -> Result<PKey<Private>, openssl::error::ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
+pub(crate) fn serial_number() -> Result<openssl::asn1::Asn1Integer, openssl::error::ErrorStack> {
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+    serial.to_asn1_integer()
+}
+
+fn generate_ca(common_name: &str) -> Result<(X509, PKey<Private>), openssl::error::ErrorStack> {
+    let key = new_key()?;
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", common_name)?;
+    let name = name.build();
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    let serial = serial_number()?;
+    cert.set_serial_number(&serial)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(&name)?;
+    cert.set_pubkey(&key)?;
+    let (not_before, not_after) = (Asn1Time::days_from_now(0)?, Asn1Time::days_from_now(CA_VALID_DAYS)?);
+    cert.set_not_before(&not_before)?;
+    cert.set_not_after(&not_after)?;
+    cert.append_extension(BasicConstraints::new().critical().ca().pathlen(0).build()?)?;
+    cert.append_extension(KeyUsage::new().critical().key_cert_sign().crl_sign().build()?)?;
+    let subject_key_id = SubjectKeyIdentifier::new().build(&cert.x509v3_context(None, None))?;
+    cert.append_extension(subject_key_id)?;
+    cert.sign(&key, MessageDigest::sha256())?;
+    Ok((cert.build(), key))
+}
+
+fn generate_leaf(
+    ca_cert: &X509Ref,
+    ca_key: &PKeyRef<Private>,
+    names: &[String],
+) -> Result<(X509, PKey<Private>), openssl::error::ErrorStack> {
+    let key = new_key()?;
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", names.first().map(String::as_str).unwrap_or("localhost"))?;
+    let name = name.build();
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    let serial = serial_number()?;
+    cert.set_serial_number(&serial)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(ca_cert.subject_name())?;
+    cert.set_pubkey(&key)?;
+    let (not_before, not_after) = (Asn1Time::days_from_now(0)?, Asn1Time::days_from_now(LEAF_VALID_DAYS)?);
+    cert.set_not_before(&not_before)?;
+    cert.set_not_after(&not_after)?;
+    cert.append_extension(BasicConstraints::new().build()?)?;
+    cert.append_extension(KeyUsage::new().critical().digital_signature().build()?)?;
+    cert.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+    let mut alt = SubjectAlternativeName::new();
+    for name in names {
+        match name.parse::<IpAddr>() {
+            Ok(_) => alt.ip(name),
+            Err(_) => alt.dns(name),
+        };
+    }
+    let alt = alt.build(&cert.x509v3_context(Some(ca_cert), None))?;
+    cert.append_extension(alt)?;
+    let authority_key_id = AuthorityKeyIdentifier::new()
+        .keyid(false)
+        .build(&cert.x509v3_context(Some(ca_cert), None))?;
+    cert.append_extension(authority_key_id)?;
+    cert.sign(ca_key, MessageDigest::sha256())?;
+    Ok((cert.build(), key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn write_certificate(files: &CertFiles, modified: SystemTime) {
+        let (ca_cert, ca_key) = generate_ca("reattachd test CA").unwrap();
+        let (cert, key) = generate_leaf(&ca_cert, &ca_key, &["localhost".to_string()]).unwrap();
+
+        std::fs::write(&files.cert, cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&files.key, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        for path in [&files.cert, &files.key] {
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(modified).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_files_and_keeps_config_on_bad_ones() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let temp = tempfile::tempdir().unwrap();
+        let data_dir = temp.path();
+        let files = CertFiles {
+            cert: data_dir.join("server.crt"),
+            key: data_dir.join("server.key"),
+            renewal: None,
+            client_ca: None,
+            data_dir: data_dir.to_path_buf(),
+        };
+        let start = SystemTime::now() - Duration::from_secs(3600);
+        write_certificate(&files, start);
+        let config = RustlsConfig::from_pem_file(&files.cert, &files.key).await.unwrap();
+        let loaded = stamp(&files);
+
+        let before = config.get_inner();
+        assert_eq!(reload_if_changed(&config, &files, loaded, false).await, loaded);
+        assert!(Arc::ptr_eq(&before, &config.get_inner()), "unchanged files are not reloaded");
+
+        write_certificate(&files, start + Duration::from_secs(60));
+        let renewed = reload_if_changed(&config, &files, loaded, false).await;
+        assert_ne!(renewed, loaded);
+        assert!(!Arc::ptr_eq(&before, &config.get_inner()));
+
+        let before = config.get_inner();
+        assert_eq!(reload_if_changed(&config, &files, renewed, true).await, renewed);
+        assert!(!Arc::ptr_eq(&before, &config.get_inner()), "forced reloads ignore the stamp");
+
+        let before = config.get_inner();
+        std::fs::write(&files.key, "not a key").unwrap();
+        assert_eq!(reload_if_changed(&config, &files, renewed, false).await, renewed);
+        assert!(Arc::ptr_eq(&before, &config.get_inner()));
+    }
+
+    #[tokio::test]
+    async fn self_signed_ca_survives_leaf_reissue() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let temp = tempfile::tempdir().unwrap();
+
+        let data_dir = temp.path();
+
+        let first = ensure_self_signed(data_dir, &[]).unwrap();
+        assert_eq!(first.ca_fingerprint.len(), 64);
+        let leaf = std::fs::read(&first.cert).unwrap();
+        let again = ensure_self_signed(data_dir, &["localhost".to_string()]).unwrap();
+        assert_eq!(std::fs::read(&again.cert).unwrap(), leaf, "covered names keep the leaf");
+
+        let lan = ensure_self_signed(data_dir, &["192.168.1.20".to_string()]).unwrap();
+        assert_eq!(lan.ca_fingerprint, first.ca_fingerprint);
+        let chain = X509::stack_from_pem(&std::fs::read(&lan.cert).unwrap()).unwrap();
+        assert_eq!(fingerprint(&chain[1]).unwrap(), first.ca_fingerprint);
+        let names = alt_names(&chain[0]);
+        for name in ["localhost", "127.0.0.1", "::1", "192.168.1.20"] {
+            assert!(names.contains(&name.to_string()), "{} in {:?}", name, names);
+        }
+        assert!(chain[0].verify(&chain[1].public_key().unwrap()).unwrap());
+        RustlsConfig::from_pem_file(&lan.cert, &lan.key).await.unwrap();
+
+        use std::os::unix::fs::PermissionsExt;
+        for key in [&lan.key, &data_dir.join("self-signed/ca.key")] {
+            let mode = std::fs::metadata(key).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -1,22 +1,25 @@
-use std::process::Command;
+use crate::tmux::{Server, TmuxError};
 
-use crate::tmux::TmuxError;
+pub fn capture_pane(server: &Server, target: &str, lines: u32) -> Result<String, TmuxError> {
+    capture(server, target, lines, true)
+}
+
+/// Like [`capture_pane`], but without escape sequences, for matching against
+/// the visible text.
+pub fn capture_pane_text(server: &Server, target: &str, lines: u32) -> Result<String, TmuxError> {
+    capture(server, target, lines, false)
+}
 
-pub fn capture_pane(target: &str, lines: u32) -> Result<String, TmuxError> {
+fn capture(server: &Server, target: &str, lines: u32, escapes: bool) -> Result<String, TmuxError> {
     let start_line = format!("-{}", lines);
 
-    let output = Command::new("tmux")
-        .args([
-            "capture-pane",
-            "-t",
-            target,
-            "-p",
-            "-e",
-            "-S",
-            &start_line,
-        ])
-        .output()
-        .map_err(TmuxError::Io)?;
+    let mut args = vec!["capture-pane", "-t", target, "-p"];
+    if escapes {
+        args.push("-e");
+    }
+    args.extend(["-S", &start_line]);
+
+    let output = super::run(server, "capture_pane", &args)?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -1,39 +1,39 @@
-use std::process::Command;
-
-use crate::tmux::TmuxError;
+use crate::tmux::{Server, TmuxError};
 
 const SESSION_PREFIX: &str = "claude-";
 
-pub fn create_session(name: &str, cwd: &str) -> Result<(), TmuxError> {
+pub fn create_session(server: &Server, name: &str, cwd: &str) -> Result<(), TmuxError> {
     let session_name = format!("{}{}", SESSION_PREFIX, name);
 
-    let output = Command::new("tmux")
-        .args([
+    let output = super::run(
+        server,
+        "create_session",
+        &[
             "new-session",
             "-d",
             "-s",
             &session_name,
             "-c",
             cwd,
-        ])
-        .output()
-        .map_err(TmuxError::Io)?;
+        ],
+    )?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(TmuxError::Command(stderr.to_string()));
     }
 
-    let output = Command::new("tmux")
-        .args([
+    let output = super::run(
+        server,
+        "create_session",
+        &[
             "send-keys",
             "-t",
             &session_name,
             "claude",
             "Enter",
-        ])
-        .output()
-        .map_err(TmuxError::Io)?;
+        ],
+    )?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
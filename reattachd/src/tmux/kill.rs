@@ -1,12 +1,7 @@
-use std::process::Command;
+use crate::tmux::{Server, TmuxError};
 
-use crate::tmux::TmuxError;
-
-pub fn kill_pane(target: &str) -> Result<(), TmuxError> {
-    let output = Command::new("tmux")
-        .args(["kill-pane", "-t", target])
-        .output()
-        .map_err(TmuxError::Io)?;
+pub fn kill_pane(server: &Server, target: &str) -> Result<(), TmuxError> {
+    let output = super::run(server, "kill_pane", &["kill-pane", "-t", target])?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -1,7 +1,5 @@
 use serde::Serialize;
-use std::process::Command;
-
-use crate::tmux::TmuxError;
+use crate::tmux::{Server, TmuxError};
 
 #[derive(Debug, Serialize)]
 pub struct Pane {
@@ -9,6 +7,9 @@ pub struct Pane {
     pub active: bool,
     pub target: String,
     pub current_path: String,
+    pub current_command: String,
+    pub dead: bool,
+    pub cursor_y: u32,
 }
 
 #[derive(Debug, Serialize)]
@@ -26,16 +27,17 @@ pub struct Session {
     pub windows: Vec<Window>,
 }
 
-pub fn list_sessions() -> Result<Vec<Session>, TmuxError> {
-    let output = Command::new("tmux")
-        .args([
+pub fn list_sessions(server: &Server) -> Result<Vec<Session>, TmuxError> {
+    let output = super::run(
+        server,
+        "list_sessions",
+        &[
             "list-panes",
             "-a",
             "-F",
-            "#{session_name}|#{session_attached}|#{window_index}|#{window_name}|#{window_active}|#{pane_index}|#{pane_active}|#{pane_current_path}",
-        ])
-        .output()
-        .map_err(TmuxError::Io)?;
+            "#{session_name}|#{session_attached}|#{window_index}|#{window_name}|#{window_active}|#{pane_index}|#{pane_active}|#{pane_current_command}|#{pane_dead}|#{cursor_y}|#{pane_current_path}",
+        ],
+    )?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -52,8 +54,9 @@ pub fn list_sessions() -> Result<Vec<Session>, TmuxError> {
     let mut sessions: Vec<Session> = Vec::new();
 
     for line in stdout.lines() {
-        let parts: Vec<&str> = line.split('|').collect();
-        if parts.len() != 8 {
+        // The path goes last so that a '|' inside it cannot shift the other fields.
+        let parts: Vec<&str> = line.splitn(11, '|').collect();
+        if parts.len() != 11 {
             continue;
         }
 
@@ -64,7 +67,10 @@ pub fn list_sessions() -> Result<Vec<Session>, TmuxError> {
         let window_active = parts[4] == "1";
         let pane_index: u32 = parts[5].parse().unwrap_or(0);
         let pane_active = parts[6] == "1";
-        let current_path = parts[7].to_string();
+        let current_command = parts[7].to_string();
+        let dead = parts[8] == "1";
+        let cursor_y: u32 = parts[9].parse().unwrap_or(0);
+        let current_path = parts[10].to_string();
 
         let target = format!("{}:{}.{}", session_name, window_index, pane_index);
 
@@ -73,6 +79,9 @@ pub fn list_sessions() -> Result<Vec<Session>, TmuxError> {
             active: pane_active,
             target,
             current_path,
+            current_command,
+            dead,
+            cursor_y,
         };
 
         let session = sessions.iter_mut().find(|s| s.name == session_name);
@@ -1,14 +1,21 @@
+use std::process::{Command, Output};
+use std::time::Instant;
+
 mod capture;
 mod create;
 mod kill;
 mod list;
 mod send;
+mod server;
+mod state;
 
-pub use capture::capture_pane;
+pub use capture::{capture_pane, capture_pane_text};
 pub use create::create_session;
 pub use kill::kill_pane;
-pub use list::list_sessions;
+pub use list::{list_sessions, Pane};
 pub use send::{send_escape, send_keys};
+pub use server::{is_valid_id, split_target, Server, Servers, DEFAULT_SERVER, SEPARATOR};
+pub use state::{pane_state, PaneState};
 
 #[derive(Debug, thiserror::Error)]
 pub enum TmuxError {
@@ -17,3 +24,18 @@ pub enum TmuxError {
     #[error("tmux command failed: {0}")]
     Command(String),
 }
+
+/// Runs `tmux` against `server` with `args`, recording the invocation under `function` for
+/// the metrics endpoint. A non-zero exit is returned as output, not an
+/// error, since some callers expect it (e.g. "no server running").
+fn run(server: &Server, function: &'static str, args: &[&str]) -> Result<Output, TmuxError> {
+    let start = Instant::now();
+    let output = Command::new("tmux").args(server.socket_args()).args(args).output();
+    metrics::histogram!("reattachd_tmux_command_duration_seconds", "function" => function)
+        .record(start.elapsed().as_secs_f64());
+    metrics::counter!("reattachd_tmux_commands_total", "function" => function).increment(1);
+    if !output.as_ref().is_ok_and(|o| o.status.success()) {
+        metrics::counter!("reattachd_tmux_command_failures_total", "function" => function).increment(1);
+    }
+    output.map_err(TmuxError::Io)
+}
@@ -1,22 +1,14 @@
-use std::process::Command;
+use crate::tmux::{Server, TmuxError};
 
-use crate::tmux::TmuxError;
-
-pub fn send_keys(target: &str, text: &str) -> Result<(), TmuxError> {
-    let output = Command::new("tmux")
-        .args(["send-keys", "-t", target, "-l", text])
-        .output()
-        .map_err(TmuxError::Io)?;
+pub fn send_keys(server: &Server, target: &str, text: &str) -> Result<(), TmuxError> {
+    let output = super::run(server, "send_keys", &["send-keys", "-t", target, "-l", text])?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(TmuxError::Command(stderr.to_string()));
     }
 
-    let output = Command::new("tmux")
-        .args(["send-keys", "-t", target, "Enter"])
-        .output()
-        .map_err(TmuxError::Io)?;
+    let output = super::run(server, "send_keys", &["send-keys", "-t", target, "Enter"])?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -26,11 +18,8 @@ pub fn send_keys(target: &str, text: &str) -> Result<(), TmuxError> {
     Ok(())
 }
 
-pub fn send_escape(target: &str) -> Result<(), TmuxError> {
-    let output = Command::new("tmux")
-        .args(["send-keys", "-t", target, "Escape"])
-        .output()
-        .map_err(TmuxError::Io)?;
+pub fn send_escape(server: &Server, target: &str) -> Result<(), TmuxError> {
+    let output = super::run(server, "send_escape", &["send-keys", "-t", target, "Escape"])?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -0,0 +1,207 @@
+//! The tmux servers the daemon manages. Besides the default server, every
+//! socket in `tmux.sockets` (`tmux -L NAME` or `tmux -S PATH`) is a server
+//! with an ID of its own, and targets of panes on it are qualified with that
+//! ID: `work::dev:0.1`. Unqualified targets mean the default server.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const DEFAULT_SERVER: &str = "default";
+/// Separates a server (or peer) ID from the rest of a target.
+pub const SEPARATOR: &str = "::";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Socket {
+    Default,
+    /// `tmux -L NAME`
+    Name(String),
+    /// `tmux -S PATH`
+    Path(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Server {
+    pub id: String,
+    pub socket: Socket,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            id: DEFAULT_SERVER.to_string(),
+            socket: Socket::Default,
+        }
+    }
+}
+
+impl Server {
+    /// Parses a `tmux.sockets` entry: `ID=SOCKET` or just `SOCKET`, where a
+    /// SOCKET with a '/' is a path and anything else a name. Without an ID,
+    /// the name (or the path's file name) is the ID.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (id, socket) = match spec.split_once('=') {
+            Some((id, socket)) => (Some(id), socket),
+            None => (None, spec),
+        };
+        if socket.is_empty() {
+            return Err(format!("{:?} has no socket", spec));
+        }
+        let socket = if socket.contains('/') {
+            Socket::Path(PathBuf::from(socket))
+        } else {
+            Socket::Name(socket.to_string())
+        };
+        let id = match (id, &socket) {
+            (Some(id), _) => id.to_string(),
+            (None, Socket::Path(path)) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            (None, Socket::Name(name)) => name.clone(),
+            (None, Socket::Default) => unreachable!(),
+        };
+        if !is_valid_id(&id) {
+            return Err(format!(
+                "{:?} is not a valid server ID (letters, digits, '-' and '_')",
+                id
+            ));
+        }
+        if id == DEFAULT_SERVER {
+            return Err(format!("{:?} is reserved for the default tmux server", id));
+        }
+        Ok(Self { id, socket })
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.socket == Socket::Default
+    }
+
+    /// The arguments that select this server, to go before the command.
+    pub(super) fn socket_args(&self) -> Vec<&std::ffi::OsStr> {
+        match &self.socket {
+            Socket::Default => vec![],
+            Socket::Name(name) => vec!["-L".as_ref(), name.as_ref()],
+            Socket::Path(path) => vec!["-S".as_ref(), path.as_os_str()],
+        }
+    }
+
+    /// The target clients use for `target` on this server.
+    pub fn qualify(&self, target: &str) -> String {
+        if self.is_default() {
+            target.to_string()
+        } else {
+            format!("{}{}{}", self.id, SEPARATOR, target)
+        }
+    }
+
+    /// Whether this server listens on `socket_path`, e.g. the first field of
+    /// `$TMUX` inside one of its panes.
+    fn listens_on(&self, socket_path: &Path) -> bool {
+        // tmux puts named sockets in a `tmux-UID` directory.
+        let named = |name: &str| {
+            socket_path.file_name().is_some_and(|f| f == name)
+                && socket_path
+                    .parent()
+                    .and_then(|dir| dir.file_name())
+                    .is_some_and(|dir| dir.to_string_lossy().starts_with("tmux-"))
+        };
+        match &self.socket {
+            Socket::Default => named("default"),
+            Socket::Name(name) => named(name),
+            Socket::Path(path) => {
+                path == socket_path
+                    || matches!(
+                        (path.canonicalize(), socket_path.canonicalize()),
+                        (Ok(a), Ok(b)) if a == b
+                    )
+            }
+        }
+    }
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Splits `ID::rest` into the ID and the rest. Unqualified targets have no ID.
+pub fn split_target(target: &str) -> (Option<&str>, &str) {
+    match target.split_once(SEPARATOR) {
+        Some((id, rest)) => (Some(id), rest),
+        None => (None, target),
+    }
+}
+
+/// The default server followed by the configured sockets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Servers(Arc<Vec<Server>>);
+
+impl Default for Servers {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl Servers {
+    pub fn new(sockets: Vec<Server>) -> Self {
+        let mut servers = vec![Server::default()];
+        servers.extend(sockets);
+        Self(Arc::new(servers))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Server> {
+        self.0.iter()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Server> {
+        self.0.iter().find(|server| server.id == id)
+    }
+
+    /// The server a client-facing target is on and the target within it, or
+    /// `None` if it's qualified with an ID that isn't one of ours.
+    pub fn resolve<'a>(&'a self, target: &'a str) -> Option<(&'a Server, &'a str)> {
+        match split_target(target) {
+            (None, target) => Some((&self.0[0], target)),
+            (Some(id), target) => self.get(id).map(|server| (server, target)),
+        }
+    }
+
+    pub fn find_socket(&self, socket_path: &Path) -> Option<&Server> {
+        self.0.iter().find(|server| server.listens_on(socket_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_socket_specs_and_resolves_targets() {
+        let work = Server::parse("work").unwrap();
+        assert_eq!(work.socket, Socket::Name("work".into()));
+        assert_eq!(work.id, "work");
+        let ci = Server::parse("ci=/tmp/ci.sock").unwrap();
+        assert_eq!(ci.socket, Socket::Path("/tmp/ci.sock".into()));
+        assert_eq!(ci.id, "ci");
+        assert_eq!(Server::parse("/run/agents").unwrap().id, "agents");
+        assert!(Server::parse("default").is_err());
+        assert!(Server::parse("a b=work").is_err());
+        assert!(Server::parse("x=").is_err());
+
+        let servers = Servers::new(vec![work, ci]);
+        let (server, target) = servers.resolve("dev:0.1").unwrap();
+        assert!(server.is_default());
+        assert_eq!(target, "dev:0.1");
+        let (server, target) = servers.resolve("ci::build:1.0").unwrap();
+        assert_eq!(server.id, "ci");
+        assert_eq!(target, "build:1.0");
+        assert_eq!(server.qualify(target), "ci::build:1.0");
+        assert_eq!(servers.iter().next().unwrap().qualify("dev:0.1"), "dev:0.1");
+        assert!(servers.resolve("mac::work::dev:0.1").is_none());
+        assert_eq!(split_target("mac::work::dev:0.1"), (Some("mac"), "work::dev:0.1"));
+
+        assert_eq!(servers.find_socket(Path::new("/tmp/tmux-1000/work")).unwrap().id, "work");
+        assert!(servers.find_socket(Path::new("/tmp/tmux-1000/default")).unwrap().is_default());
+        assert_eq!(servers.find_socket(Path::new("/tmp/ci.sock")).unwrap().id, "ci");
+        assert!(servers.find_socket(Path::new("/tmp/tmux-1000/other")).is_none());
+    }
+}
@@ -0,0 +1,40 @@
+use crate::tmux::{Server, TmuxError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaneState {
+    pub current_command: String,
+    pub dead: bool,
+}
+
+/// Returns the state of a single pane, or `None` if the pane no longer exists.
+pub fn pane_state(server: &Server, target: &str) -> Result<Option<PaneState>, TmuxError> {
+    let output = super::run(
+        server,
+        "pane_state",
+        &[
+            "display-message",
+            "-p",
+            "-t",
+            target,
+            "#{pane_dead}|#{pane_current_command}",
+        ],
+    )?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("can't find") || stderr.contains("no server running") {
+            return Ok(None);
+        }
+        return Err(TmuxError::Command(stderr.to_string()));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let parts: Vec<&str> = stdout.trim_end_matches('\n').splitn(2, '|').collect();
+    if parts.len() != 2 {
+        return Err(TmuxError::Command(format!("unexpected pane state: {}", stdout)));
+    }
+    Ok(Some(PaneState {
+        dead: parts[0] == "1",
+        current_command: parts[1].to_string(),
+    }))
+}
@@ -0,0 +1,305 @@
+//! Reading the end of Claude Code transcripts (JSONL, often hundreds of MB)
+//! without loading them into memory.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// How much of a transcript is scanned from the end by default.
+pub const DEFAULT_SCAN_BYTES: u64 = 4 * 1024 * 1024;
+const CHUNK_SIZE: usize = 64 * 1024;
+const MAX_SUMMARY_CHARS: usize = 80;
+
+/// Yields the lines of a file from last to first, reading it backwards in
+/// chunks and stopping once `max_bytes` have been read. A line cut off by
+/// that limit is dropped rather than returned partially.
+pub struct ReverseLines {
+    file: File,
+    /// Offset of the first byte already read.
+    pos: u64,
+    /// Bytes from `pos` that have not been returned yet.
+    buf: Vec<u8>,
+    remaining: u64,
+}
+
+impl ReverseLines {
+    pub fn open(path: &Path, max_bytes: u64) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let pos = file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            file,
+            pos,
+            buf: Vec::new(),
+            remaining: max_bytes,
+        })
+    }
+
+    /// Prepends the previous chunk to `buf`; false once nothing more may be
+    /// read.
+    fn read_chunk(&mut self) -> bool {
+        let len = (CHUNK_SIZE as u64).min(self.pos).min(self.remaining);
+        if len == 0 {
+            return false;
+        }
+        let start = self.pos - len;
+        let mut chunk = vec![0; len as usize];
+        let read = self
+            .file
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| self.file.read_exact(&mut chunk));
+        if read.is_err() {
+            return false;
+        }
+        chunk.extend_from_slice(&self.buf);
+        self.buf = chunk;
+        self.pos = start;
+        self.remaining -= len;
+        true
+    }
+}
+
+impl Iterator for ReverseLines {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if let Some(i) = self.buf.iter().rposition(|&b| b == b'\n') {
+                let line = self.buf.split_off(i + 1);
+                self.buf.truncate(i);
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            }
+            if self.pos == 0 {
+                // The file's first line has no newline before it.
+                if self.buf.is_empty() {
+                    return None;
+                }
+                let line = std::mem::take(&mut self.buf);
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            }
+            if !self.read_chunk() {
+                return None;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolUse {
+    pub name: String,
+    /// The most telling input, e.g. the command or file path.
+    pub summary: String,
+}
+
+impl std::fmt::Display for ToolUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.summary.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}: {}", self.name, self.summary)
+        }
+    }
+}
+
+/// What the end of a transcript says about the current turn.
+#[derive(Debug, Default, PartialEq)]
+pub struct TranscriptTail {
+    /// Text of the last assistant message that had any.
+    pub last_message: Option<String>,
+    /// Tools used since the user's last prompt, oldest first.
+    pub tool_uses: Vec<ToolUse>,
+    /// The latest tool call without a result yet, i.e. what a permission
+    /// prompt is asking about.
+    pub pending_tool_use: Option<ToolUse>,
+}
+
+impl TranscriptTail {
+    /// Tool uses as a one-line summary, e.g. `Bash: cargo test, Edit: src/main.rs`.
+    pub fn tool_summary(&self) -> Option<String> {
+        if self.tool_uses.is_empty() {
+            return None;
+        }
+        Some(
+            self.tool_uses
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+}
+
+/// Scans at most `max_bytes` from the end of a transcript. Lines that aren't
+/// valid JSON (e.g. a line still being written) are skipped.
+pub fn read_tail(path: &Path, max_bytes: u64) -> std::io::Result<TranscriptTail> {
+    Ok(scan(ReverseLines::open(path, max_bytes)?))
+}
+
+fn scan(lines: impl Iterator<Item = String>) -> TranscriptTail {
+    let mut tail = TranscriptTail::default();
+    let mut answered: HashSet<String> = HashSet::new();
+    let mut pending_checked = false;
+    let mut tool_uses = Vec::new();
+    let mut in_turn = true;
+
+    for line in lines {
+        if !in_turn && tail.last_message.is_some() {
+            break;
+        }
+        let Ok(entry) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        let content = entry.pointer("/message/content");
+        let items = content.and_then(|c| c.as_array()).map(Vec::as_slice).unwrap_or(&[]);
+
+        match entry.get("type").and_then(|v| v.as_str()) {
+            Some("user") => {
+                // Meta entries (e.g. command output Claude Code injects) are
+                // not prompts the user typed.
+                let is_meta = entry.get("isMeta").and_then(|v| v.as_bool()).unwrap_or(false);
+                let mut is_prompt = !is_meta && content.is_some_and(|c| c.is_string());
+                for item in items {
+                    match item.get("type").and_then(|v| v.as_str()) {
+                        Some("tool_result") => {
+                            if let Some(id) = item.get("tool_use_id").and_then(|v| v.as_str()) {
+                                answered.insert(id.to_string());
+                            }
+                        }
+                        Some("text") => is_prompt = !is_meta,
+                        _ => {}
+                    }
+                }
+                // Everything before the user's prompt belongs to an earlier
+                // turn, which only matters if this one had no text yet.
+                if is_prompt {
+                    in_turn = false;
+                }
+            }
+            Some("assistant") => {
+                let mut texts = Vec::new();
+                for item in items.iter().rev() {
+                    match item.get("type").and_then(|v| v.as_str()) {
+                        Some("text") => {
+                            if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
+                                texts.push(text.to_string());
+                            }
+                        }
+                        Some("tool_use") if in_turn => {
+                            let tool_use = summarize_tool_use(item);
+                            if !pending_checked {
+                                pending_checked = true;
+                                let id = item.get("id").and_then(|v| v.as_str()).unwrap_or("");
+                                if !answered.contains(id) {
+                                    tail.pending_tool_use = Some(tool_use.clone());
+                                }
+                            }
+                            tool_uses.push(tool_use);
+                        }
+                        _ => {}
+                    }
+                }
+                if tail.last_message.is_none() && !texts.is_empty() {
+                    texts.reverse();
+                    tail.last_message = Some(texts.join("\n"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    tool_uses.reverse();
+    tail.tool_uses = tool_uses;
+    tail
+}
+
+fn summarize_tool_use(item: &Value) -> ToolUse {
+    let name = item
+        .get("name")
+        .and_then(|v| v.as_str())
+        .unwrap_or("tool")
+        .to_string();
+    let input = item.get("input");
+    let summary = ["command", "file_path", "notebook_path", "path", "pattern", "url", "query", "description"]
+        .iter()
+        .find_map(|key| input.and_then(|i| i.get(*key)).and_then(|v| v.as_str()))
+        .map(|s| {
+            let line = s.lines().next().unwrap_or("").trim();
+            if line.chars().count() > MAX_SUMMARY_CHARS || s.lines().nth(1).is_some() {
+                let cut: String = line.chars().take(MAX_SUMMARY_CHARS).collect();
+                format!("{}…", cut)
+            } else {
+                line.to_string()
+            }
+        })
+        .unwrap_or_default();
+    ToolUse { name, summary }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/transcripts")
+            .join(name)
+    }
+
+    fn temp_file(content: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, content).unwrap();
+        file
+    }
+
+    #[test]
+    fn reverse_lines_across_chunks() {
+        let lines: Vec<String> = (0..20_000).map(|i| format!("line {}", i)).collect();
+        let file = temp_file(format!("{}\n", lines.join("\n")).as_bytes());
+        let read: Vec<String> = ReverseLines::open(file.path(), u64::MAX).unwrap().collect();
+        let expected: Vec<String> = lines.into_iter().rev().collect();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn reverse_lines_stops_at_byte_budget() {
+        let file = temp_file(b"first\nsecond\nthird\n");
+        let read: Vec<String> = ReverseLines::open(file.path(), 8).unwrap().collect();
+        // "third\n" fits; "second" is cut off and dropped.
+        assert_eq!(read, ["third"]);
+    }
+
+    #[test]
+    fn finished_turn() {
+        let tail = read_tail(&fixture("finished_turn.jsonl"), DEFAULT_SCAN_BYTES).unwrap();
+        assert_eq!(tail.last_message.as_deref(), Some("All 42 tests pass. Want me to open a PR?"));
+        assert_eq!(
+            tail.tool_summary().as_deref(),
+            Some("Read: src/lib.rs, Edit: src/lib.rs, Bash: cargo test")
+        );
+        assert_eq!(tail.pending_tool_use, None);
+    }
+
+    #[test]
+    fn permission_request() {
+        let tail = read_tail(&fixture("permission_request.jsonl"), DEFAULT_SCAN_BYTES).unwrap();
+        assert_eq!(
+            tail.pending_tool_use,
+            Some(ToolUse {
+                name: "Bash".into(),
+                summary: "rm -rf target/debug".into()
+            })
+        );
+        assert_eq!(tail.last_message.as_deref(), Some("I'll clean the build directory first."));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let tail = read_tail(&fixture("malformed.jsonl"), DEFAULT_SCAN_BYTES).unwrap();
+        assert_eq!(tail.last_message.as_deref(), Some("Done."));
+    }
+}
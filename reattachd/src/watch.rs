@@ -0,0 +1,544 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::RwLock;
+
+use crate::apns::{ApnsService, Notification};
+use crate::detect::{self, is_shell, PaneSnapshot, PaneStatus};
+use crate::tmux;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const CAPTURE_LINES: u32 = 200;
+/// APNs category of watcher notifications.
+const WATCH_CATEGORY: &str = "WATCH_TRIGGERED";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WatchTrigger {
+    /// Fires when a new line of output matches `pattern`.
+    Match { pattern: String },
+    /// Fires when the foreground program returns to the shell, or the pane
+    /// dies or disappears.
+    Exited,
+    /// Fires once the pane has produced no output for `minutes`.
+    Idle { minutes: u64 },
+    /// Fires when the pane starts waiting for input, as judged by
+    /// [`detect::classify`].
+    WaitingForInput,
+}
+
+impl WatchTrigger {
+    pub fn describe(&self) -> String {
+        match self {
+            WatchTrigger::Match { pattern } => format!("output matches /{}/", pattern),
+            WatchTrigger::Exited => "process exits".to_string(),
+            WatchTrigger::Idle { minutes } => format!("no output for {}m", minutes),
+            WatchTrigger::WaitingForInput => "waiting for input".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Watcher {
+    pub id: String,
+    pub target: String,
+    pub trigger: WatchTrigger,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Remove the watcher after it has fired once.
+    #[serde(default)]
+    pub once: bool,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_triggered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewWatcher {
+    pub target: String,
+    pub trigger: WatchTrigger,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub once: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WatchError {
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("Idle time must be at least one minute")]
+    InvalidIdle,
+    #[error("Pane target must not be empty")]
+    EmptyTarget,
+    #[error("Unknown tmux server '{0}'; watchers only watch this host's panes")]
+    UnknownServer(String),
+}
+
+pub struct WatchService {
+    watchers: RwLock<Vec<Watcher>>,
+    servers: tmux::Servers,
+    data_path: PathBuf,
+    loaded_mtime: std::sync::Mutex<Option<SystemTime>>,
+}
+
+impl WatchService {
+    pub async fn new(data_dir: PathBuf, servers: tmux::Servers) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&data_dir)?;
+        let data_path = data_dir.join("watchers.json");
+
+        let service = Self {
+            watchers: RwLock::new(Vec::new()),
+            servers,
+            data_path,
+            loaded_mtime: std::sync::Mutex::new(None),
+        };
+        service.reload().await;
+        Ok(service)
+    }
+
+    fn file_mtime(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.data_path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Re-reads the store if it changed on disk, e.g. after `reattachd watch add`.
+    async fn reload(&self) {
+        let mtime = self.file_mtime();
+        {
+            let mut loaded = self.loaded_mtime.lock().unwrap_or_else(|e| e.into_inner());
+            if mtime.is_none() || *loaded == mtime {
+                return;
+            }
+            *loaded = mtime;
+        }
+        if let Ok(content) = std::fs::read_to_string(&self.data_path) {
+            match serde_json::from_str::<Vec<Watcher>>(&content) {
+                Ok(watchers) => *self.watchers.write().await = watchers,
+                Err(e) => tracing::warn!("Failed to parse {}: {}", self.data_path.display(), e),
+            }
+        }
+    }
+
+    async fn save(&self) -> Result<(), std::io::Error> {
+        let watchers = self.watchers.read().await;
+        let content = serde_json::to_string_pretty(&*watchers)?;
+        std::fs::write(&self.data_path, content)?;
+        *self.loaded_mtime.lock().unwrap_or_else(|e| e.into_inner()) = self.file_mtime();
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Vec<Watcher> {
+        self.reload().await;
+        self.watchers.read().await.clone()
+    }
+
+    pub async fn add(&self, new: NewWatcher) -> Result<Watcher, WatchError> {
+        if new.target.trim().is_empty() {
+            return Err(WatchError::EmptyTarget);
+        }
+        if self.servers.resolve(&new.target).is_none() {
+            let id = tmux::split_target(&new.target).0.unwrap_or_default();
+            return Err(WatchError::UnknownServer(id.to_string()));
+        }
+        match new.trigger {
+            WatchTrigger::Match { ref pattern } => {
+                Regex::new(pattern)?;
+            }
+            WatchTrigger::Idle { minutes: 0 } => return Err(WatchError::InvalidIdle),
+            _ => {}
+        }
+
+        self.reload().await;
+        let watcher = Watcher {
+            id: uuid::Uuid::new_v4().to_string(),
+            target: new.target,
+            trigger: new.trigger,
+            title: new.title.filter(|t| !t.is_empty()),
+            once: new.once,
+            created_at: Utc::now(),
+            last_triggered_at: None,
+        };
+        self.watchers.write().await.push(watcher.clone());
+        if let Err(e) = self.save().await {
+            tracing::error!("Failed to save watchers: {}", e);
+        }
+        Ok(watcher)
+    }
+
+    pub async fn remove(&self, id: &str) -> bool {
+        self.reload().await;
+        let removed = {
+            let mut watchers = self.watchers.write().await;
+            let len_before = watchers.len();
+            watchers.retain(|w| w.id != id);
+            watchers.len() < len_before
+        };
+
+        if removed {
+            if let Err(e) = self.save().await {
+                tracing::error!("Failed to save watchers: {}", e);
+            }
+        }
+        removed
+    }
+
+    async fn mark_triggered(&self, id: &str) {
+        {
+            let mut watchers = self.watchers.write().await;
+            if let Some(pos) = watchers.iter().position(|w| w.id == id) {
+                if watchers[pos].once {
+                    watchers.remove(pos);
+                } else {
+                    watchers[pos].last_triggered_at = Some(Utc::now());
+                }
+            }
+        }
+        if let Err(e) = self.save().await {
+            tracing::error!("Failed to save watchers: {}", e);
+        }
+    }
+
+    /// Polls the watched panes forever, sending a notification whenever a
+    /// watcher fires.
+    pub async fn run(self: Arc<Self>, apns: Option<Arc<ApnsService>>) {
+        let mut states: HashMap<String, WatchState> = HashMap::new();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let watchers = self.list().await;
+            states.retain(|id, _| watchers.iter().any(|w| &w.id == id));
+            if watchers.is_empty() {
+                continue;
+            }
+
+            let mut targets: Vec<String> = watchers.iter().map(|w| w.target.clone()).collect();
+            targets.sort();
+            targets.dedup();
+            let servers = self.servers.clone();
+            let observations = match tokio::task::spawn_blocking(move || observe(&servers, &targets)).await {
+                Ok(observations) => observations,
+                Err(e) => {
+                    tracing::error!("Watcher poll failed: {}", e);
+                    continue;
+                }
+            };
+
+            for watcher in &watchers {
+                let Some(observation) = observations.get(&watcher.target) else {
+                    continue;
+                };
+                let state = states
+                    .entry(watcher.id.clone())
+                    .or_insert_with(|| WatchState::new(&watcher.trigger));
+                let Some(body) = state.evaluate(&watcher.trigger, observation, Instant::now()) else {
+                    continue;
+                };
+
+                let title = watcher.title.clone().unwrap_or_else(|| watcher.target.clone());
+                tracing::info!("Watcher {} fired on {}: {}", watcher.id, watcher.target, body);
+                if let Some(ref apns) = apns {
+                    let notification = Notification {
+                        category: Some(WATCH_CATEGORY.to_string()),
+                        pane_target: Some(watcher.target.clone()),
+                        ..Notification::new(title, body)
+                    };
+                    if let Err(e) = apns.send_notification(&notification).await {
+                        tracing::warn!("Failed to send watcher notification: {}", e);
+                    }
+                }
+                self.mark_triggered(&watcher.id).await;
+            }
+        }
+    }
+}
+
+struct Observation {
+    /// `None` when the pane no longer exists.
+    pane: Option<tmux::PaneState>,
+    lines: Vec<String>,
+}
+
+fn observe(servers: &tmux::Servers, targets: &[String]) -> HashMap<String, Observation> {
+    let mut observations = HashMap::new();
+    for qualified in targets {
+        // Watchers made before a socket was removed from the config.
+        let Some((server, target)) = servers.resolve(qualified) else {
+            continue;
+        };
+        let pane = match tmux::pane_state(server, target) {
+            Ok(pane) => pane,
+            Err(e) => {
+                tracing::debug!("Failed to inspect pane {}: {}", target, e);
+                continue;
+            }
+        };
+        let lines = if pane.is_some() {
+            tmux::capture_pane_text(server, target, CAPTURE_LINES)
+                .map(|output| trimmed_lines(&output))
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        observations.insert(qualified.clone(), Observation { pane, lines });
+    }
+    observations
+}
+
+fn trimmed_lines(output: &str) -> Vec<String> {
+    let mut lines: Vec<String> = output.lines().map(|l| l.trim_end().to_string()).collect();
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Returns the lines of `current` that were not already in `previous`,
+/// assuming the pane has scrolled by some number of lines in between.
+///
+/// If no alignment is found, the last line of `previous` is assumed to have
+/// still been in the middle of being written (e.g. a progress bar) and the
+/// alignment is retried without it.
+fn new_lines<'a>(previous: &[String], current: &'a [String]) -> &'a [String] {
+    let settled = &previous[..previous.len().saturating_sub(1)];
+    for candidate in [previous, settled] {
+        for shift in 0..candidate.len() {
+            let overlap = &candidate[shift..];
+            if current.len() >= overlap.len() && current[..overlap.len()] == *overlap {
+                return &current[overlap.len()..];
+            }
+        }
+    }
+    current
+}
+
+struct WatchState {
+    pattern: Option<Regex>,
+    previous: Option<Vec<String>>,
+    last_change: Instant,
+    idle_fired: bool,
+    was_running: bool,
+    exit_fired: bool,
+    last_status: Option<PaneStatus>,
+}
+
+impl WatchState {
+    fn new(trigger: &WatchTrigger) -> Self {
+        let pattern = match trigger {
+            WatchTrigger::Match { pattern } => Regex::new(pattern).ok(),
+            _ => None,
+        };
+        Self {
+            pattern,
+            previous: None,
+            last_change: Instant::now(),
+            idle_fired: false,
+            was_running: false,
+            exit_fired: false,
+            last_status: None,
+        }
+    }
+
+    /// Feeds one observation of the pane and returns the notification body if
+    /// the watcher fires.
+    fn evaluate(&mut self, trigger: &WatchTrigger, observation: &Observation, now: Instant) -> Option<String> {
+        let changed = self.previous.as_deref() != Some(observation.lines.as_slice());
+        let previous = if changed {
+            self.last_change = now;
+            self.idle_fired = false;
+            self.previous.replace(observation.lines.clone())
+        } else {
+            None
+        };
+
+        match trigger {
+            // Only fresh output counts, so the same line printed again, e.g.
+            // by the next build, fires again.
+            WatchTrigger::Match { .. } => {
+                let previous = previous?;
+                let pattern = self.pattern.as_ref()?;
+                new_lines(&previous, &observation.lines)
+                    .iter()
+                    .find(|line| pattern.is_match(line))
+                    .map(|matched| matched.trim().to_string())
+            }
+            WatchTrigger::Exited => {
+                let body = match &observation.pane {
+                    None => Some("Pane closed".to_string()),
+                    Some(pane) if pane.dead => Some("Process exited".to_string()),
+                    Some(pane) if is_shell(&pane.current_command) && self.was_running => {
+                        Some("Process exited".to_string())
+                    }
+                    Some(_) => None,
+                };
+                self.was_running = observation
+                    .pane
+                    .as_ref()
+                    .is_some_and(|p| !p.dead && !is_shell(&p.current_command));
+                if body.is_some() && !self.exit_fired {
+                    self.exit_fired = true;
+                    return body;
+                }
+                if self.was_running {
+                    self.exit_fired = false;
+                }
+                None
+            }
+            WatchTrigger::WaitingForInput => {
+                let pane = observation.pane.as_ref()?;
+                let status = detect::classify(&PaneSnapshot {
+                    current_command: &pane.current_command,
+                    dead: pane.dead,
+                    screen: &observation.lines,
+                    cursor_y: None,
+                    quiet_for: now.duration_since(self.last_change),
+                });
+                let previous = self.last_status.replace(status);
+                let entered = status == PaneStatus::WaitingForInput
+                    && previous.is_some_and(|p| p != PaneStatus::WaitingForInput);
+                entered.then(|| {
+                    observation
+                        .lines
+                        .iter()
+                        .rev()
+                        .find(|l| !l.trim().is_empty())
+                        .map(|l| l.trim().to_string())
+                        .unwrap_or_else(|| "Waiting for input".to_string())
+                })
+            }
+            WatchTrigger::Idle { minutes } => {
+                let limit = Duration::from_secs(minutes.saturating_mul(60));
+                if !self.idle_fired && now.duration_since(self.last_change) >= limit {
+                    self.idle_fired = true;
+                    return Some(format!("No output for {} minutes", minutes));
+                }
+                None
+            }
+        }
+    }
+}
+
+pub type SharedWatchService = Arc<WatchService>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn observation(items: &[&str], command: &str) -> Observation {
+        Observation {
+            pane: Some(tmux::PaneState {
+                current_command: command.to_string(),
+                dead: false,
+            }),
+            lines: lines(items),
+        }
+    }
+
+    #[test]
+    fn new_lines_follows_scrolling() {
+        let previous = lines(&["a", "b", "c"]);
+        assert_eq!(new_lines(&previous, &lines(&["a", "b", "c", "d"])), lines(&["d"]).as_slice());
+        assert_eq!(new_lines(&previous, &lines(&["b", "c", "d", "e"])), lines(&["d", "e"]).as_slice());
+        assert_eq!(new_lines(&previous, &lines(&["x", "y"])), lines(&["x", "y"]).as_slice());
+    }
+
+    #[test]
+    fn new_lines_reports_rewritten_last_line() {
+        let previous = lines(&["a", "b", "50%"]);
+        assert_eq!(new_lines(&previous, &lines(&["a", "b", "60%"])), lines(&["60%"]).as_slice());
+    }
+
+    #[test]
+    fn match_fires_only_on_new_output() {
+        let trigger = WatchTrigger::Match {
+            pattern: "error".to_string(),
+        };
+        let mut state = WatchState::new(&trigger);
+        let now = Instant::now();
+
+        // Existing output is never reported.
+        assert_eq!(state.evaluate(&trigger, &observation(&["old error"], "cargo"), now), None);
+        assert_eq!(
+            state.evaluate(&trigger, &observation(&["old error", "compiling", "error: oops"], "cargo"), now),
+            Some("error: oops".to_string())
+        );
+        // Unchanged output does not fire again.
+        assert_eq!(
+            state.evaluate(&trigger, &observation(&["old error", "compiling", "error: oops"], "cargo"), now),
+            None
+        );
+        assert_eq!(
+            state.evaluate(&trigger, &observation(&["old error", "compiling", "error: oops", "$"], "zsh"), now),
+            None
+        );
+        // The same error from the next run does.
+        let rerun = ["compiling", "error: oops", "$", "compiling", "error: oops"];
+        assert_eq!(
+            state.evaluate(&trigger, &observation(&rerun, "cargo"), now),
+            Some("error: oops".to_string())
+        );
+    }
+
+    #[test]
+    fn exited_fires_when_command_returns_to_shell() {
+        let trigger = WatchTrigger::Exited;
+        let mut state = WatchState::new(&trigger);
+        let now = Instant::now();
+
+        assert_eq!(state.evaluate(&trigger, &observation(&["$ make"], "make"), now), None);
+        assert_eq!(
+            state.evaluate(&trigger, &observation(&["$ make", "$"], "zsh"), now),
+            Some("Process exited".to_string())
+        );
+        assert_eq!(state.evaluate(&trigger, &observation(&["$ make", "$"], "zsh"), now), None);
+
+        let gone = Observation {
+            pane: None,
+            lines: Vec::new(),
+        };
+        let mut state = WatchState::new(&trigger);
+        assert_eq!(state.evaluate(&trigger, &gone, now), Some("Pane closed".to_string()));
+    }
+
+    #[test]
+    fn waiting_for_input_fires_on_transition() {
+        let trigger = WatchTrigger::WaitingForInput;
+        let mut state = WatchState::new(&trigger);
+        let now = Instant::now();
+
+        let working = ["✻ Thinking… (esc to interrupt)"];
+        let asking = ["Do you want to proceed?", "❯ 1. Yes"];
+        assert_eq!(state.evaluate(&trigger, &observation(&asking, "node"), now), None);
+        assert_eq!(state.evaluate(&trigger, &observation(&working, "node"), now), None);
+        assert_eq!(
+            state.evaluate(&trigger, &observation(&asking, "node"), now),
+            Some("❯ 1. Yes".to_string())
+        );
+        assert_eq!(state.evaluate(&trigger, &observation(&asking, "node"), now), None);
+    }
+
+    #[test]
+    fn idle_fires_once_per_quiet_period() {
+        let trigger = WatchTrigger::Idle { minutes: 5 };
+        let mut state = WatchState::new(&trigger);
+        let start = Instant::now();
+
+        assert_eq!(state.evaluate(&trigger, &observation(&["working"], "cargo"), start), None);
+        let later = start + Duration::from_secs(5 * 60);
+        assert_eq!(
+            state.evaluate(&trigger, &observation(&["working"], "cargo"), later),
+            Some("No output for 5 minutes".to_string())
+        );
+        assert_eq!(state.evaluate(&trigger, &observation(&["working"], "cargo"), later), None);
+        assert_eq!(state.evaluate(&trigger, &observation(&["working", "more"], "cargo"), later), None);
+    }
+}